@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use candid::{CandidType, encode_one, decode_one};
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _, Storable};
+use ic_stable_structures::memory_manager::{MemoryManager, VirtualMemory, MemoryId};
+use ic_stable_structures::storable::Bound;
+use serde::Deserialize;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// magic written by the memory manager at the start of the stable memory
+const MEMORY_MANAGER_MAGIC: &[u8; 3] = b"MGR";
+
+// the canister's state (owner, vetkd) lives in its own memory, after the tables'
+pub const STATE_MEMORY_ID: MemoryId = MemoryId::new(254);
+// schema versions of the data stored in the tables' memories
+pub const VERSIONS_MEMORY_ID: MemoryId = MemoryId::new(253);
+// number of rows written to the tables, so readers spanning many messages can detect changes
+pub const WRITES_MEMORY_ID: MemoryId = MemoryId::new(252);
+// the snapshot import in progress, if any
+pub const SNAPSHOT_IMPORT_MEMORY_ID: MemoryId = MemoryId::new(251);
+// the maintenance in progress, if any, during which writes are rejected
+pub const MAINTENANCE_MEMORY_ID: MemoryId = MemoryId::new(250);
+// the expires_at index of the prescription auths. The table's index memories are all taken, and the one
+// of the retired prescription_to index may still hold its entries, so it's never reused
+pub const PRESCRIPTION_AUTHS_EXPIRES_AT_MEMORY_ID: MemoryId = MemoryId::new(249);
+// the rebuild of the relation tables and indexes in progress, if any
+pub const REBUILD_MEMORY_ID: MemoryId = MemoryId::new(248);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get_memory(
+    id: MemoryId
+) -> Memory {
+    MEMORY_MANAGER.with(|mm| mm.borrow().get(id))
+}
+
+pub fn count_write(
+) {
+    let memory = get_memory(WRITES_MEMORY_ID);
+    if memory.size() == 0 {
+        memory.grow(1);
+    }
+    let writes = get_writes() + 1;
+    memory.write(0, &writes.to_le_bytes());
+}
+
+pub fn get_writes(
+) -> u64 {
+    let memory = get_memory(WRITES_MEMORY_ID);
+    if memory.size() == 0 {
+        return 0;
+    }
+    let mut buf = [0u8; 8];
+    memory.read(0, &mut buf);
+    u64::from_le_bytes(buf)
+}
+
+/*
+ * stable memory written by versions that dumped the whole DB on pre_upgrade doesn't start
+ * with the memory manager header. It must be loaded before the memory manager is initialized,
+ * as that would overwrite it
+ */
+pub fn is_legacy_layout(
+) -> bool {
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return false;
+    }
+
+    let mut magic = [0u8; 3];
+    ic_cdk::api::stable::stable64_read(0, &mut magic);
+    &magic != MEMORY_MANAGER_MAGIC
+}
+
+/*
+ * keys and values are stored Candid-encoded, so records can keep evolving by adding opt fields
+ */
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stored<T> (pub T);
+
+impl<T> Storable for Stored<T>
+    where
+        T: CandidType + for<'a> Deserialize<'a> {
+    fn to_bytes(
+        &self
+    ) -> Cow<[u8]> {
+        Cow::Owned(encode_one(&self.0).unwrap())
+    }
+
+    fn from_bytes(
+        bytes: Cow<[u8]>
+    ) -> Self {
+        Self(decode_one(&bytes).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// a value as stored, so it can be migrated without being decoded
+pub struct Raw (pub Vec<u8>);
+
+impl Storable for Raw {
+    fn to_bytes(
+        &self
+    ) -> Cow<[u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(
+        bytes: Cow<[u8]>
+    ) -> Self {
+        Self(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
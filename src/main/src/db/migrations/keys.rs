@@ -1,13 +1,21 @@
-use std::collections::BTreeMap;
-
-use candid::{CandidType, Principal, decode_args};
+use candid::{CandidType, Principal};
 use serde::Deserialize;
-use crate::{models::key::{KeyId, Key, KeyKind}, db::traits::table::TableData};
+use crate::models::key::{KeyId, Key, KeyKind};
+use super::{TableMigration, convert_row, convert_table};
+
+pub const MIGRATIONS: [TableMigration; 1] = [
+    TableMigration {
+        from: 1,
+        row: v1_to_v2_row,
+        table: v1_to_v2_table,
+    },
+];
 
 #[derive(CandidType, Clone, Deserialize)]
-struct KeyV0_1 {
+struct KeyV1 {
     pub id: KeyId,
-    pub country: String,
+    // text in the first rows written with this version, so it's decoded as opt to accept both
+    pub country: Option<String>,
     pub kind: KeyKind,
     pub value: String,
     pub created_at: u64,
@@ -18,33 +26,125 @@ struct KeyV0_1 {
     pub deleted_by: Option<Principal>,
 }
 
-pub fn migrate(
-    from_version: f32,
-    buf: &[u8] 
-) -> Result<TableData<KeyId, Key>, String> {
-    if from_version != 0.1 {
-        return Err("Unsupported version".to_string());
-    }
-
-    let table = decode_args::<'_, (TableData<KeyId, KeyV0_1>, )>(buf)
-        .map_err(|e| format!("{:?}", e))?;
-
-    Ok(
-        TableData(
-            BTreeMap::from_iter(table.0.0.iter().map(|e| 
-                (e.0.clone(), Key {
-                    id: e.1.id.clone(),
-                    kind: e.1.kind.clone(),
-                    country: Some(e.1.country.clone()),
-                    value: e.1.value.clone(),
-                    created_at: e.1.created_at,
-                    created_by: e.1.created_by,
-                    updated_at: e.1.updated_at,
-                    updated_by: e.1.updated_by,
-                    deleted_at: e.1.deleted_at,
-                    deleted_by: e.1.deleted_by,
-                })
-            ))
-        )
-    )
+// country became optional
+fn v1_to_v2(
+    e: KeyV1
+) -> Key {
+    Key {
+        id: e.id,
+        kind: e.kind,
+        country: e.country.filter(|country| !country.is_empty()),
+        value: e.value,
+        created_at: e.created_at,
+        created_by: e.created_by,
+        updated_at: e.updated_at,
+        updated_by: e.updated_by,
+        deleted_at: e.deleted_at,
+        deleted_by: e.deleted_by,
+    }
+}
+
+fn v1_to_v2_row(
+    buf: &[u8]
+) -> Result<Vec<u8>, String> {
+    convert_row(buf, v1_to_v2)
+}
+
+fn v1_to_v2_table(
+    buf: &[u8]
+) -> Result<Vec<u8>, String> {
+    convert_table::<KeyId, _, _>(buf, v1_to_v2)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use candid::{decode_one, encode_one};
+    use crate::db::TableName;
+    use crate::db::migrations::Migratable;
+    use crate::db::traits::table::TableData;
+    use super::*;
+
+    #[derive(CandidType)]
+    struct KeyV1Text {
+        pub id: KeyId,
+        pub country: String,
+        pub kind: KeyKind,
+        pub value: String,
+        pub created_at: u64,
+        pub created_by: Principal,
+        pub updated_at: Option<u64>,
+        pub updated_by: Option<Principal>,
+        pub deleted_at: Option<u64>,
+        pub deleted_by: Option<Principal>,
+    }
+
+    fn key_v1(
+        country: Option<&str>
+    ) -> KeyV1 {
+        KeyV1 {
+            id: "k1".to_string(),
+            country: country.map(|c| c.to_string()),
+            kind: KeyKind::EmailAddress,
+            value: "ana@example.com".to_string(),
+            created_at: 10,
+            created_by: Principal::anonymous(),
+            updated_at: None,
+            updated_by: None,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    fn migrate_row(
+        buf: Vec<u8>
+    ) -> Key {
+        decode_one::<Key>(&v1_to_v2_row(&buf).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn v1_to_v2_keeps_the_country() {
+        let key = migrate_row(encode_one(key_v1(Some("BR"))).unwrap());
+        assert_eq!(key.id, "k1");
+        assert_eq!(key.country, Some("BR".to_string()));
+        assert!(key.kind == KeyKind::EmailAddress);
+        assert_eq!(key.value, "ana@example.com");
+        assert_eq!(key.created_at, 10);
+    }
+
+    #[test]
+    fn v1_to_v2_drops_empty_countries() {
+        assert_eq!(migrate_row(encode_one(key_v1(Some(""))).unwrap()).country, None);
+        assert_eq!(migrate_row(encode_one(key_v1(None)).unwrap()).country, None);
+    }
+
+    #[test]
+    fn v1_to_v2_accepts_countries_stored_as_text() {
+        let v1 = key_v1(Some("BR"));
+        let buf = encode_one(KeyV1Text {
+            id: v1.id,
+            country: "BR".to_string(),
+            kind: v1.kind,
+            value: v1.value,
+            created_at: v1.created_at,
+            created_by: v1.created_by,
+            updated_at: None,
+            updated_by: None,
+            deleted_at: None,
+            deleted_by: None,
+        }).unwrap();
+        assert_eq!(migrate_row(buf).country, Some("BR".to_string()));
+    }
+
+    #[test]
+    fn v1_tables_are_migrated_through_the_registry() {
+        let mut rows = BTreeMap::new();
+        rows.insert("k1".to_string(), key_v1(Some("")));
+        let buf = candid::encode_args((TableData(rows), )).unwrap();
+
+        let buf = TableName::Keys.migrate_table(1, buf).unwrap();
+        let table = candid::decode_args::<'_, (TableData<KeyId, Key>, )>(&buf).unwrap().0;
+        assert_eq!(table.0.len(), 1);
+        assert_eq!(table.0["k1"].country, None);
+    }
 }
@@ -1 +1,298 @@
 pub mod keys;
+pub mod prescription_auths;
+pub mod state;
+
+use std::collections::BTreeMap;
+use candid::{CandidType, decode_one, encode_one, decode_args, ser::IDLBuilder, utils::ArgumentEncoder};
+use ic_stable_structures::StableBTreeMap;
+use serde::Deserialize;
+use crate::db::{TableName, TABLES};
+use crate::db::memory::{get_memory, Memory, Raw, Stored, VERSIONS_MEMORY_ID};
+use crate::db::traits::table::TableData;
+use crate::models::access_log::AccessLogId;
+use crate::models::group::GroupId;
+use crate::models::key::KeyId;
+use crate::models::prescription::PrescriptionId;
+use crate::models::prescription_auth::PrescriptionAuthId;
+use crate::models::prescription_template::PrescriptionTemplateId;
+use crate::models::trusted_cert::TrustedCertId;
+use crate::models::crl::CrlId;
+use crate::models::user::UserId;
+
+// version of the tables and of the STATE when they were versioned as f32 (0.1)
+pub const BASE_VERSION: u32 = 1;
+
+pub type Convert = fn(&[u8]) -> Result<Vec<u8>, String>;
+
+// converts the STATE stored with version `from` to version `from + 1`
+pub struct Migration {
+    pub from: u32,
+    pub convert: Convert,
+}
+
+// converts the rows stored with version `from` to version `from + 1`
+pub struct TableMigration {
+    pub from: u32,
+    // a single row, as stored in the table's memory
+    pub row: Convert,
+    // a whole table, as dumped by the legacy versions or exported to a snapshot
+    pub table: Convert,
+}
+
+impl TableName {
+    // must be listed in version order
+    pub fn migrations(
+        &self
+    ) -> &'static [TableMigration] {
+        match self {
+            TableName::Keys => &keys::MIGRATIONS,
+            TableName::PrescriptionAuths => &prescription_auths::MIGRATIONS,
+            _ => &[],
+        }
+    }
+
+    // each migration registered bumps the table's version
+    pub fn version(
+        &self
+    ) -> u32 {
+        BASE_VERSION + self.migrations().len() as u32
+    }
+}
+
+// chains the conversions needed to bring data stored with version from to version to
+pub fn migrate(
+    from: u32,
+    to: u32,
+    buf: Vec<u8>,
+    find: impl Fn(u32) -> Option<Convert>
+) -> Result<Vec<u8>, String> {
+    if from > to {
+        return Err(format!("Version {} is newer than the supported one ({})", from, to));
+    }
+
+    let mut buf = buf;
+    for version in from..to {
+        let convert = match find(version) {
+            None => return Err(format!("No migration from version {}", version)),
+            Some(convert) => convert
+        };
+        buf = convert(&buf)?;
+    }
+
+    Ok(buf)
+}
+
+// implemented by the table names, so the tables can find their migrations
+pub trait Migratable {
+    fn migrate_table(
+        &self,
+        from: u32,
+        buf: Vec<u8>
+    ) -> Result<Vec<u8>, String>;
+}
+
+impl Migratable for TableName {
+    fn migrate_table(
+        &self,
+        from: u32,
+        buf: Vec<u8>
+    ) -> Result<Vec<u8>, String> {
+        migrate(from, self.version(), buf, |version|
+            self.migrations().iter().find(|m| m.from == version).map(|m| m.table)
+        )
+    }
+}
+
+pub fn migrate_state(
+    from: u32,
+    buf: Vec<u8>
+) -> Result<Vec<u8>, String> {
+    migrate(from, state::VERSION, buf, |version|
+        state::MIGRATIONS.iter().find(|m| m.from == version).map(|m| m.convert)
+    )
+}
+
+// checks that the migrations of every table, and of the STATE, form a chain starting at BASE_VERSION
+pub fn validate_registry(
+) -> Result<(), String> {
+    for table in TABLES.iter() {
+        for (i, migration) in table.migrations().iter().enumerate() {
+            if migration.from != BASE_VERSION + i as u32 {
+                return Err(format!("Migration {} of table {} should be from version {}", i, table, BASE_VERSION + i as u32));
+            }
+        }
+    }
+
+    for (i, migration) in state::MIGRATIONS.iter().enumerate() {
+        if migration.from != BASE_VERSION + i as u32 {
+            return Err(format!("Migration {} of STATE should be from version {}", i, BASE_VERSION + i as u32));
+        }
+    }
+
+    Ok(())
+}
+
+/*
+ * migrates, in place, the rows stored with older versions. Must be called before the tables are
+ * loaded, as their rows can't be decoded until migrated. Returns the tables migrated
+ */
+pub fn migrate_stored(
+) -> Result<Vec<TableName>, String> {
+    let mut versions: StableBTreeMap<Stored<TableName>, u32, Memory> =
+        StableBTreeMap::init(get_memory(VERSIONS_MEMORY_ID));
+
+    // nothing recorded yet: the rows were written with the current versions
+    if versions.is_empty() {
+        record_versions();
+        return Ok(vec![]);
+    }
+
+    let mut migrated = vec![];
+    for table in TABLES.iter() {
+        let from = versions.get(&Stored(table.clone())).unwrap_or(BASE_VERSION);
+        if from == table.version() {
+            continue;
+        }
+
+        match table {
+            TableName::Users => migrate_rows::<UserId>(table, from)?,
+            TableName::Keys => migrate_rows::<KeyId>(table, from)?,
+            TableName::Prescriptions => migrate_rows::<PrescriptionId>(table, from)?,
+            TableName::PrescriptionAuths => migrate_rows::<PrescriptionAuthId>(table, from)?,
+            TableName::PrescriptionTemplates => migrate_rows::<PrescriptionTemplateId>(table, from)?,
+            TableName::UserPrescriptionsRel => migrate_rows::<UserId>(table, from)?,
+            TableName::PrescriptionAuthsRel => migrate_rows::<PrescriptionId>(table, from)?,
+            TableName::PrincipalKeysRel => migrate_rows::<UserId>(table, from)?,
+            TableName::KeyPrincipalRel => migrate_rows::<KeyId>(table, from)?,
+            TableName::Groups => migrate_rows::<GroupId>(table, from)?,
+            TableName::PrincipalGroupsRel => migrate_rows::<UserId>(table, from)?,
+            TableName::DoctorTemplatesRel => migrate_rows::<UserId>(table, from)?,
+            TableName::TrustedCerts => migrate_rows::<TrustedCertId>(table, from)?,
+            TableName::Crls => migrate_rows::<CrlId>(table, from)?,
+            TableName::AccessLogs => migrate_rows::<AccessLogId>(table, from)?,
+        };
+
+        versions.insert(Stored(table.clone()), table.version());
+        migrated.push(table.clone());
+    }
+
+    Ok(migrated)
+}
+
+// records that all tables are stored with their current versions
+pub fn record_versions(
+) {
+    let mut versions: StableBTreeMap<Stored<TableName>, u32, Memory> =
+        StableBTreeMap::init(get_memory(VERSIONS_MEMORY_ID));
+
+    for table in TABLES.iter() {
+        versions.insert(Stored(table.clone()), table.version());
+    }
+}
+
+fn migrate_rows<K>(
+    table: &TableName,
+    from: u32
+) -> Result<(), String>
+    where
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> {
+    let mut map: StableBTreeMap<Stored<K>, Raw, Memory> =
+        StableBTreeMap::init(get_memory(table.memory_id()));
+
+    let keys: Vec<Stored<K>> = map.iter().map(|(k, _)| k).collect();
+    for k in keys {
+        if let Some(Raw(buf)) = map.get(&k) {
+            let buf = migrate(from, table.version(), buf, |version|
+                table.migrations().iter().find(|m| m.from == version).map(|m| m.row)
+            )?;
+            map.insert(k, Raw(buf));
+        }
+    }
+
+    Ok(())
+}
+
+// helpers for the migrations, converting the values with f
+
+pub fn convert_row<Old, New>(
+    buf: &[u8],
+    f: fn(Old) -> New
+) -> Result<Vec<u8>, String>
+    where
+        Old: CandidType + for<'a> Deserialize<'a>,
+        New: CandidType {
+    let old = decode_one::<Old>(buf).map_err(|e| format!("{:?}", e))?;
+    encode_one(f(old)).map_err(|e| format!("{:?}", e))
+}
+
+pub fn convert_table<K, Old, New>(
+    buf: &[u8],
+    f: fn(Old) -> New
+) -> Result<Vec<u8>, String>
+    where
+        K: Ord + CandidType + for<'a> Deserialize<'a>,
+        Old: CandidType + for<'a> Deserialize<'a>,
+        New: CandidType {
+    let old = decode_args::<'_, (TableData<K, Old>, )>(buf)
+        .map_err(|e| format!("{:?}", e))?.0;
+
+    let new = TableData(old.0.into_iter()
+        .map(|(k, v)| (k, f(v)))
+        .collect::<BTreeMap<K, New>>());
+
+    let mut ser = IDLBuilder::new();
+    (&new, ).encode(&mut ser).map_err(|e| format!("{:?}", e))?;
+    ser.serialize_to_vec().map_err(|e| format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v1_to_v2(
+        buf: &[u8]
+    ) -> Result<Vec<u8>, String> {
+        Ok([buf, b"2"].concat())
+    }
+
+    fn v2_to_v3(
+        buf: &[u8]
+    ) -> Result<Vec<u8>, String> {
+        Ok([buf, b"3"].concat())
+    }
+
+    fn find(
+        version: u32
+    ) -> Option<Convert> {
+        match version {
+            1 => Some(v1_to_v2),
+            2 => Some(v2_to_v3),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn migrate_chains_the_conversions() {
+        assert_eq!(migrate(1, 3, b"1".to_vec(), find).unwrap(), b"123".to_vec());
+        assert_eq!(migrate(2, 3, b"2".to_vec(), find).unwrap(), b"23".to_vec());
+        assert_eq!(migrate(3, 3, b"3".to_vec(), find).unwrap(), b"3".to_vec());
+    }
+
+    #[test]
+    fn migrate_rejects_newer_versions() {
+        assert!(migrate(4, 3, vec![], find).is_err());
+    }
+
+    #[test]
+    fn migrate_fails_on_missing_migrations() {
+        assert!(migrate(1, 4, vec![], find).is_err());
+    }
+
+    #[test]
+    fn the_registry_is_valid() {
+        assert!(validate_registry().is_ok());
+        assert_eq!(TableName::Keys.version(), 2);
+        assert_eq!(TableName::PrescriptionAuths.version(), 3);
+        assert_eq!(TableName::Users.version(), BASE_VERSION);
+    }
+}
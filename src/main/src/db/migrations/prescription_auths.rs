@@ -0,0 +1,196 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use crate::models::prescription::PrescriptionId;
+use crate::models::prescription_auth::{PrescriptionAuthId, PrescriptionAuth, PrescriptionAuthKind, PrescriptionAuthSubject};
+use super::{TableMigration, convert_row, convert_table};
+
+pub const MIGRATIONS: [TableMigration; 2] = [
+    TableMigration {
+        from: 1,
+        row: v1_to_v2_row,
+        table: v1_to_v2_table,
+    },
+    TableMigration {
+        from: 2,
+        row: v2_to_v3_row,
+        table: v2_to_v3_table,
+    },
+];
+
+#[derive(CandidType, Clone, Deserialize)]
+struct PrescriptionAuthV1 {
+    pub id: PrescriptionAuthId,
+    pub prescription_id: PrescriptionId,
+    pub kind: PrescriptionAuthKind,
+    pub from: Principal,
+    pub to: PrescriptionAuthSubject,
+    pub expires_at: Option<u64>,
+    pub created_at: u64,
+    pub created_by: Principal,
+    pub updated_at: Option<u64>,
+    pub updated_by: Option<Principal>,
+    pub deleted_at: Option<u64>,
+    pub deleted_by: Option<Principal>,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+struct PrescriptionAuthV2 {
+    pub id: PrescriptionAuthId,
+    pub prescription_id: PrescriptionId,
+    pub kind: PrescriptionAuthKind,
+    pub from: Principal,
+    pub to: PrescriptionAuthSubject,
+    pub expires_at: Option<u64>,
+    pub chain: Vec<PrescriptionAuthId>,
+    pub created_at: u64,
+    pub created_by: Principal,
+    pub updated_at: Option<u64>,
+    pub updated_by: Option<Principal>,
+    pub deleted_at: Option<u64>,
+    pub deleted_by: Option<Principal>,
+}
+
+// the delegation chain was added. All the grants before it were created by the patients
+fn v1_to_v2(
+    e: PrescriptionAuthV1
+) -> PrescriptionAuthV2 {
+    PrescriptionAuthV2 {
+        id: e.id,
+        prescription_id: e.prescription_id,
+        kind: e.kind,
+        from: e.from,
+        to: e.to,
+        expires_at: e.expires_at,
+        chain: vec![],
+        created_at: e.created_at,
+        created_by: e.created_by,
+        updated_at: e.updated_at,
+        updated_by: e.updated_by,
+        deleted_at: e.deleted_at,
+        deleted_by: e.deleted_by,
+    }
+}
+
+fn v1_to_v2_row(
+    buf: &[u8]
+) -> Result<Vec<u8>, String> {
+    convert_row(buf, v1_to_v2)
+}
+
+fn v1_to_v2_table(
+    buf: &[u8]
+) -> Result<Vec<u8>, String> {
+    convert_table::<PrescriptionAuthId, _, _>(buf, v1_to_v2)
+}
+
+// expired_at was added. The grants expired before it are expired by the next sweep
+fn v2_to_v3(
+    e: PrescriptionAuthV2
+) -> PrescriptionAuth {
+    PrescriptionAuth {
+        id: e.id,
+        prescription_id: e.prescription_id,
+        kind: e.kind,
+        from: e.from,
+        to: e.to,
+        expires_at: e.expires_at,
+        expired_at: None,
+        chain: e.chain,
+        created_at: e.created_at,
+        created_by: e.created_by,
+        updated_at: e.updated_at,
+        updated_by: e.updated_by,
+        deleted_at: e.deleted_at,
+        deleted_by: e.deleted_by,
+    }
+}
+
+fn v2_to_v3_row(
+    buf: &[u8]
+) -> Result<Vec<u8>, String> {
+    convert_row(buf, v2_to_v3)
+}
+
+fn v2_to_v3_table(
+    buf: &[u8]
+) -> Result<Vec<u8>, String> {
+    convert_table::<PrescriptionAuthId, _, _>(buf, v2_to_v3)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use candid::{decode_one, encode_one};
+    use crate::db::TableName;
+    use crate::db::migrations::Migratable;
+    use crate::db::traits::table::TableData;
+    use super::*;
+
+    fn auth_v1(
+    ) -> PrescriptionAuthV1 {
+        PrescriptionAuthV1 {
+            id: "a1".to_string(),
+            prescription_id: "p1".to_string(),
+            kind: PrescriptionAuthKind::Read,
+            from: Principal::anonymous(),
+            to: PrescriptionAuthSubject::Group("g1".to_string()),
+            expires_at: Some(100),
+            created_at: 10,
+            created_by: Principal::anonymous(),
+            updated_at: None,
+            updated_by: None,
+            deleted_at: Some(20),
+            deleted_by: Some(Principal::anonymous()),
+        }
+    }
+
+    #[test]
+    fn v1_to_v2_starts_an_empty_chain() {
+        let buf = v1_to_v2_row(&encode_one(auth_v1()).unwrap()).unwrap();
+        let auth = decode_one::<PrescriptionAuthV2>(&buf).unwrap();
+        assert_eq!(auth.id, "a1");
+        assert!(auth.chain.is_empty());
+        assert!(auth.kind == PrescriptionAuthKind::Read);
+        assert!(auth.to == PrescriptionAuthSubject::Group("g1".to_string()));
+        assert_eq!(auth.expires_at, Some(100));
+        assert_eq!(auth.deleted_at, Some(20));
+    }
+
+    #[test]
+    fn v2_to_v3_leaves_the_expiration_to_the_sweeper() {
+        let buf = v1_to_v2_row(&encode_one(auth_v1()).unwrap()).unwrap();
+        let buf = v2_to_v3_row(&buf).unwrap();
+        let auth = decode_one::<PrescriptionAuth>(&buf).unwrap();
+        assert_eq!(auth.expired_at, None);
+        assert!(auth.chain.is_empty());
+        assert_eq!(auth.prescription_id, "p1");
+        assert_eq!(auth.expires_at, Some(100));
+        assert_eq!(auth.created_at, 10);
+        assert_eq!(auth.deleted_at, Some(20));
+    }
+
+    #[test]
+    fn v1_tables_are_migrated_through_the_registry() {
+        let mut rows = BTreeMap::new();
+        rows.insert("a1".to_string(), auth_v1());
+        let buf = candid::encode_args((TableData(rows), )).unwrap();
+
+        let buf = TableName::PrescriptionAuths.migrate_table(1, buf).unwrap();
+        let table = candid::decode_args::<'_, (TableData<PrescriptionAuthId, PrescriptionAuth>, )>(&buf).unwrap().0;
+        assert_eq!(table.0.len(), 1);
+        assert!(table.0["a1"].chain.is_empty());
+        assert_eq!(table.0["a1"].expired_at, None);
+    }
+
+    #[test]
+    fn v2_tables_skip_the_first_migration() {
+        let buf = v1_to_v2_row(&encode_one(auth_v1()).unwrap()).unwrap();
+        let mut rows = BTreeMap::new();
+        rows.insert("a1".to_string(), decode_one::<PrescriptionAuthV2>(&buf).unwrap());
+        let buf = candid::encode_args((TableData(rows), )).unwrap();
+
+        let buf = TableName::PrescriptionAuths.migrate_table(2, buf).unwrap();
+        let table = candid::decode_args::<'_, (TableData<PrescriptionAuthId, PrescriptionAuth>, )>(&buf).unwrap().0;
+        assert_eq!(table.0["a1"].id, "a1");
+    }
+}
@@ -0,0 +1,6 @@
+use super::{Migration, BASE_VERSION};
+
+// none yet: the STATE is still stored as it was with the version 0.1
+pub const MIGRATIONS: [Migration; 0] = [];
+
+pub const VERSION: u32 = BASE_VERSION + MIGRATIONS.len() as u32;
@@ -1,10 +1,18 @@
 pub mod traits;
 pub mod tables;
 pub mod migrations;
+pub mod memory;
+pub mod reindex;
 
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
-use ic_cdk::api::stable::{StableWriter, StableReader};
+use candid::CandidType;
+use serde::Deserialize;
+use ic_stable_structures::memory_manager::MemoryId;
+use self::tables::access_logs::AccessLogsTable;
+use self::tables::crls::CrlsTable;
+use self::tables::doctor_templates_rel::DoctorTemplatesRelTable;
 use self::tables::user_prescriptions_rel::UserPrescriptionsRelTable;
 use self::tables::groups::GroupsTable;
 use self::tables::key_principal_rel::KeyPrincipalRelTable;
@@ -15,10 +23,13 @@ use self::tables::keys::KeysTable;
 use self::tables::prescriptions::PrescriptionsTable;
 use self::tables::principal_groups_rel::PrincipalGroupsRelTable;
 use self::tables::principal_keys_rel::PrincipalKeysRelTable;
+use self::tables::trusted_certs::TrustedCertsTable;
 use self::tables::users::UsersTable;
-use self::traits::table::{TableSerializable, TableDeserializable, TableSubscribable, Table};
+use self::traits::table::{TableDeserializable, TableSubscribable, Table};
+use self::traits::transaction::Transactional;
+use crate::utils::serdeser::read_raw;
 
-#[derive(Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TableName {
     Users,
     Prescriptions,
@@ -31,8 +42,114 @@ pub enum TableName {
     KeyPrincipalRel,
     Groups,
     PrincipalGroupsRel,
+    DoctorTemplatesRel,
+    TrustedCerts,
+    Crls,
+    AccessLogs,
 }
 
+impl fmt::Display for TableName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableName::Users => write!(f, "user"),
+            TableName::Prescriptions => write!(f, "prescription"),
+            TableName::Keys => write!(f, "key"),
+            TableName::PrescriptionAuths => write!(f, "prescription_auth"),
+            TableName::PrescriptionTemplates => write!(f, "prescription_template"),
+            TableName::UserPrescriptionsRel => write!(f, "user_prescriptions_rel"),
+            TableName::PrescriptionAuthsRel => write!(f, "prescription_auths_rel"),
+            TableName::PrincipalKeysRel => write!(f, "principal_keys_rel"),
+            TableName::KeyPrincipalRel => write!(f, "key_principal_rel"),
+            TableName::Groups => write!(f, "group"),
+            TableName::PrincipalGroupsRel => write!(f, "principal_groups_rel"),
+            TableName::DoctorTemplatesRel => write!(f, "doctor_templates_rel"),
+            TableName::TrustedCerts => write!(f, "trusted_cert"),
+            TableName::Crls => write!(f, "crl"),
+            TableName::AccessLogs => write!(f, "access_log"),
+        }
+    }
+}
+
+impl TableName {
+    // each table has its own virtual memory. Ids must never be reused or reordered
+    pub fn memory_id(
+        &self
+    ) -> MemoryId {
+        MemoryId::new(self.id())
+    }
+
+    // each table can have up to INDEXES_PER_TABLE indexes, stored after the tables' memories
+    pub fn index_memory_id(
+        &self,
+        index: u8
+    ) -> MemoryId {
+        assert!(index < INDEXES_PER_TABLE, "Too many indexes in table {}", self);
+        MemoryId::new(INDEXES_MEMORY_ID + self.id() * INDEXES_PER_TABLE + index)
+    }
+
+    fn id(
+        &self
+    ) -> u8 {
+        match self {
+            TableName::Users => 0,
+            TableName::Prescriptions => 1,
+            TableName::Keys => 2,
+            TableName::PrescriptionAuths => 3,
+            TableName::PrescriptionTemplates => 4,
+            TableName::UserPrescriptionsRel => 5,
+            TableName::PrescriptionAuthsRel => 6,
+            TableName::PrincipalKeysRel => 7,
+            TableName::KeyPrincipalRel => 8,
+            TableName::Groups => 9,
+            TableName::PrincipalGroupsRel => 10,
+            TableName::DoctorTemplatesRel => 11,
+            TableName::TrustedCerts => 12,
+            TableName::Crls => 13,
+            TableName::AccessLogs => 14,
+        }
+    }
+
+    // relation tables are derived from the other tables through their events
+    pub fn is_relation(
+        &self
+    ) -> bool {
+        match self {
+            TableName::UserPrescriptionsRel |
+            TableName::PrescriptionAuthsRel |
+            TableName::PrincipalKeysRel |
+            TableName::KeyPrincipalRel |
+            TableName::PrincipalGroupsRel |
+            TableName::DoctorTemplatesRel => true,
+            _ => false,
+        }
+    }
+}
+
+pub const TABLES: [TableName; 15] = [
+    TableName::Users,
+    TableName::Keys,
+    TableName::Prescriptions,
+    TableName::PrescriptionAuths,
+    TableName::PrescriptionTemplates,
+    TableName::UserPrescriptionsRel,
+    TableName::PrescriptionAuthsRel,
+    TableName::PrincipalKeysRel,
+    TableName::KeyPrincipalRel,
+    TableName::Groups,
+    TableName::PrincipalGroupsRel,
+    TableName::DoctorTemplatesRel,
+    TableName::TrustedCerts,
+    TableName::Crls,
+    TableName::AccessLogs,
+];
+
+const INDEXES_MEMORY_ID: u8 = 64;
+const INDEXES_PER_TABLE: u8 = 4;
+
+// number of tables stored by the versions that dumped the whole DB on pre_upgrade, from users
+// to principal_groups_rel. That layout is frozen: tables added later are never part of it
+const LEGACY_TABLES: usize = 11;
+
 pub struct DB {
     pub users: Rc<RefCell<UsersTable>>,
     pub prescriptions: Rc<RefCell<PrescriptionsTable>>,
@@ -45,6 +162,10 @@ pub struct DB {
     pub key_principal_rel: Rc<RefCell<KeyPrincipalRelTable>>,
     pub groups: Rc<RefCell<GroupsTable>>,
     pub principal_groups_rel: Rc<RefCell<PrincipalGroupsRelTable>>,
+    pub doctor_templates_rel: Rc<RefCell<DoctorTemplatesRelTable>>,
+    pub trusted_certs: Rc<RefCell<TrustedCertsTable>>,
+    pub crls: Rc<RefCell<CrlsTable>>,
+    pub access_logs: Rc<RefCell<AccessLogsTable>>,
 }
 
 impl DB {
@@ -61,6 +182,10 @@ impl DB {
         let key_principal_rel = Rc::new(RefCell::new(KeyPrincipalRelTable::new()));
         let groups = Rc::new(RefCell::new(GroupsTable::new()));
         let principal_groups_rel = Rc::new(RefCell::new(PrincipalGroupsRelTable::new()));
+        let doctor_templates_rel = Rc::new(RefCell::new(DoctorTemplatesRelTable::new()));
+        let trusted_certs = Rc::new(RefCell::new(TrustedCertsTable::new()));
+        let crls = Rc::new(RefCell::new(CrlsTable::new()));
+        let access_logs = Rc::new(RefCell::new(AccessLogsTable::new()));
 
         //
         prescriptions.borrow_mut().subscribe(user_prescriptions_rel.clone());
@@ -74,6 +199,8 @@ impl DB {
         prescription_auths.borrow_mut().subscribe(user_prescriptions_rel.clone());
         //
         groups.borrow_mut().subscribe(principal_groups_rel.clone());
+        //
+        prescription_templates.borrow_mut().subscribe(doctor_templates_rel.clone());
         
         Self {
             users,
@@ -87,42 +214,176 @@ impl DB {
             key_principal_rel,
             groups,
             principal_groups_rel,
+            doctor_templates_rel,
+            trusted_certs,
+            crls,
+            access_logs,
         }
     }
 
-    pub fn serialize(
-        &self,
-        writer: &mut StableWriter
-    ) -> Result<(), String> {
-        self.users.borrow().serialize(writer)?;
-        self.keys.borrow().serialize(writer)?;
-        self.prescriptions.borrow().serialize(writer)?;
-        self.prescription_auths.borrow().serialize(writer)?;
-        self.prescription_templates.borrow().serialize(writer)?;
-        self.user_prescriptions_rel.borrow().serialize(writer)?;
-        self.prescription_auths_rel.borrow().serialize(writer)?;
-        self.principal_keys_rel.borrow().serialize(writer)?;
-        self.key_principal_rel.borrow().serialize(writer)?;
-        self.groups.borrow().serialize(writer)?;
-        self.principal_groups_rel.borrow().serialize(writer)?;
-        Ok(())
+    // reads the tables dumped by the versions prior to the stable structures, without decoding them
+    pub fn read_legacy(
+        reader: &mut impl std::io::Read
+    ) -> Result<Vec<(u32, Vec<u8>)>, String> {
+        (0..LEGACY_TABLES)
+            .map(|_| read_raw(reader))
+            .collect()
     }
 
-    pub fn deserialize(
+    pub fn deserialize_legacy(
         &mut self,
-        reader: &mut StableReader
+        tables: &Vec<(u32, Vec<u8>)>
     ) -> Result<(), String> {
-        self.users.borrow_mut().deserialize(reader, true)?;
-        self.keys.borrow_mut().deserialize(reader, true)?;
-        self.prescriptions.borrow_mut().deserialize(reader, true)?;
-        self.prescription_auths.borrow_mut().deserialize(reader, true)?;
-        self.prescription_templates.borrow_mut().deserialize(reader, true)?;
-        self.user_prescriptions_rel.borrow_mut().deserialize(reader, true)?;
-        self.prescription_auths_rel.borrow_mut().deserialize(reader, true)?;
-        self.principal_keys_rel.borrow_mut().deserialize(reader, true)?;
-        self.key_principal_rel.borrow_mut().deserialize(reader, true)?;
-        self.groups.borrow_mut().deserialize(reader, true)?;
-        self.principal_groups_rel.borrow_mut().deserialize(reader, true)?;
+        if tables.len() != LEGACY_TABLES {
+            return Err("Invalid number of tables".to_string());
+        }
+
+        self.users.borrow_mut().deserialize(tables[0].0, &tables[0].1)?;
+        self.keys.borrow_mut().deserialize(tables[1].0, &tables[1].1)?;
+        self.prescriptions.borrow_mut().deserialize(tables[2].0, &tables[2].1)?;
+        self.prescription_auths.borrow_mut().deserialize(tables[3].0, &tables[3].1)?;
+        self.prescription_templates.borrow_mut().deserialize(tables[4].0, &tables[4].1)?;
+        self.user_prescriptions_rel.borrow_mut().deserialize(tables[5].0, &tables[5].1)?;
+        self.prescription_auths_rel.borrow_mut().deserialize(tables[6].0, &tables[6].1)?;
+        self.principal_keys_rel.borrow_mut().deserialize(tables[7].0, &tables[7].1)?;
+        self.key_principal_rel.borrow_mut().deserialize(tables[8].0, &tables[8].1)?;
+        self.groups.borrow_mut().deserialize(tables[9].0, &tables[9].1)?;
+        self.principal_groups_rel.borrow_mut().deserialize(tables[10].0, &tables[10].1)?;
+        // the tables added after the legacy layout was frozen start empty, except the relations,
+        // which are derived from the primary tables already loaded
+        self.rebuild_doctor_templates_rel();
         Ok(())
     }
+
+    fn for_each_table(
+        &self,
+        f: impl Fn(&mut dyn Transactional)
+    ) {
+        f(self.users.borrow_mut().get_data_mut());
+        f(self.keys.borrow_mut().get_data_mut());
+        f(self.prescriptions.borrow_mut().get_data_mut());
+        f(self.prescription_auths.borrow_mut().get_data_mut());
+        f(self.prescription_templates.borrow_mut().get_data_mut());
+        f(self.user_prescriptions_rel.borrow_mut().get_data_mut());
+        f(self.prescription_auths_rel.borrow_mut().get_data_mut());
+        f(self.principal_keys_rel.borrow_mut().get_data_mut());
+        f(self.key_principal_rel.borrow_mut().get_data_mut());
+        f(self.groups.borrow_mut().get_data_mut());
+        f(self.principal_groups_rel.borrow_mut().get_data_mut());
+        f(self.doctor_templates_rel.borrow_mut().get_data_mut());
+        f(self.trusted_certs.borrow_mut().get_data_mut());
+        f(self.crls.borrow_mut().get_data_mut());
+        f(self.access_logs.borrow_mut().get_data_mut());
+    }
+}
+
+// changes to all tables, including the ones made by subscribers, are committed or rolled back together
+impl Transactional for DB {
+    fn begin(
+        &mut self
+    ) {
+        self.for_each_table(|table| table.begin());
+    }
+
+    fn commit(
+        &mut self
+    ) {
+        self.for_each_table(|table| table.commit());
+    }
+
+    fn rollback(
+        &mut self
+    ) {
+        self.for_each_table(|table| table.rollback());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use candid::{CandidType, Principal, ser::IDLBuilder, utils::ArgumentEncoder};
+    use crate::db::traits::table::TableData;
+    use crate::models::group::{Group, GroupId};
+    use crate::models::key::{Key, KeyId};
+    use crate::models::prescription::{Prescription, PrescriptionId};
+    use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthId};
+    use crate::models::prescription_template::{PrescriptionTemplate, PrescriptionTemplateId};
+    use crate::models::user::{User, UserId};
+    use crate::utils::serdeser::{serialize, read_raw};
+    use super::*;
+
+    fn empty_table<K, V>(
+        table: TableName
+    ) -> (u32, Vec<u8>)
+        where
+            K: Ord + CandidType,
+            V: CandidType {
+        let mut ser = IDLBuilder::new();
+        (&TableData::<K, V>(BTreeMap::new()), ).encode(&mut ser).unwrap();
+        (table.version(), ser.serialize_to_vec().unwrap())
+    }
+
+    // the tables dumped by the baseline, in the order they were written
+    fn baseline_tables(
+    ) -> Vec<(u32, Vec<u8>)> {
+        vec![
+            empty_table::<UserId, User>(TableName::Users),
+            empty_table::<KeyId, Key>(TableName::Keys),
+            empty_table::<PrescriptionId, Prescription>(TableName::Prescriptions),
+            empty_table::<PrescriptionAuthId, PrescriptionAuth>(TableName::PrescriptionAuths),
+            empty_table::<PrescriptionTemplateId, PrescriptionTemplate>(TableName::PrescriptionTemplates),
+            empty_table::<UserId, BTreeSet<PrescriptionId>>(TableName::UserPrescriptionsRel),
+            empty_table::<PrescriptionId, BTreeSet<PrescriptionAuthId>>(TableName::PrescriptionAuthsRel),
+            empty_table::<Principal, BTreeSet<KeyId>>(TableName::PrincipalKeysRel),
+            empty_table::<KeyId, Principal>(TableName::KeyPrincipalRel),
+            empty_table::<GroupId, Group>(TableName::Groups),
+            empty_table::<Principal, BTreeSet<GroupId>>(TableName::PrincipalGroupsRel),
+        ]
+    }
+
+    // the STATE is dumped right after the tables
+    fn legacy_dump(
+        tables: usize
+    ) -> Vec<u8> {
+        let mut buf = vec![];
+        for _ in 0..tables {
+            serialize(&TableData::<String, String>(BTreeMap::new()), 1, &mut buf).unwrap();
+        }
+        serialize(&"state".to_string(), 7, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn read_legacy_stops_before_the_state() {
+        let buf = legacy_dump(11);
+        let mut reader = &buf[..];
+
+        let tables = DB::read_legacy(&mut reader).unwrap();
+        assert_eq!(tables.len(), 11);
+
+        let (version, _) = read_raw(&mut reader).unwrap();
+        assert_eq!(version, 7);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn deserialize_legacy_rejects_other_layouts() {
+        let mut db = DB::new();
+        let tables = vec![(1, vec![]); 14];
+        assert!(db.deserialize_legacy(&tables).is_err());
+    }
+
+    #[test]
+    fn legacy_load_leaves_trusted_certs_empty() {
+        let mut db = DB::new();
+        db.deserialize_legacy(&baseline_tables()).unwrap();
+        assert_eq!(db.trusted_certs.borrow().get_data().len(), 0);
+    }
+
+    #[test]
+    fn legacy_load_leaves_crls_empty() {
+        let mut db = DB::new();
+        db.deserialize_legacy(&baseline_tables()).unwrap();
+        assert_eq!(db.crls.borrow().get_data().len(), 0);
+    }
 }
@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use crate::models::consistency::{ConsistencyReport, ConsistencyTableReport, RebuildStatus};
+use crate::models::group::{Group, GroupId};
+use crate::models::key::{Key, KeyId};
+use crate::models::prescription::{Prescription, PrescriptionId};
+use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthId, PrescriptionAuthSubject};
+use crate::models::prescription_template::{PrescriptionTemplate, PrescriptionTemplateId};
+use super::{DB, TableName};
+use super::traits::table::{Table, TableData, TableStorage};
+
+// contents of the relation tables, as derived from the primary tables
+struct Relations {
+    user_prescriptions: BTreeMap<Principal, BTreeSet<PrescriptionId>>,
+    prescription_auths: BTreeMap<PrescriptionId, BTreeSet<PrescriptionAuthId>>,
+    principal_keys: BTreeMap<Principal, BTreeSet<KeyId>>,
+    key_principal: BTreeMap<KeyId, Principal>,
+    principal_groups: BTreeMap<Principal, BTreeSet<GroupId>>,
+    doctor_templates: BTreeMap<Principal, BTreeSet<PrescriptionTemplateId>>,
+}
+
+// primary tables, in the order their rows are processed by rebuild_chunk()
+const REBUILD_TABLES: [TableName; 9] = [
+    TableName::Users,
+    TableName::Keys,
+    TableName::Prescriptions,
+    TableName::PrescriptionAuths,
+    TableName::PrescriptionTemplates,
+    TableName::Groups,
+    TableName::TrustedCerts,
+    TableName::Crls,
+    TableName::AccessLogs,
+];
+
+impl DB {
+    // compares the relation tables with the ones derived from the primary tables
+    pub fn check_relations(
+        &self
+    ) -> ConsistencyReport {
+        let rels = self.derive_relations();
+
+        let tables = vec![
+            compare(
+                TableName::UserPrescriptionsRel,
+                self.user_prescriptions_rel.borrow().get_data(),
+                &rels.user_prescriptions,
+                |v| v.is_empty()
+            ),
+            compare(
+                TableName::PrescriptionAuthsRel,
+                self.prescription_auths_rel.borrow().get_data(),
+                &rels.prescription_auths,
+                |v| v.is_empty()
+            ),
+            compare(
+                TableName::PrincipalKeysRel,
+                self.principal_keys_rel.borrow().get_data(),
+                &rels.principal_keys,
+                |v| v.is_empty()
+            ),
+            compare(
+                TableName::KeyPrincipalRel,
+                self.key_principal_rel.borrow().get_data(),
+                &rels.key_principal,
+                |_| false
+            ),
+            compare(
+                TableName::PrincipalGroupsRel,
+                self.principal_groups_rel.borrow().get_data(),
+                &rels.principal_groups,
+                |v| v.is_empty()
+            ),
+            compare(
+                TableName::DoctorTemplatesRel,
+                self.doctor_templates_rel.borrow().get_data(),
+                &rels.doctor_templates,
+                |v| v.is_empty()
+            ),
+        ];
+
+        ConsistencyReport {
+            consistent: tables.iter().all(|e| e.is_consistent()),
+            tables,
+            checked_at: ic_cdk::api::time(),
+        }
+    }
+
+    /*
+     * drops the relation tables and the indexes of the primary tables, which are then rebuilt by
+     * rebuild_chunk(). Resetting the memories can't be undone, so it must not be called inside a transaction
+     */
+    pub fn rebuild_begin(
+        &mut self,
+        now: u64
+    ) -> RebuildStatus {
+        self.user_prescriptions_rel.borrow_mut().get_data_mut().reset();
+        self.prescription_auths_rel.borrow_mut().get_data_mut().reset();
+        self.principal_keys_rel.borrow_mut().get_data_mut().reset();
+        self.key_principal_rel.borrow_mut().get_data_mut().reset();
+        self.principal_groups_rel.borrow_mut().get_data_mut().reset();
+        self.doctor_templates_rel.borrow_mut().get_data_mut().reset();
+
+        self.users.borrow_mut().get_data_mut().reset_indexes();
+        self.keys.borrow_mut().get_data_mut().reset_indexes();
+        self.prescriptions.borrow_mut().get_data_mut().reset_indexes();
+        self.prescription_auths.borrow_mut().get_data_mut().reset_indexes();
+        self.prescription_templates.borrow_mut().get_data_mut().reset_indexes();
+        self.groups.borrow_mut().get_data_mut().reset_indexes();
+        self.trusted_certs.borrow_mut().get_data_mut().reset_indexes();
+        self.crls.borrow_mut().get_data_mut().reset_indexes();
+        self.access_logs.borrow_mut().get_data_mut().reset_indexes();
+
+        RebuildStatus {
+            table: REBUILD_TABLES[0].clone(),
+            cursor: None,
+            rows: 0,
+            done: false,
+            started_at: now,
+        }
+    }
+
+    // indexes up to max_rows rows of the primary tables, from the cursor of status on, and adds their relations
+    pub fn rebuild_chunk(
+        &mut self,
+        status: &RebuildStatus,
+        max_rows: usize
+    ) -> Result<RebuildStatus, String> {
+        let mut rels = Relations::new();
+        let cursor = &status.cursor;
+        let (rows, next) = match status.table {
+            TableName::Users => reindex_chunk(self.users.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, |_, _, _| ()),
+            TableName::Keys => reindex_chunk(self.keys.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, Relations::add_key),
+            TableName::Prescriptions => reindex_chunk(self.prescriptions.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, Relations::add_prescription),
+            TableName::PrescriptionAuths => reindex_chunk(self.prescription_auths.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, Relations::add_auth),
+            TableName::PrescriptionTemplates => reindex_chunk(self.prescription_templates.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, Relations::add_template),
+            TableName::Groups => reindex_chunk(self.groups.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, Relations::add_group),
+            TableName::TrustedCerts => reindex_chunk(self.trusted_certs.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, |_, _, _| ()),
+            TableName::Crls => reindex_chunk(self.crls.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, |_, _, _| ()),
+            TableName::AccessLogs => reindex_chunk(self.access_logs.borrow_mut().get_data_mut(), cursor, max_rows, &mut rels, |_, _, _| ()),
+            _ => return Err(format!("Table {} isn't rebuilt", status.table)),
+        }?;
+
+        self.add_relations(rels);
+
+        let mut status = status.clone();
+        status.rows += rows;
+        status.cursor = next;
+        if status.cursor.is_none() {
+            match REBUILD_TABLES.iter().position(|e| *e == status.table) {
+                Some(i) if i + 1 < REBUILD_TABLES.len() => status.table = REBUILD_TABLES[i + 1].clone(),
+                _ => status.done = true,
+            }
+        }
+
+        Ok(status)
+    }
+
+    // doctor_templates_rel isn't part of the legacy layout, so it's derived after that is loaded
+    pub fn rebuild_doctor_templates_rel(
+        &mut self
+    ) {
+        let rels = self.derive_relations();
+        self.doctor_templates_rel.borrow_mut().set_data(TableData(rels.doctor_templates));
+    }
+
+    // rebuilds the secondary indexes of the primary tables from their rows
+    pub fn rebuild_indexes(
+        &mut self
+    ) {
+        self.users.borrow_mut().get_data_mut().rebuild_indexes();
+        self.keys.borrow_mut().get_data_mut().rebuild_indexes();
+        self.prescriptions.borrow_mut().get_data_mut().rebuild_indexes();
+        self.prescription_auths.borrow_mut().get_data_mut().rebuild_indexes();
+        self.prescription_templates.borrow_mut().get_data_mut().rebuild_indexes();
+        self.groups.borrow_mut().get_data_mut().rebuild_indexes();
+        self.trusted_certs.borrow_mut().get_data_mut().rebuild_indexes();
+        self.crls.borrow_mut().get_data_mut().rebuild_indexes();
+        self.access_logs.borrow_mut().get_data_mut().rebuild_indexes();
+    }
+
+    fn derive_relations(
+        &self
+    ) -> Relations {
+        let mut rels = Relations::new();
+
+        for (id, prescription) in self.prescriptions.borrow().get_data().iter() {
+            rels.add_prescription(id, prescription);
+        }
+
+        for (id, auth) in self.prescription_auths.borrow().get_data().iter() {
+            rels.add_auth(id, auth);
+        }
+
+        for (id, key) in self.keys.borrow().get_data().iter() {
+            rels.add_key(id, key);
+        }
+
+        for (id, group) in self.groups.borrow().get_data().iter() {
+            rels.add_group(id, group);
+        }
+
+        for (id, template) in self.prescription_templates.borrow().get_data().iter() {
+            rels.add_template(id, template);
+        }
+
+        rels
+    }
+
+    // merges relations derived from some of the rows into the relation tables
+    fn add_relations(
+        &mut self,
+        rels: Relations
+    ) {
+        add_sets(self.user_prescriptions_rel.borrow_mut().get_data_mut(), rels.user_prescriptions);
+        add_sets(self.prescription_auths_rel.borrow_mut().get_data_mut(), rels.prescription_auths);
+        add_sets(self.principal_keys_rel.borrow_mut().get_data_mut(), rels.principal_keys);
+        add_sets(self.principal_groups_rel.borrow_mut().get_data_mut(), rels.principal_groups);
+        add_sets(self.doctor_templates_rel.borrow_mut().get_data_mut(), rels.doctor_templates);
+
+        let mut key_principal = self.key_principal_rel.borrow_mut();
+        for (id, principal) in rels.key_principal {
+            key_principal.get_data_mut().insert(id, principal);
+        }
+    }
+}
+
+/*
+ * must be kept in sync with the TableSubscriber::on() handlers of the relation tables.
+ * Soft deleted rows are skipped, as their deletion was notified
+ */
+impl Relations {
+    fn new(
+    ) -> Self {
+        Self {
+            user_prescriptions: BTreeMap::new(),
+            prescription_auths: BTreeMap::new(),
+            principal_keys: BTreeMap::new(),
+            key_principal: BTreeMap::new(),
+            principal_groups: BTreeMap::new(),
+            doctor_templates: BTreeMap::new(),
+        }
+    }
+
+    fn add_prescription(
+        &mut self,
+        id: PrescriptionId,
+        prescription: Prescription
+    ) {
+        if prescription.deleted_at.is_some() {
+            return;
+        }
+        self.user_prescriptions.entry(prescription.doctor).or_default()
+            .insert(id.clone());
+        self.user_prescriptions.entry(prescription.patient).or_default()
+            .insert(id);
+    }
+
+    fn add_auth(
+        &mut self,
+        id: PrescriptionAuthId,
+        auth: PrescriptionAuth
+    ) {
+        if auth.deleted_at.is_some() {
+            return;
+        }
+        if let PrescriptionAuthSubject::User(to) = auth.to {
+            self.user_prescriptions.entry(to).or_default()
+                .insert(auth.prescription_id.clone());
+        }
+        self.prescription_auths.entry(auth.prescription_id).or_default()
+            .insert(id);
+    }
+
+    fn add_key(
+        &mut self,
+        id: KeyId,
+        key: Key
+    ) {
+        if key.deleted_at.is_some() {
+            return;
+        }
+        self.principal_keys.entry(key.created_by).or_default()
+            .insert(id.clone());
+        self.key_principal.insert(id, key.created_by);
+    }
+
+    fn add_group(
+        &mut self,
+        id: GroupId,
+        group: Group
+    ) {
+        // group creator is included
+        self.principal_groups.entry(group.created_by).or_default()
+            .insert(id.clone());
+        for member in group.members {
+            self.principal_groups.entry(member).or_default()
+                .insert(id.clone());
+        }
+    }
+
+    fn add_template(
+        &mut self,
+        id: PrescriptionTemplateId,
+        template: PrescriptionTemplate
+    ) {
+        if template.deleted_at.is_some() {
+            return;
+        }
+        self.doctor_templates.entry(template.created_by).or_default()
+            .insert(id);
+    }
+}
+
+fn reindex_chunk<K, V>(
+    storage: &mut TableStorage<K, V>,
+    cursor: &Option<Vec<u8>>,
+    max_rows: usize,
+    rels: &mut Relations,
+    relate: fn(&mut Relations, K, V)
+) -> Result<(u64, Option<Vec<u8>>), String>
+    where
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>,
+        V: CandidType + for<'a> Deserialize<'a> {
+    let (rows, next) = storage.reindex_chunk(cursor, max_rows)?;
+    let count = rows.len() as u64;
+    for (k, v) in rows {
+        relate(rels, k, v);
+    }
+
+    Ok((count, next))
+}
+
+fn add_sets<K, V>(
+    storage: &mut TableStorage<K, BTreeSet<V>>,
+    sets: BTreeMap<K, BTreeSet<V>>
+)
+    where
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>,
+        V: Ord + CandidType + for<'a> Deserialize<'a> {
+    for (k, set) in sets {
+        let mut stored = storage.get(&k).unwrap_or_default();
+        stored.extend(set);
+        storage.insert(k, stored);
+    }
+}
+
+fn compare<K, V>(
+    table: TableName,
+    stored: &TableStorage<K, V>,
+    expected: &BTreeMap<K, V>,
+    is_empty: fn(&V) -> bool
+) -> ConsistencyTableReport
+    where
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> + Display,
+        V: PartialEq + CandidType + for<'a> Deserialize<'a> {
+    let mut report = ConsistencyTableReport::new(table);
+    report.expected_rows = expected.len() as u64;
+
+    for (k, v) in stored.iter() {
+        report.rows += 1;
+        match expected.get(&k) {
+            // empty rows are left behind by deletions
+            None => if !is_empty(&v) {
+                report.unexpected += 1;
+                report.add_divergence(format!("{}: unexpected", k));
+            },
+            Some(e) => if *e != v {
+                report.different += 1;
+                report.add_divergence(format!("{}: different", k));
+            },
+        }
+    }
+
+    for k in expected.keys() {
+        if !stored.contains_key(k) {
+            report.missing += 1;
+            report.add_divergence(format!("{}: missing", k));
+        }
+    }
+
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::db::tables::prescription_auths::PrescriptionAuthsTable;
+    use crate::db::traits::crud::Crud;
+    use crate::db::traits::transaction::Transactional;
+    use crate::fixtures::{auth, prescription, principal, template};
+    use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthKind};
+    use crate::models::prescription_template::PrescriptionTemplate;
+    use super::*;
+
+    fn ids(
+        ids: &[&str]
+    ) -> BTreeSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    // the primary tables are written directly, so the relation tables aren't notified
+    fn db(
+    ) -> DB {
+        let db = DB::new();
+        db.prescriptions.borrow_mut().insert("p1".to_string(), prescription("p1")).unwrap();
+        db.prescription_auths.borrow_mut().insert("a1".to_string(), PrescriptionAuth {
+            to: PrescriptionAuthSubject::User(principal(3)),
+            ..auth("a1", PrescriptionAuthKind::Read)
+        }).unwrap();
+        db.prescription_auths.borrow_mut().insert("a2".to_string(), PrescriptionAuth {
+            to: PrescriptionAuthSubject::User(principal(4)),
+            deleted_at: Some(10),
+            ..auth("a2", PrescriptionAuthKind::Read)
+        }).unwrap();
+        db.prescription_templates.borrow_mut().insert("t1".to_string(), template("t1")).unwrap();
+        db.prescription_templates.borrow_mut().insert("t2".to_string(), PrescriptionTemplate {
+            deleted_at: Some(10),
+            ..template("t2")
+        }).unwrap();
+        db
+    }
+
+    // one row per chunk, so every table is resumed from its cursor
+    fn rebuild(
+        db: &mut DB
+    ) -> RebuildStatus {
+        let mut status = db.rebuild_begin(0);
+        while !status.done {
+            status = db.transaction(|db| db.rebuild_chunk(&status, 1)).unwrap();
+        }
+        status
+    }
+
+    #[test]
+    fn rebuild_derives_the_relations_from_the_live_rows() {
+        let mut db = db();
+        let status = rebuild(&mut db);
+        assert_eq!(status.rows, 5);
+
+        let user_prescriptions = db.user_prescriptions_rel.borrow();
+        assert_eq!(user_prescriptions.find_by_id(&principal(1)), Some(ids(&["p1"])));
+        assert_eq!(user_prescriptions.find_by_id(&principal(2)), Some(ids(&["p1"])));
+        assert_eq!(user_prescriptions.find_by_id(&principal(3)), Some(ids(&["p1"])));
+        assert_eq!(user_prescriptions.find_by_id(&principal(4)), None);
+
+        assert_eq!(db.prescription_auths_rel.borrow().find_by_id(&"p1".to_string()), Some(ids(&["a1"])));
+        assert_eq!(db.doctor_templates_rel.borrow().find_by_id(&principal(1)), Some(ids(&["t1"])));
+    }
+
+    #[test]
+    fn rebuild_replaces_the_stale_rows() {
+        let mut db = db();
+        db.user_prescriptions_rel.borrow_mut().get_data_mut().insert(principal(5), ids(&["p9"]));
+        rebuild(&mut db);
+
+        assert_eq!(db.user_prescriptions_rel.borrow().find_by_id(&principal(5)), None);
+    }
+
+    #[test]
+    fn rebuild_restores_the_indexes() {
+        let mut db = db();
+        db.prescription_auths.borrow_mut().get_data_mut().reset_indexes();
+        rebuild(&mut db);
+
+        let key = vec![PrescriptionAuthsTable::subject_value(&PrescriptionAuthSubject::User(principal(3)))];
+        assert_eq!(db.prescription_auths.borrow().get_data().find_ids("to", &key), vec!["a1".to_string()]);
+    }
+
+    #[test]
+    fn rebuild_doctor_templates_rel_leaves_the_other_relations() {
+        let mut db = db();
+        db.rebuild_doctor_templates_rel();
+
+        assert_eq!(db.doctor_templates_rel.borrow().find_by_id(&principal(1)), Some(ids(&["t1"])));
+        assert_eq!(db.user_prescriptions_rel.borrow().get_data().len(), 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,62 @@
+use crate::db::TableName;
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableStorage, Table, TableSchema, TableVersioned};
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::crud::Crud;
+use crate::models::access_log::{AccessLogId, AccessLog};
+
+pub struct AccessLogsTable {
+    pub schema: TableSchema<TableName>,
+    pub data: TableStorage<AccessLogId, AccessLog>,
+}
+
+impl Table<TableName, AccessLogId, AccessLog> for AccessLogsTable {
+    fn new(
+    ) -> Self {
+        Self {
+            schema: TableSchema { 
+                version: TableName::AccessLogs.version(),
+                name: TableName::AccessLogs, 
+            },
+            data: TableStorage::new(get_memory(TableName::AccessLogs.memory_id()))
+                .with_index(
+                    "patient",
+                    false,
+                    get_memory(TableName::AccessLogs.index_memory_id(0)),
+                    |v| vec![vec![IndexValue::Principal(v.patient)]]
+                )
+                .with_index(
+                    "doctor",
+                    false,
+                    get_memory(TableName::AccessLogs.index_memory_id(1)),
+                    |v| vec![vec![IndexValue::Principal(v.doctor)]]
+                ),
+        }
+    }
+
+    fn get_data(
+        &self
+    ) -> &TableStorage<AccessLogId, AccessLog> {
+        &self.data
+    }
+
+    fn get_data_mut(
+        &mut self
+    ) -> &mut TableStorage<AccessLogId, AccessLog> {
+        &mut self.data
+    }
+    
+    fn get_schema(
+        &self
+    ) -> &TableSchema<TableName> {
+        &self.schema
+    }
+}
+
+impl TableVersioned<TableName, AccessLogId, AccessLog> for AccessLogsTable {}
+
+impl TableDeserializable<TableName, AccessLogId, AccessLog> for AccessLogsTable {}
+
+impl TableSnapshotable<TableName, AccessLogId, AccessLog> for AccessLogsTable {}
+
+impl Crud<TableName, AccessLogId, AccessLog> for AccessLogsTable {}
\ No newline at end of file
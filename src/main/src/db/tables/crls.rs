@@ -0,0 +1,49 @@
+use crate::db::TableName;
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableStorage, Table, TableSchema, TableVersioned};
+use crate::db::traits::crud::Crud;
+use crate::models::crl::{CrlId, Crl};
+
+pub struct CrlsTable {
+    pub schema: TableSchema<TableName>,
+    pub data: TableStorage<CrlId, Crl>,
+}
+
+impl Table<TableName, CrlId, Crl> for CrlsTable {
+    fn new(
+    ) -> Self {
+        Self {
+            schema: TableSchema { 
+                version: TableName::Crls.version(),
+                name: TableName::Crls, 
+            },
+            data: TableStorage::new(get_memory(TableName::Crls.memory_id())),
+        }
+    }
+
+    fn get_data(
+        &self
+    ) -> &TableStorage<CrlId, Crl> {
+        &self.data
+    }
+
+    fn get_data_mut(
+        &mut self
+    ) -> &mut TableStorage<CrlId, Crl> {
+        &mut self.data
+    }
+    
+    fn get_schema(
+        &self
+    ) -> &TableSchema<TableName> {
+        &self.schema
+    }
+}
+
+impl TableVersioned<TableName, CrlId, Crl> for CrlsTable {}
+
+impl TableDeserializable<TableName, CrlId, Crl> for CrlsTable {}
+
+impl TableSnapshotable<TableName, CrlId, Crl> for CrlsTable {}
+
+impl Crud<TableName, CrlId, Crl> for CrlsTable {}
\ No newline at end of file
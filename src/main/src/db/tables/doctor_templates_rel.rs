@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+use crate::db::TableName;
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableEventKind, TableEventKey, TableSubscriber, TableStorage, Table, TableSchema, TableVersioned, TableEvent};
+use crate::models::error::Error;
+use crate::db::traits::crud::Crud;
+use crate::models::{user::UserId, prescription_template::PrescriptionTemplateId};
+
+pub struct DoctorTemplatesRelTable {
+    pub schema: TableSchema<TableName>,
+    pub data: TableStorage<UserId, BTreeSet<PrescriptionTemplateId>>,
+}
+
+impl Table<TableName, UserId, BTreeSet<PrescriptionTemplateId>> for DoctorTemplatesRelTable {
+    fn new(
+    ) -> Self {
+        Self {
+            schema: TableSchema {
+                version: TableName::DoctorTemplatesRel.version(),
+                name: TableName::DoctorTemplatesRel,
+            },
+            data: TableStorage::new(get_memory(TableName::DoctorTemplatesRel.memory_id())),
+        }
+    }
+
+    fn get_data(
+        &self
+    ) -> &TableStorage<UserId, BTreeSet<PrescriptionTemplateId>> {
+        &self.data
+    }
+
+    fn get_data_mut(
+        &mut self
+    ) -> &mut TableStorage<UserId, BTreeSet<PrescriptionTemplateId>> {
+        &mut self.data
+    }
+
+    fn get_schema(
+        &self
+    ) -> &TableSchema<TableName> {
+        &self.schema
+    }
+}
+
+impl TableVersioned<TableName, UserId, BTreeSet<PrescriptionTemplateId>> for DoctorTemplatesRelTable {}
+
+impl TableDeserializable<TableName, UserId, BTreeSet<PrescriptionTemplateId>> for DoctorTemplatesRelTable {}
+
+impl TableSnapshotable<TableName, UserId, BTreeSet<PrescriptionTemplateId>> for DoctorTemplatesRelTable {}
+
+impl Crud<TableName, UserId, BTreeSet<PrescriptionTemplateId>> for DoctorTemplatesRelTable {}
+
+impl TableSubscriber<TableName> for DoctorTemplatesRelTable {
+    fn on(
+        &mut self,
+        event: &TableEvent<TableName>
+    ) -> Result<(), Error> {
+        match event.table_name {
+            TableName::PrescriptionTemplates => {
+                if let (
+                        TableEventKey::Text(template_key),
+                        TableEventKey::Principal(doctor_key)
+                    ) = (event.pkey.clone(), event.keys[0].clone()) {
+                    match event.kind {
+                        TableEventKind::Create => {
+                            let mut ids = self.data.get(&doctor_key).unwrap_or_default();
+                            ids.insert(template_key.clone());
+                            self.data.insert(doctor_key.clone(), ids);
+                        },
+                        TableEventKind::Update => {
+                            // assuming doctor_key won't be updated
+                        },
+                        TableEventKind::Delete => {
+                            let mut ids = match self.data.get(&doctor_key) {
+                                None => return Err(Error::not_found(&self.schema.name.to_string(), &doctor_key)),
+                                Some(ids) => ids,
+                            };
+                            ids.remove(&template_key);
+                            self.data.insert(doctor_key.clone(), ids);
+                        },
+                    }
+                }
+            },
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
+        }
+
+        Ok(())
+    }
+}
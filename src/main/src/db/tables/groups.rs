@@ -1,12 +1,12 @@
-use std::collections::BTreeMap;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableSubscribable, TableDeserializable, TableEventKey, TableData, TableSubs, Table, TableSchema, TableVersioned};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableSubscribable, TableDeserializable, TableSnapshotable, TableEventKey, TableStorage, TableSubs, Table, TableSchema, TableVersioned};
 use crate::db::traits::crud::{CrudSubscribable, Crud};
 use crate::models::group::{GroupId, Group};
 
 pub struct GroupsTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<GroupId, Group>,
+    pub data: TableStorage<GroupId, Group>,
     pub subs: TableSubs<TableName>,
 }
 
@@ -15,32 +15,25 @@ impl Table<TableName, GroupId, Group> for GroupsTable {
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::Groups.version(),
                 name: TableName::Groups, 
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::Groups.memory_id())),
             subs: TableSubs(Vec::new()),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<GroupId, Group> {
+    ) -> &TableStorage<GroupId, Group> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<GroupId, Group> {
+    ) -> &mut TableStorage<GroupId, Group> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<GroupId, Group>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -49,12 +42,12 @@ impl Table<TableName, GroupId, Group> for GroupsTable {
     }
 }
 
-impl TableSerializable<TableName, GroupId, Group> for GroupsTable {}
-
 impl TableVersioned<TableName, GroupId, Group> for GroupsTable {}
 
 impl TableDeserializable<TableName, GroupId, Group> for GroupsTable {}
 
+impl TableSnapshotable<TableName, GroupId, Group> for GroupsTable {}
+
 impl Crud<TableName, GroupId, Group> for GroupsTable {}
 
 impl CrudSubscribable<TableName, GroupId, Group> for GroupsTable {}
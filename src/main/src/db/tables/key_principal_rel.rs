@@ -1,13 +1,14 @@
-use std::collections::BTreeMap;
 use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableEventKind, TableEventKey, TableSubscriber, TableData, Table, TableSchema, TableVersioned, TableEvent};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableEventKind, TableEventKey, TableSubscriber, TableStorage, Table, TableSchema, TableVersioned, TableEvent};
+use crate::models::error::Error;
 use crate::db::traits::crud::Crud;
 use crate::models::key::KeyId;
 
 pub struct KeyPrincipalRelTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<KeyId, Principal>,
+    pub data: TableStorage<KeyId, Principal>,
 }
     
 impl Table<TableName, KeyId, Principal> for KeyPrincipalRelTable {
@@ -15,32 +16,25 @@ impl Table<TableName, KeyId, Principal> for KeyPrincipalRelTable {
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::KeyPrincipalRel.version(),
                 name: TableName::KeyPrincipalRel,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::KeyPrincipalRel.memory_id())),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<KeyId, Principal> {
+    ) -> &TableStorage<KeyId, Principal> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<KeyId, Principal> {
+    ) -> &mut TableStorage<KeyId, Principal> {
         &mut self.data
     }
 
-    fn set_data(
-        &mut self,
-        data: TableData<KeyId, Principal>
-    ) {
-        self.data = data;
-    }
-
     fn get_schema(
         &self
     ) -> &TableSchema<TableName> {
@@ -48,19 +42,19 @@ impl Table<TableName, KeyId, Principal> for KeyPrincipalRelTable {
     }
 }
 
-impl TableSerializable<TableName, KeyId, Principal> for KeyPrincipalRelTable {}
-
 impl TableVersioned<TableName, KeyId, Principal> for KeyPrincipalRelTable {}
 
 impl TableDeserializable<TableName, KeyId, Principal> for KeyPrincipalRelTable {}
 
+impl TableSnapshotable<TableName, KeyId, Principal> for KeyPrincipalRelTable {}
+
 impl Crud<TableName, KeyId, Principal> for KeyPrincipalRelTable {}
 
 impl TableSubscriber<TableName> for KeyPrincipalRelTable {
     fn on(
         &mut self,
         event: &TableEvent<TableName>
-    ) {
+    ) -> Result<(), Error> {
         match event.table_name {
             TableName::Keys => {
                 if let (
@@ -69,19 +63,21 @@ impl TableSubscriber<TableName> for KeyPrincipalRelTable {
                     ) = (event.pkey.clone(), event.keys[0].clone()) {
                     match event.kind {
                         TableEventKind::Create => {
-                            self.data.0
+                            self.data
                                 .insert(key.clone(), principal.clone());
                         },
                         TableEventKind::Update => {
                             // assuming key won't be updated
                         },
                         TableEventKind::Delete => {
-                            self.data.0.remove(&key);
+                            self.data.remove(&key);
                         },
                     }
                 }
             }
-            _ => panic!("Unsupported")
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,12 +1,15 @@
-use std::collections::BTreeMap;
+use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableSubscribable, TableDeserializable, TableEventKey, TableSubs, TableData, Table, TableSchema, TableVersioned};
-use crate::db::traits::crud::{CrudSubscribable, Crud};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableSubscribable, TableDeserializable, TableSnapshotable, TableEventKey, TableSubs, TableStorage, Table, TableSchema, TableVersioned};
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, DELETED_AT_INDEX};
 use crate::models::key::{KeyId, Key};
+use crate::models::search::{SEARCH_INDEX, key_search_keys};
 
 pub struct KeysTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<KeyId, Key>,
+    pub data: TableStorage<KeyId, Key>,
     pub subs: TableSubs<TableName>,
 }
 
@@ -15,32 +18,37 @@ impl Table<TableName, KeyId, Key> for KeysTable {
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::Keys.version(),
                 name: TableName::Keys,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::Keys.memory_id()))
+                .with_index(
+                    DELETED_AT_INDEX,
+                    false,
+                    get_memory(TableName::Keys.index_memory_id(0)),
+                    |v| v.deleted_at.map(|at| vec![IndexValue::Nat(at)]).into_iter().collect()
+                )
+                .with_index(
+                    SEARCH_INDEX,
+                    false,
+                    get_memory(TableName::Keys.index_memory_id(1)),
+                    key_search_keys
+                ),
             subs: TableSubs(Vec::new()),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<KeyId, Key> {
+    ) -> &TableStorage<KeyId, Key> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<KeyId, Key> {
+    ) -> &mut TableStorage<KeyId, Key> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<KeyId, Key>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -49,24 +57,36 @@ impl Table<TableName, KeyId, Key> for KeysTable {
     }
 }
 
-impl TableSerializable<TableName, KeyId, Key> for KeysTable {}
-
 impl TableDeserializable<TableName, KeyId, Key> for KeysTable {}
 
-impl TableVersioned<TableName, KeyId, Key> for KeysTable {
-    fn migrate(
-        &self,
-        from_version: f32,
-        buf: &[u8]
-    ) -> Result<TableData<KeyId, Key>, String> {
-        crate::db::migrations::keys::migrate(from_version, buf)
+impl TableSnapshotable<TableName, KeyId, Key> for KeysTable {}
+
+impl TableVersioned<TableName, KeyId, Key> for KeysTable {}
+
+impl Crud<TableName, KeyId, Key> for KeysTable {
+    fn is_deleted(
+        v: &Key
+    ) -> bool {
+        v.deleted_at.is_some()
     }
 }
 
-impl Crud<TableName, KeyId, Key> for KeysTable {}
-
 impl CrudSubscribable<TableName, KeyId, Key> for KeysTable {}
 
+impl CrudSoftDeletable<TableName, KeyId, Key> for KeysTable {
+    fn set_deleted(
+        v: Key,
+        deleted_at: Option<u64>,
+        deleted_by: Option<Principal>
+    ) -> Key {
+        Key {
+            deleted_at,
+            deleted_by,
+            ..v
+        }
+    }
+}
+
 impl TableSubscribable<TableName, KeyId, Key> for KeysTable {
     fn get_subs(
         &self
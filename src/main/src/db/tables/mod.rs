@@ -8,4 +8,8 @@ pub mod principal_keys_rel;
 pub mod key_principal_rel;
 pub mod prescription_auths_rel;
 pub mod groups;
-pub mod principal_groups_rel;
\ No newline at end of file
+pub mod principal_groups_rel;
+pub mod doctor_templates_rel;
+pub mod trusted_certs;
+pub mod crls;
+pub mod access_logs;
\ No newline at end of file
@@ -1,15 +1,18 @@
+use candid::Principal;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
 use std::rc::Rc;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableData, Table, TableSubs, TableSubscribable, TableEventKey, TableSchema, TableVersioned, TableSubscriber, TableEvent, TableEventKind};
-use crate::db::traits::crud::{CrudSubscribable, Crud};
+use crate::db::memory::{get_memory, PRESCRIPTION_AUTHS_EXPIRES_AT_MEMORY_ID};
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableStorage, Table, TableSubs, TableSubscribable, TableEventKey, TableSchema, TableVersioned, TableSubscriber, TableEvent, TableEventKind};
+use crate::models::error::Error;
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, DELETED_AT_INDEX};
 use crate::models::prescription_auth::{PrescriptionAuthId, PrescriptionAuth, PrescriptionAuthSubject};
 use super::prescription_auths_rel::PrescriptionAuthsRelTable;
 
 pub struct PrescriptionAuthsTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<PrescriptionAuthId, PrescriptionAuth>,
+    pub data: TableStorage<PrescriptionAuthId, PrescriptionAuth>,
     pub subs: TableSubs<TableName>,
     pub aux: Option<Rc<RefCell<PrescriptionAuthsRelTable>>>,
 }
@@ -21,6 +24,15 @@ impl PrescriptionAuthsTable {
     ) {
         self.aux = Some(aux);
     }
+
+    pub fn subject_value(
+        subject: &PrescriptionAuthSubject
+    ) -> IndexValue {
+        match subject {
+            PrescriptionAuthSubject::User(user) => IndexValue::Principal(*user),
+            PrescriptionAuthSubject::Group(group) => IndexValue::Text(group.clone()),
+        }
+    }
 }
 
 impl Table<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {
@@ -28,10 +40,38 @@ impl Table<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuth
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::PrescriptionAuths.version(),
                 name: TableName::PrescriptionAuths, 
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::PrescriptionAuths.memory_id()))
+                .with_index(
+                    "to",
+                    false,
+                    get_memory(TableName::PrescriptionAuths.index_memory_id(0)),
+                    |v| vec![vec![Self::subject_value(&v.to)]]
+                )
+                // index 1 held the unused prescription_to index. This one is built when migrating to version 3
+                .with_index(
+                    "expires_at",
+                    false,
+                    get_memory(PRESCRIPTION_AUTHS_EXPIRES_AT_MEMORY_ID),
+                    |v| match (v.deleted_at, v.expires_at) {
+                        (None, Some(at)) => vec![vec![IndexValue::Nat(at)]],
+                        _ => vec![],
+                    }
+                )
+                .with_index(
+                    DELETED_AT_INDEX,
+                    false,
+                    get_memory(TableName::PrescriptionAuths.index_memory_id(2)),
+                    |v| v.deleted_at.map(|at| vec![IndexValue::Nat(at)]).into_iter().collect()
+                )
+                .with_index(
+                    "chain",
+                    false,
+                    get_memory(TableName::PrescriptionAuths.index_memory_id(3)),
+                    |v| v.chain.iter().map(|id| vec![IndexValue::Text(id.clone())]).collect()
+                ),
             subs: TableSubs(Vec::new()),
             aux: None,
         }
@@ -39,22 +79,15 @@ impl Table<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuth
 
     fn get_data(
         &self
-    ) -> &TableData<PrescriptionAuthId, PrescriptionAuth> {
+    ) -> &TableStorage<PrescriptionAuthId, PrescriptionAuth> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<PrescriptionAuthId, PrescriptionAuth> {
+    ) -> &mut TableStorage<PrescriptionAuthId, PrescriptionAuth> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<PrescriptionAuthId, PrescriptionAuth>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -63,16 +96,36 @@ impl Table<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuth
     }
 }
 
-impl TableSerializable<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {}
-
 impl TableVersioned<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {}
 
 impl TableDeserializable<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {}
 
-impl Crud<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {}
+impl TableSnapshotable<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {}
+
+impl Crud<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {
+    fn is_deleted(
+        v: &PrescriptionAuth
+    ) -> bool {
+        v.deleted_at.is_some()
+    }
+}
 
 impl CrudSubscribable<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {}
 
+impl CrudSoftDeletable<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {
+    fn set_deleted(
+        v: PrescriptionAuth,
+        deleted_at: Option<u64>,
+        deleted_by: Option<Principal>
+    ) -> PrescriptionAuth {
+        PrescriptionAuth {
+            deleted_at,
+            deleted_by,
+            ..v
+        }
+    }
+}
+
 impl TableSubscribable<TableName, PrescriptionAuthId, PrescriptionAuth> for PrescriptionAuthsTable {
     fn get_subs(
         &self
@@ -109,7 +162,7 @@ impl TableSubscriber<TableName> for PrescriptionAuthsTable {
     fn on(
         &mut self,
         event: &TableEvent<TableName>
-    ) {
+    ) -> Result<(), Error> {
         match event.table_name {
             TableName::Prescriptions => {
                 if let TableEventKey::Text(prescription_key) = event.pkey.clone() {
@@ -119,18 +172,25 @@ impl TableSubscriber<TableName> for PrescriptionAuthsTable {
                         TableEventKind::Update => {
                         },
                         TableEventKind::Delete => {
-                            let ids: Vec<_> = match self.aux.as_deref().unwrap().borrow().find_by_id(&prescription_key) {
+                            let aux = match self.aux.as_deref() {
+                                Some(aux) => aux,
+                                None => return Err(Error::internal("Prescription auths relation table not set"))
+                            };
+                            let ids: Vec<_> = match aux.borrow().find_by_id(&prescription_key) {
                                 Some(ids) => ids.iter().cloned().collect(),
-                                None => return
+                                None => return Ok(())
                             };
-                            ids.iter().cloned().for_each(|id| {
-                                _ = self.delete_and_notify(&id);
-                            })
+                            // kept for the retention period, like the prescription
+                            for id in ids {
+                                self.soft_delete_and_notify(&id, None)?;
+                            }
                         },
                     }
                 }
             },
-            _ => panic!("Unsupported")
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
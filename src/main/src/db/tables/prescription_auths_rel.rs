@@ -1,12 +1,14 @@
-use std::collections::{BTreeSet, BTreeMap};
+use std::collections::BTreeSet;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableEventKind, TableEventKey, TableSubscriber, TableData, Table, TableSchema, TableVersioned, TableEvent};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableEventKind, TableEventKey, TableSubscriber, TableStorage, Table, TableSchema, TableVersioned, TableEvent};
+use crate::models::error::Error;
 use crate::db::traits::crud::Crud;
 use crate::models::{prescription::PrescriptionId, prescription_auth::PrescriptionAuthId};
 
 pub struct PrescriptionAuthsRelTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<PrescriptionId, BTreeSet<PrescriptionAuthId>>,
+    pub data: TableStorage<PrescriptionId, BTreeSet<PrescriptionAuthId>>,
 }
     
 impl Table<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for PrescriptionAuthsRelTable {
@@ -14,31 +16,24 @@ impl Table<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for Prescrip
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::PrescriptionAuthsRel.version(),
                 name: TableName::PrescriptionAuthsRel, 
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::PrescriptionAuthsRel.memory_id())),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<PrescriptionId, BTreeSet<PrescriptionAuthId>> {
+    ) -> &TableStorage<PrescriptionId, BTreeSet<PrescriptionAuthId>> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<PrescriptionId, BTreeSet<PrescriptionAuthId>> {
+    ) -> &mut TableStorage<PrescriptionId, BTreeSet<PrescriptionAuthId>> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<PrescriptionId, BTreeSet<PrescriptionAuthId>>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -47,19 +42,19 @@ impl Table<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for Prescrip
     }
 }
 
-impl TableSerializable<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for PrescriptionAuthsRelTable {}
-
 impl TableVersioned<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for PrescriptionAuthsRelTable {}
 
 impl TableDeserializable<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for PrescriptionAuthsRelTable {}
 
+impl TableSnapshotable<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for PrescriptionAuthsRelTable {}
+
 impl Crud<TableName, PrescriptionId, BTreeSet<PrescriptionAuthId>> for PrescriptionAuthsRelTable {}
 
 impl TableSubscriber<TableName> for PrescriptionAuthsRelTable {
     fn on(
         &mut self,
         event: &TableEvent<TableName>
-    ) {
+    ) -> Result<(), Error> {
         match event.table_name {
             TableName::PrescriptionAuths => {
                 if let (
@@ -68,24 +63,27 @@ impl TableSubscriber<TableName> for PrescriptionAuthsRelTable {
                     ) = (event.pkey.clone(), event.keys[0].clone()) {
                     match event.kind {
                         TableEventKind::Create => {
-                            if !self.data.0.contains_key(&prescription_key) {
-                                self.data.0.insert(prescription_key.clone(), BTreeSet::new());
-                            }
-
-                            self.data.0.get_mut(&prescription_key).unwrap()
-                                .insert(prescription_auth_key.clone());
+                            let mut ids = self.data.get(&prescription_key).unwrap_or_default();
+                            ids.insert(prescription_auth_key.clone());
+                            self.data.insert(prescription_key.clone(), ids);
                         },
                         TableEventKind::Update => {
                             // assuming doctor_key won't be updated
                         },
                         TableEventKind::Delete => {
-                            self.data.0.get_mut(&prescription_key).unwrap()
-                                .remove(&prescription_auth_key);
+                            let mut ids = match self.data.get(&prescription_key) {
+                                None => return Err(Error::not_found(&self.schema.name.to_string(), &prescription_key)),
+                                Some(ids) => ids,
+                            };
+                            ids.remove(&prescription_auth_key);
+                            self.data.insert(prescription_key.clone(), ids);
                         },
                     }
                 }
             },
-            _ => panic!("Unsupported")
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,45 +1,48 @@
-use std::collections::BTreeMap;
+use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableData, Table, TableSchema, TableVersioned};
-use crate::db::traits::crud::Crud;
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableStorage, Table, TableSchema, TableVersioned, TableSubscribable, TableSubs, TableEventKey};
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, DELETED_AT_INDEX};
 use crate::models::prescription_template::{PrescriptionTemplateId, PrescriptionTemplate};
 
 pub struct PrescriptionTemplatesTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<PrescriptionTemplateId, PrescriptionTemplate>,
+    pub data: TableStorage<PrescriptionTemplateId, PrescriptionTemplate>,
+    pub subs: TableSubs<TableName>,
 }
 
 impl Table<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {
     fn new(
     ) -> Self {
         Self {
-            schema: TableSchema { 
-                version: 0.1,
-                name: TableName::PrescriptionTemplates, 
+            schema: TableSchema {
+                version: TableName::PrescriptionTemplates.version(),
+                name: TableName::PrescriptionTemplates,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::PrescriptionTemplates.memory_id()))
+                .with_index(
+                    DELETED_AT_INDEX,
+                    false,
+                    get_memory(TableName::PrescriptionTemplates.index_memory_id(0)),
+                    |v| v.deleted_at.map(|at| vec![IndexValue::Nat(at)]).into_iter().collect()
+                ),
+            subs: TableSubs(Vec::new()),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<PrescriptionTemplateId, PrescriptionTemplate> {
+    ) -> &TableStorage<PrescriptionTemplateId, PrescriptionTemplate> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<PrescriptionTemplateId, PrescriptionTemplate> {
+    ) -> &mut TableStorage<PrescriptionTemplateId, PrescriptionTemplate> {
         &mut self.data
     }
 
-    fn set_data(
-        &mut self,
-        data: TableData<PrescriptionTemplateId, PrescriptionTemplate>
-    ) {
-        self.data = data;
-    }
-    
     fn get_schema(
         &self
     ) -> &TableSchema<TableName> {
@@ -47,10 +50,60 @@ impl Table<TableName, PrescriptionTemplateId, PrescriptionTemplate> for Prescrip
     }
 }
 
-impl TableSerializable<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {}
-
 impl TableVersioned<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {}
 
 impl TableDeserializable<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {}
 
-impl Crud<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {}
\ No newline at end of file
+impl TableSnapshotable<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {}
+
+impl Crud<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {
+    fn is_deleted(
+        v: &PrescriptionTemplate
+    ) -> bool {
+        v.deleted_at.is_some()
+    }
+}
+
+impl CrudSubscribable<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {}
+
+impl CrudSoftDeletable<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {
+    fn set_deleted(
+        v: PrescriptionTemplate,
+        deleted_at: Option<u64>,
+        deleted_by: Option<Principal>
+    ) -> PrescriptionTemplate {
+        PrescriptionTemplate {
+            deleted_at,
+            deleted_by,
+            ..v
+        }
+    }
+}
+
+impl TableSubscribable<TableName, PrescriptionTemplateId, PrescriptionTemplate> for PrescriptionTemplatesTable {
+    fn get_subs(
+        &self
+    ) -> &TableSubs<TableName> {
+        &self.subs
+    }
+
+    fn get_subs_mut(
+        &mut self
+    ) -> &mut TableSubs<TableName> {
+        &mut self.subs
+    }
+
+    fn get_pkey(
+        k: &PrescriptionTemplateId
+    ) -> TableEventKey {
+        TableEventKey::Text(k.clone())
+    }
+
+    fn get_keys(
+        v: &PrescriptionTemplate
+    ) -> Vec<TableEventKey> {
+        vec![
+            TableEventKey::Principal(v.created_by.clone())
+        ]
+    }
+}
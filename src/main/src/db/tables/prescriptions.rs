@@ -1,12 +1,14 @@
-use std::collections::BTreeMap;
+use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableSubscribable, TableDeserializable, TableEventKey, TableData, TableSubs, Table, TableSchema, TableVersioned};
-use crate::db::traits::crud::{CrudSubscribable, Crud};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableSubscribable, TableDeserializable, TableSnapshotable, TableEventKey, TableStorage, TableSubs, Table, TableSchema, TableVersioned};
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, DELETED_AT_INDEX};
 use crate::models::prescription::{PrescriptionId, Prescription};
 
 pub struct PrescriptionsTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<PrescriptionId, Prescription>,
+    pub data: TableStorage<PrescriptionId, Prescription>,
     pub subs: TableSubs<TableName>,
 }
 
@@ -15,32 +17,43 @@ impl Table<TableName, PrescriptionId, Prescription> for PrescriptionsTable {
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::Prescriptions.version(),
                 name: TableName::Prescriptions, 
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::Prescriptions.memory_id()))
+                .with_index(
+                    "doctor",
+                    false,
+                    get_memory(TableName::Prescriptions.index_memory_id(0)),
+                    |v| vec![vec![IndexValue::Principal(v.doctor)]]
+                )
+                .with_index(
+                    "patient",
+                    false,
+                    get_memory(TableName::Prescriptions.index_memory_id(1)),
+                    |v| vec![vec![IndexValue::Principal(v.patient)]]
+                )
+                .with_index(
+                    DELETED_AT_INDEX,
+                    false,
+                    get_memory(TableName::Prescriptions.index_memory_id(2)),
+                    |v| v.deleted_at.map(|at| vec![IndexValue::Nat(at)]).into_iter().collect()
+                ),
             subs: TableSubs(Vec::new()),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<PrescriptionId, Prescription> {
+    ) -> &TableStorage<PrescriptionId, Prescription> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<PrescriptionId, Prescription> {
+    ) -> &mut TableStorage<PrescriptionId, Prescription> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<PrescriptionId, Prescription>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -49,16 +62,36 @@ impl Table<TableName, PrescriptionId, Prescription> for PrescriptionsTable {
     }
 }
 
-impl TableSerializable<TableName, PrescriptionId, Prescription> for PrescriptionsTable {}
-
 impl TableVersioned<TableName, PrescriptionId, Prescription> for PrescriptionsTable {}
 
 impl TableDeserializable<TableName, PrescriptionId, Prescription> for PrescriptionsTable {}
 
-impl Crud<TableName, PrescriptionId, Prescription> for PrescriptionsTable {}
+impl TableSnapshotable<TableName, PrescriptionId, Prescription> for PrescriptionsTable {}
+
+impl Crud<TableName, PrescriptionId, Prescription> for PrescriptionsTable {
+    fn is_deleted(
+        v: &Prescription
+    ) -> bool {
+        v.deleted_at.is_some()
+    }
+}
 
 impl CrudSubscribable<TableName, PrescriptionId, Prescription> for PrescriptionsTable {}
 
+impl CrudSoftDeletable<TableName, PrescriptionId, Prescription> for PrescriptionsTable {
+    fn set_deleted(
+        v: Prescription,
+        deleted_at: Option<u64>,
+        deleted_by: Option<Principal>
+    ) -> Prescription {
+        Prescription {
+            deleted_at,
+            deleted_by,
+            ..v
+        }
+    }
+}
+
 impl TableSubscribable<TableName, PrescriptionId, Prescription> for PrescriptionsTable {
     fn get_subs(
         &self
@@ -1,13 +1,15 @@
-use std::collections::{BTreeSet, BTreeMap};
+use std::collections::BTreeSet;
 use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableEventKind, TableEventKey, TableSubscriber, TableData, Table, TableSchema, TableVersioned, TableEvent};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableEventKind, TableEventKey, TableSubscriber, TableStorage, Table, TableSchema, TableVersioned, TableEvent};
+use crate::models::error::Error;
 use crate::db::traits::crud::Crud;
 use crate::models::group::GroupId;
 
 pub struct PrincipalGroupsRelTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<Principal, BTreeSet<GroupId>>,
+    pub data: TableStorage<Principal, BTreeSet<GroupId>>,
 }
     
 impl Table<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable {
@@ -15,31 +17,24 @@ impl Table<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::PrincipalGroupsRel.version(),
                 name: TableName::PrincipalGroupsRel,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::PrincipalGroupsRel.memory_id())),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<Principal, BTreeSet<GroupId>> {
+    ) -> &TableStorage<Principal, BTreeSet<GroupId>> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<Principal, BTreeSet<GroupId>> {
+    ) -> &mut TableStorage<Principal, BTreeSet<GroupId>> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<Principal, BTreeSet<GroupId>>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -48,57 +43,61 @@ impl Table<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable
     }
 }
 
-impl TableSerializable<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable {}
-
 impl TableVersioned<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable {}
 
 impl TableDeserializable<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable {}
 
+impl TableSnapshotable<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable {}
+
 impl Crud<TableName, Principal, BTreeSet<GroupId>> for PrincipalGroupsRelTable {}
 
 impl TableSubscriber<TableName> for PrincipalGroupsRelTable {
     fn on(
         &mut self,
         event: &TableEvent<TableName>
-    ) {
+    ) -> Result<(), Error> {
         match event.table_name {
             TableName::Groups => {
                 if let TableEventKey::Text(group_key) = event.pkey.clone() {
                     match event.kind {
                         TableEventKind::Create => {
-                            event.keys.iter().for_each(|key| {
+                            for key in event.keys.iter() {
                                 if let TableEventKey::Principal(principal) = key {
-                                    if !self.data.0.contains_key(&principal) {
-                                        self.data.0.insert(principal.clone(), BTreeSet::new());
-                                    }
-        
-                                    self.data.0.get_mut(&principal).unwrap()
-                                        .insert(group_key.clone());
+                                    let mut ids = self.data.get(&principal).unwrap_or_default();
+                                    ids.insert(group_key.clone());
+                                    self.data.insert(principal.clone(), ids);
                                 }
-                            });
+                            }
                         },
                         TableEventKind::Update => {
-                            event.keys.iter().for_each(|key| {
+                            for key in event.keys.iter() {
                                 if let TableEventKey::Principal(principal) = key {
-                                    self.data.0.clear();
+                                    self.data.clear();
         
-                                    self.data.0.get_mut(&principal).unwrap()
-                                        .insert(group_key.clone());
+                                    let mut ids = self.data.get(&principal).unwrap_or_default();
+                                    ids.insert(group_key.clone());
+                                    self.data.insert(principal.clone(), ids);
                                 }
-                            });
+                            }
                         },
                         TableEventKind::Delete => {
-                            event.keys.iter().for_each(|key| {
+                            for key in event.keys.iter() {
                                 if let TableEventKey::Principal(principal) = key {
-                                    self.data.0.get_mut(&principal).unwrap()
-                                        .remove(&group_key);
+                                    let mut ids = match self.data.get(&principal) {
+                                        None => return Err(Error::not_found(&self.schema.name.to_string(), &principal)),
+                                        Some(ids) => ids,
+                                    };
+                                    ids.remove(&group_key);
+                                    self.data.insert(principal.clone(), ids);
                                 }
-                            });
+                            }
                         },
                     }
                 }
             },
-            _ => panic!("Unsupported")
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,13 +1,15 @@
-use std::collections::{BTreeSet, BTreeMap};
+use std::collections::BTreeSet;
 use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableEventKind, TableEventKey, TableSubscriber, TableData, Table, TableSchema, TableVersioned, TableEvent};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableEventKind, TableEventKey, TableSubscriber, TableStorage, Table, TableSchema, TableVersioned, TableEvent};
+use crate::models::error::Error;
 use crate::db::traits::crud::Crud;
 use crate::models::key::KeyId;
 
 pub struct PrincipalKeysRelTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<Principal, BTreeSet<KeyId>>,
+    pub data: TableStorage<Principal, BTreeSet<KeyId>>,
 }
     
 impl Table<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {
@@ -15,31 +17,24 @@ impl Table<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::PrincipalKeysRel.version(),
                 name: TableName::PrincipalKeysRel,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::PrincipalKeysRel.memory_id())),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<Principal, BTreeSet<KeyId>> {
+    ) -> &TableStorage<Principal, BTreeSet<KeyId>> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<Principal, BTreeSet<KeyId>> {
+    ) -> &mut TableStorage<Principal, BTreeSet<KeyId>> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<Principal, BTreeSet<KeyId>>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -48,19 +43,19 @@ impl Table<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {
     }
 }
 
-impl TableSerializable<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {}
-
 impl TableVersioned<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {}
 
 impl TableDeserializable<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {}
 
+impl TableSnapshotable<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {}
+
 impl Crud<TableName, Principal, BTreeSet<KeyId>> for PrincipalKeysRelTable {}
 
 impl TableSubscriber<TableName> for PrincipalKeysRelTable {
     fn on(
         &mut self,
         event: &TableEvent<TableName>
-    ) {
+    ) -> Result<(), Error> {
         match event.table_name {
             TableName::Keys => {
                 if let (
@@ -69,24 +64,27 @@ impl TableSubscriber<TableName> for PrincipalKeysRelTable {
                     ) = (event.pkey.clone(), event.keys[0].clone()) {
                     match event.kind {
                         TableEventKind::Create => {
-                            if !self.data.0.contains_key(&principal) {
-                                self.data.0.insert(principal.clone(), BTreeSet::new());
-                            }
-
-                            self.data.0.get_mut(&principal).unwrap()
-                                .insert(key.clone());
+                            let mut ids = self.data.get(&principal).unwrap_or_default();
+                            ids.insert(key.clone());
+                            self.data.insert(principal.clone(), ids);
                         },
                         TableEventKind::Update => {
                             // assuming principal won't be updated
                         },
                         TableEventKind::Delete => {
-                            self.data.0.get_mut(&principal).unwrap()
-                                .remove(&key);
+                            let mut ids = match self.data.get(&principal) {
+                                None => return Err(Error::not_found(&self.schema.name.to_string(), &principal)),
+                                Some(ids) => ids,
+                            };
+                            ids.remove(&key);
+                            self.data.insert(principal.clone(), ids);
                         },
                     }
                 }
             },
-            _ => panic!("Unsupported")
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
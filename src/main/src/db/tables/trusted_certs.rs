@@ -0,0 +1,49 @@
+use crate::db::TableName;
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableStorage, Table, TableSchema, TableVersioned};
+use crate::db::traits::crud::Crud;
+use crate::models::trusted_cert::{TrustedCertId, TrustedCert};
+
+pub struct TrustedCertsTable {
+    pub schema: TableSchema<TableName>,
+    pub data: TableStorage<TrustedCertId, TrustedCert>,
+}
+
+impl Table<TableName, TrustedCertId, TrustedCert> for TrustedCertsTable {
+    fn new(
+    ) -> Self {
+        Self {
+            schema: TableSchema { 
+                version: TableName::TrustedCerts.version(),
+                name: TableName::TrustedCerts, 
+            },
+            data: TableStorage::new(get_memory(TableName::TrustedCerts.memory_id())),
+        }
+    }
+
+    fn get_data(
+        &self
+    ) -> &TableStorage<TrustedCertId, TrustedCert> {
+        &self.data
+    }
+
+    fn get_data_mut(
+        &mut self
+    ) -> &mut TableStorage<TrustedCertId, TrustedCert> {
+        &mut self.data
+    }
+    
+    fn get_schema(
+        &self
+    ) -> &TableSchema<TableName> {
+        &self.schema
+    }
+}
+
+impl TableVersioned<TableName, TrustedCertId, TrustedCert> for TrustedCertsTable {}
+
+impl TableDeserializable<TableName, TrustedCertId, TrustedCert> for TrustedCertsTable {}
+
+impl TableSnapshotable<TableName, TrustedCertId, TrustedCert> for TrustedCertsTable {}
+
+impl Crud<TableName, TrustedCertId, TrustedCert> for TrustedCertsTable {}
\ No newline at end of file
@@ -1,11 +1,13 @@
-use std::collections::{BTreeSet, BTreeMap};
+use std::collections::BTreeSet;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableEventKind, TableEventKey, TableSubscriber, TableData, Table, TableSchema, TableVersioned, TableEvent};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableEventKind, TableEventKey, TableSubscriber, TableStorage, Table, TableSchema, TableVersioned, TableEvent};
+use crate::models::error::Error;
 use crate::db::traits::crud::Crud;
 use crate::models::{user::UserId, prescription::PrescriptionId};
 
 pub struct UserPrescriptionsRelTable {
-    pub data: TableData<UserId, BTreeSet<PrescriptionId>>,
+    pub data: TableStorage<UserId, BTreeSet<PrescriptionId>>,
     pub schema: TableSchema<TableName>,
 }
     
@@ -14,32 +16,25 @@ impl Table<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRel
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::UserPrescriptionsRel.version(),
                 name: TableName::UserPrescriptionsRel,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::UserPrescriptionsRel.memory_id())),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<UserId, BTreeSet<PrescriptionId>> {
+    ) -> &TableStorage<UserId, BTreeSet<PrescriptionId>> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<UserId, BTreeSet<PrescriptionId>> {
+    ) -> &mut TableStorage<UserId, BTreeSet<PrescriptionId>> {
         &mut self.data
     }
 
-    fn set_data(
-        &mut self,
-        data: TableData<UserId, BTreeSet<PrescriptionId>>
-    ) {
-        self.data = data;
-    }
-
     fn get_schema(
         &self
     ) -> &TableSchema<TableName> {
@@ -47,19 +42,19 @@ impl Table<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRel
     }
 }
 
-impl TableSerializable<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRelTable {}
-
 impl TableVersioned<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRelTable {}
 
 impl TableDeserializable<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRelTable {}
 
+impl TableSnapshotable<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRelTable {}
+
 impl Crud<TableName, UserId, BTreeSet<PrescriptionId>> for UserPrescriptionsRelTable {}
 
 impl TableSubscriber<TableName> for UserPrescriptionsRelTable {
     fn on(
         &mut self,
         event: &TableEvent<TableName>
-    ) {
+    ) -> Result<(), Error> {
         match event.table_name {
             TableName::Prescriptions => {
                 if let (
@@ -70,29 +65,31 @@ impl TableSubscriber<TableName> for UserPrescriptionsRelTable {
                     match event.kind {
                         TableEventKind::Create => {
                             // doctor
-                            if !self.data.0.contains_key(&doctor_key) {
-                                self.data.0.insert(doctor_key.clone(), BTreeSet::new());
-                            }
-
-                            self.data.0.get_mut(&doctor_key).unwrap()
-                                .insert(prescription_key.clone());
+                            let mut ids = self.data.get(&doctor_key).unwrap_or_default();
+                            ids.insert(prescription_key.clone());
+                            self.data.insert(doctor_key.clone(), ids);
 
                             // patient
-                            if !self.data.0.contains_key(&patient_key) {
-                                self.data.0.insert(patient_key.clone(), BTreeSet::new());
-                            }
-
-                            self.data.0.get_mut(&patient_key).unwrap()
-                                .insert(prescription_key.clone());
+                            let mut ids = self.data.get(&patient_key).unwrap_or_default();
+                            ids.insert(prescription_key.clone());
+                            self.data.insert(patient_key.clone(), ids);
                         },
                         TableEventKind::Update => {
                             // assuming user_key won't be updated
                         },
                         TableEventKind::Delete => {
-                            self.data.0.get_mut(&doctor_key).unwrap()
-                                .remove(&prescription_key);
-                            self.data.0.get_mut(&patient_key).unwrap()
-                                .remove(&prescription_key);
+                            let mut ids = match self.data.get(&doctor_key) {
+                                None => return Err(Error::not_found(&self.schema.name.to_string(), &doctor_key)),
+                                Some(ids) => ids,
+                            };
+                            ids.remove(&prescription_key);
+                            self.data.insert(doctor_key.clone(), ids);
+                            let mut ids = match self.data.get(&patient_key) {
+                                None => return Err(Error::not_found(&self.schema.name.to_string(), &patient_key)),
+                                Some(ids) => ids,
+                            };
+                            ids.remove(&prescription_key);
+                            self.data.insert(patient_key.clone(), ids);
                         },
                     }
                 }
@@ -105,24 +102,27 @@ impl TableSubscriber<TableName> for UserPrescriptionsRelTable {
                     match event.kind {
                         TableEventKind::Create => {
                             // to
-                            if !self.data.0.contains_key(&to_key) {
-                                self.data.0.insert(to_key.clone(), BTreeSet::new());
-                            }
-
-                            self.data.0.get_mut(&to_key).unwrap()
-                                .insert(prescription_key.clone());
+                            let mut ids = self.data.get(&to_key).unwrap_or_default();
+                            ids.insert(prescription_key.clone());
+                            self.data.insert(to_key.clone(), ids);
                         },
                         TableEventKind::Update => {
                             // assuming user_key won't be updated
                         },
                         TableEventKind::Delete => {
-                            self.data.0.get_mut(&to_key).unwrap()
-                                .remove(&prescription_key);
+                            let mut ids = match self.data.get(&to_key) {
+                                None => return Err(Error::not_found(&self.schema.name.to_string(), &to_key)),
+                                Some(ids) => ids,
+                            };
+                            ids.remove(&prescription_key);
+                            self.data.insert(to_key.clone(), ids);
                         },
                     }
                 }
             },
-            _ => panic!("Unsupported")
+            _ => return Err(Error::internal(&format!("Unsupported event from table {}", event.table_name)))
         }
+
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,12 +1,15 @@
-use std::collections::BTreeMap;
+use candid::Principal;
 use crate::db::TableName;
-use crate::db::traits::table::{TableSerializable, TableDeserializable, TableData, Table, TableSchema, TableVersioned, TableSubscribable, TableSubs, TableEventKey};
-use crate::db::traits::crud::{CrudSubscribable, Crud};
+use crate::db::memory::get_memory;
+use crate::db::traits::table::{TableDeserializable, TableSnapshotable, TableStorage, Table, TableSchema, TableVersioned, TableSubscribable, TableSubs, TableEventKey};
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, DELETED_AT_INDEX};
 use crate::models::user::{UserId, User};
+use crate::models::search::{SEARCH_INDEX, user_search_keys};
 
 pub struct UsersTable {
     pub schema: TableSchema<TableName>,
-    pub data: TableData<UserId, User>,
+    pub data: TableStorage<UserId, User>,
     pub subs: TableSubs<TableName>,
 }
 
@@ -15,32 +18,37 @@ impl Table<TableName, UserId, User> for UsersTable {
     ) -> Self {
         Self {
             schema: TableSchema { 
-                version: 0.1,
+                version: TableName::Users.version(),
                 name: TableName::Users,
             },
-            data: TableData(BTreeMap::new()),
+            data: TableStorage::new(get_memory(TableName::Users.memory_id()))
+                .with_index(
+                    DELETED_AT_INDEX,
+                    false,
+                    get_memory(TableName::Users.index_memory_id(0)),
+                    |v| v.deleted_at.map(|at| vec![IndexValue::Nat(at)]).into_iter().collect()
+                )
+                .with_index(
+                    SEARCH_INDEX,
+                    false,
+                    get_memory(TableName::Users.index_memory_id(1)),
+                    user_search_keys
+                ),
             subs: TableSubs(Vec::new()),
         }
     }
 
     fn get_data(
         &self
-    ) -> &TableData<UserId, User> {
+    ) -> &TableStorage<UserId, User> {
         &self.data
     }
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<UserId, User> {
+    ) -> &mut TableStorage<UserId, User> {
         &mut self.data
     }
-
-    fn set_data(
-        &mut self,
-        data: TableData<UserId, User>
-    ) {
-        self.data = data;
-    }
     
     fn get_schema(
         &self
@@ -49,16 +57,36 @@ impl Table<TableName, UserId, User> for UsersTable {
     }
 }
 
-impl TableSerializable<TableName, UserId, User> for UsersTable {}
-
 impl TableVersioned<TableName, UserId, User> for UsersTable {}
 
 impl TableDeserializable<TableName, UserId, User> for UsersTable {}
 
-impl Crud<TableName, UserId, User> for UsersTable {}
+impl TableSnapshotable<TableName, UserId, User> for UsersTable {}
+
+impl Crud<TableName, UserId, User> for UsersTable {
+    fn is_deleted(
+        v: &User
+    ) -> bool {
+        v.deleted_at.is_some()
+    }
+}
 
 impl CrudSubscribable<TableName, UserId, User> for UsersTable {}
 
+impl CrudSoftDeletable<TableName, UserId, User> for UsersTable {
+    fn set_deleted(
+        v: User,
+        deleted_at: Option<u64>,
+        deleted_by: Option<Principal>
+    ) -> User {
+        User {
+            deleted_at,
+            deleted_by,
+            ..v
+        }
+    }
+}
+
 impl TableSubscribable<TableName, UserId, User> for UsersTable {
     fn get_subs(
         &self
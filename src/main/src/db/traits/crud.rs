@@ -1,58 +1,228 @@
-use candid::CandidType;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::ops::Bound;
+use candid::{CandidType, Principal};
 use serde::Deserialize;
+use crate::models::error::Error;
+use super::index::{IndexKey, IndexValue};
 use super::table::{TableSubscribable, TableEventKind, Table, TableEvent};
 
+pub const MAX_PAGE_SIZE: u32 = 100;
+// index that soft deletable tables must declare, with the deleted_at of the rows deleted
+pub const DELETED_AT_INDEX: &str = "deleted_at";
+
+#[derive(CandidType, Deserialize, Clone, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PaginationFilter {
+    pub created_from: Option<u64>,
+    pub created_to: Option<u64>,
+}
 
 #[derive(CandidType, Deserialize)]
 pub struct Pagination {
-    pub offset: u32,
+    // next_cursor of the previous page, or None to start from the first one
+    pub cursor: Option<String>,
     pub limit: u32,
+    pub order: SortOrder,
+    pub filter: Option<PaginationFilter>,
+}
+
+#[derive(CandidType)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    // number of ids paged through, in all pages. It's an upper bound of the items, as the rows filtered
+    // out are counted too
+    pub estimated_total: u64,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(
+        self,
+        f: impl FnMut(T) -> U
+    ) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+            estimated_total: self.estimated_total,
+        }
+    }
+}
+
+impl Pagination {
+    pub fn accepts(
+        &self,
+        created_at: u64
+    ) -> bool {
+        match &self.filter {
+            None => true,
+            Some(filter) => 
+                filter.created_from.map_or(true, |from| created_at >= from) &&
+                filter.created_to.map_or(true, |to| created_at <= to),
+        }
+    }
+
+    // pages through the ids in key order, starting after the cursor. Only the rows up to the end of
+    // the page are loaded. load() must return None for the rows to be skipped
+    pub fn paginate<K, V>(
+        &self,
+        ids: &BTreeSet<K>,
+        load: impl Fn(&K) -> Option<V>
+    ) -> Result<Page<V>, Error>
+        where
+            K: Ord + CandidType + for<'a> Deserialize<'a> {
+        let cursor = match &self.cursor {
+            None => None,
+            Some(cursor) => Some(Self::decode_cursor::<K>(cursor)
+                .ok_or(Error::validation("cursor", "Invalid cursor"))?),
+        };
+
+        let iter: Box<dyn Iterator<Item = &K>> = match (&self.order, &cursor) {
+            (SortOrder::Asc, None) => 
+                Box::new(ids.iter()),
+            (SortOrder::Asc, Some(k)) => 
+                Box::new(ids.range((Bound::Excluded(k), Bound::Unbounded))),
+            (SortOrder::Desc, None) => 
+                Box::new(ids.iter().rev()),
+            (SortOrder::Desc, Some(k)) => 
+                Box::new(ids.range((Bound::Unbounded, Bound::Excluded(k))).rev()),
+        };
+
+        let limit = self.limit.min(MAX_PAGE_SIZE) as usize;
+        let mut items = vec![];
+        let mut last = None;
+        let mut more = false;
+        for k in iter {
+            if let Some(v) = load(k) {
+                if items.len() == limit {
+                    more = true;
+                    break;
+                }
+                items.push(v);
+                last = Some(k);
+            }
+        }
+
+        let next_cursor = match (more, last) {
+            (true, Some(k)) => Some(Self::encode_cursor(k)?),
+            _ => None,
+        };
+
+        Ok(Page {
+            items,
+            next_cursor,
+            estimated_total: ids.len() as u64,
+        })
+    }
+
+    fn encode_cursor<K>(
+        k: &K
+    ) -> Result<String, Error>
+        where
+            K: CandidType {
+        candid::encode_one(k)
+            .map(hex::encode)
+            .map_err(|e| Error::internal(&format!("{:?}", e)))
+    }
+
+    fn decode_cursor<K>(
+        cursor: &str
+    ) -> Option<K>
+        where
+            K: CandidType + for<'a> Deserialize<'a> {
+        let buf = hex::decode(cursor).ok()?;
+        candid::decode_one::<K>(&buf).ok()
+    }
 }
 
 pub trait Crud<TN, K, V> 
     where 
-        K: Ord + CandidType, 
-        V: CandidType, 
+        TN: Display,
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> + Display, 
+        V: CandidType + for<'a> Deserialize<'a>, 
         Self: Table<TN, K, V> {
     
     fn insert(
         &mut self,
         k: K,
         v: V
-    ) -> Result<(), String> {
-        if self.get_data().0.contains_key(&k) {
-            Err("Duplicated key".to_string())
+    ) -> Result<(), Error> {
+        if self.get_data().contains_key(&k) {
+            Err(Error::conflict(&self.get_schema().name.to_string(), &k))
         }
         else {
-            self.get_data_mut().0.insert(k, v);
+            self.check_unique(&k, &v)?;
+            self.get_data_mut().insert(k, v);
             Ok(())
         }
     }
 
-    fn find_by_id<'a>(
-        &'a self,
-        k: &'a K
-    ) -> Option<&'a V> {
-       self.get_data().0.get(k)
+    // soft deleted rows are kept in the table, but must be ignored by the queries
+    fn is_deleted(
+        _v: &V
+    ) -> bool {
+        false
     }
 
-    fn get<'a>(
-        &'a self,
-        k: &'a K
-    ) -> &'a V {
-        self.get_data().0.get(k).unwrap()
+    fn find_by_id(
+        &self,
+        k: &K
+    ) -> Option<V> {
+        self.get_data().get(k)
+            .filter(|v| !Self::is_deleted(v))
+    }
+
+    fn get(
+        &self,
+        k: &K
+    ) -> V {
+        self.find_by_id(k).unwrap()
+    }
+
+    fn find_by_index(
+        &self,
+        index: &str,
+        key: &IndexKey
+    ) -> Vec<V> {
+        self.get_data().find_ids(index, key).iter()
+            .filter_map(|k| self.find_by_id(k))
+            .collect()
+    }
+
+    fn find_one_by_index(
+        &self,
+        index: &str,
+        key: &IndexKey
+    ) -> Option<V> {
+        self.get_data().find_ids(index, key).iter()
+            .find_map(|k| self.find_by_id(k))
+    }
+
+    fn check_unique(
+        &self,
+        k: &K,
+        v: &V
+    ) -> Result<(), Error> {
+        self.get_data().check_unique(k, v)
+            .map_err(|index| Error::conflict(&format!("{}.{}", self.get_schema().name, index), k))
     }
 
     fn update(
         &mut self,
         k: K,
         v: V
-    ) -> Result<(), String> {
-        if !self.get_data().0.contains_key(&k) {
-            Err("Not found".to_string())
+    ) -> Result<(), Error> {
+        if self.find_by_id(&k).is_none() {
+            Err(Error::not_found(&self.get_schema().name.to_string(), &k))
         }
         else {
-            self.get_data_mut().0.insert(k, v);
+            self.check_unique(&k, &v)?;
+            self.get_data_mut().insert(k, v);
             Ok(())
         }
     }
@@ -60,9 +230,9 @@ pub trait Crud<TN, K, V>
     fn delete(
         &mut self,
         k: &K
-    ) -> Result<(), String> {
-        match self.get_data_mut().0.remove(k) {
-            None => Err("Not found".to_string()),
+    ) -> Result<(), Error> {
+        match self.get_data_mut().remove(k) {
+            None => Err(Error::not_found(&self.get_schema().name.to_string(), k)),
             _ => Ok(())
         }
     }
@@ -70,25 +240,27 @@ pub trait Crud<TN, K, V>
 
 pub trait CrudSubscribable<TN, K, V> 
     where 
-        K: Ord + CandidType, 
-        V: CandidType, 
+        TN: Display,
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> + Display, 
+        V: CandidType + for<'a> Deserialize<'a>, 
         Self: Table<TN, K, V> + Crud<TN, K, V> + TableSubscribable<TN, K, V> {
     fn insert_and_notify(
         &mut self,
         k: K,
         v: V
-    ) -> Result<(), String> {
-        if self.get_data().0.contains_key(&k) {
-            Err("Duplicated key".to_string())
+    ) -> Result<(), Error> {
+        if self.get_data().contains_key(&k) {
+            Err(Error::conflict(&self.get_schema().name.to_string(), &k))
         }
         else {
+            self.check_unique(&k, &v)?;
             self.notify(&TableEvent {
                 table_name: &self.get_schema().name,
                 kind: TableEventKind::Create, 
                 pkey: Self::get_pkey(&k),
                 keys: Self::get_keys(&v)
-            });
-            self.get_data_mut().0.insert(k, v);
+            })?;
+            self.get_data_mut().insert(k, v);
             Ok(())
         }
     }
@@ -97,18 +269,19 @@ pub trait CrudSubscribable<TN, K, V>
         &mut self,
         k: K,
         v: V
-    ) -> Result<(), String> {
-        if !self.get_data().0.contains_key(&k) {
-            Err("Not found".to_string())
+    ) -> Result<(), Error> {
+        if self.find_by_id(&k).is_none() {
+            Err(Error::not_found(&self.get_schema().name.to_string(), &k))
         }
         else {
+            self.check_unique(&k, &v)?;
             self.notify(&TableEvent {
                 table_name: &self.get_schema().name,
                 kind: TableEventKind::Update, 
                 pkey: Self::get_pkey(&k),
                 keys: Self::get_keys(&v)
-            });
-            self.get_data_mut().0.insert(k, v);
+            })?;
+            self.get_data_mut().insert(k, v);
             Ok(())
         }
     }
@@ -116,20 +289,209 @@ pub trait CrudSubscribable<TN, K, V>
     fn delete_and_notify(
         &mut self,
         k: &K
-    ) -> Result<(), String> {
-        let v = self.get_data_mut().0.remove(k);
+    ) -> Result<(), Error> {
+        let v = self.get_data_mut().remove(k);
         if let Some(v) = v {
             self.notify(&TableEvent {
                 table_name: &self.get_schema().name,
                 kind: TableEventKind::Delete, 
                 pkey: Self::get_pkey(k),
                 keys: Self::get_keys(&v)
-            });
+            })?;
             Ok(())
         }
         else {
-            Err("Not found".to_string())
+            Err(Error::not_found(&self.get_schema().name.to_string(), k))
+        }
+    }
+}
+
+pub trait CrudSoftDeletable<TN, K, V> 
+    where 
+        TN: Display,
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> + Display, 
+        V: CandidType + for<'a> Deserialize<'a>, 
+        Self: Table<TN, K, V> + Crud<TN, K, V> + CrudSubscribable<TN, K, V> + TableSubscribable<TN, K, V> {
+    fn set_deleted(
+        v: V,
+        deleted_at: Option<u64>,
+        deleted_by: Option<Principal>
+    ) -> V;
+
+    // the row is kept until purged, but the subscribers are notified as if it had been removed
+    fn soft_delete_and_notify(
+        &mut self,
+        k: &K,
+        deleted_by: Option<Principal>
+    ) -> Result<(), Error> {
+        let v = match self.find_by_id(k) {
+            None => return Err(Error::not_found(&self.get_schema().name.to_string(), k)),
+            Some(v) => v
+        };
+        
+        self.notify(&TableEvent {
+            table_name: &self.get_schema().name,
+            kind: TableEventKind::Delete, 
+            pkey: Self::get_pkey(k),
+            keys: Self::get_keys(&v)
+        })?;
+        let v = Self::set_deleted(v, Some(ic_cdk::api::time()), deleted_by);
+        self.get_data_mut().insert(k.clone(), v);
+        Ok(())
+    }
+
+    fn find_deleted(
+        &self,
+        k: &K
+    ) -> Option<V> {
+        self.get_data().get(k)
+            .filter(|v| Self::is_deleted(v))
+    }
+
+    // the subscribers are notified as if the row had been created again
+    fn restore_and_notify(
+        &mut self,
+        k: &K
+    ) -> Result<(), Error> {
+        let v = match self.find_deleted(k) {
+            None => return Err(Error::not_found(&self.get_schema().name.to_string(), k)),
+            Some(v) => Self::set_deleted(v, None, None)
+        };
+
+        self.notify(&TableEvent {
+            table_name: &self.get_schema().name,
+            kind: TableEventKind::Create, 
+            pkey: Self::get_pkey(k),
+            keys: Self::get_keys(&v)
+        })?;
+        self.get_data_mut().insert(k.clone(), v);
+        Ok(())
+    }
+
+    // physically removes up to limit rows deleted before the given time. Returns the number of rows removed
+    fn purge(
+        &mut self,
+        deleted_before: u64,
+        limit: usize
+    ) -> usize {
+        let ids = self.get_data().find_ids_in_range(
+            DELETED_AT_INDEX, 
+            &vec![IndexValue::Nat(0)], 
+            &vec![IndexValue::Nat(deleted_before)], 
+            limit
+        );
+
+        // the subscribers were notified when the rows were soft deleted
+        for k in &ids {
+            self.get_data_mut().remove(k);
         }
+
+        ids.len()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::*;
+
+    fn pagination(
+        cursor: Option<String>,
+        limit: u32,
+        order: SortOrder
+    ) -> Pagination {
+        Pagination {
+            cursor,
+            limit,
+            order,
+            filter: None,
+        }
+    }
+
+    fn ids(
+    ) -> BTreeSet<u32> {
+        (1..=5).collect()
+    }
+
+    #[test]
+    fn paginate_follows_the_cursor() {
+        let first = pagination(None, 2, SortOrder::Asc)
+            .paginate(&ids(), |k| Some(*k))
+            .unwrap();
+        assert_eq!(first.items, vec![1, 2]);
+        assert_eq!(first.estimated_total, 5);
+
+        let second = pagination(first.next_cursor, 2, SortOrder::Asc)
+            .paginate(&ids(), |k| Some(*k))
+            .unwrap();
+        assert_eq!(second.items, vec![3, 4]);
+
+        let last = pagination(second.next_cursor, 2, SortOrder::Asc)
+            .paginate(&ids(), |k| Some(*k))
+            .unwrap();
+        assert_eq!(last.items, vec![5]);
+        assert!(last.next_cursor.is_none());
+    }
+
+    #[test]
+    fn paginate_in_descending_order() {
+        let first = pagination(None, 3, SortOrder::Desc)
+            .paginate(&ids(), |k| Some(*k))
+            .unwrap();
+        assert_eq!(first.items, vec![5, 4, 3]);
+
+        let second = pagination(first.next_cursor, 3, SortOrder::Desc)
+            .paginate(&ids(), |k| Some(*k))
+            .unwrap();
+        assert_eq!(second.items, vec![2, 1]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[test]
+    fn paginate_skips_the_rows_not_loaded() {
+        let page = pagination(None, 2, SortOrder::Asc)
+            .paginate(&ids(), |k| if k % 2 == 1 { Some(*k) } else { None })
+            .unwrap();
+        assert_eq!(page.items, vec![1, 3]);
+        assert!(page.next_cursor.is_some());
+
+        // the cursor points to the last item returned, not to the last id visited
+        let page = pagination(page.next_cursor, 2, SortOrder::Asc)
+            .paginate(&ids(), |k| if k % 2 == 1 { Some(*k) } else { None })
+            .unwrap();
+        assert_eq!(page.items, vec![5]);
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn paginate_stops_loading_after_the_page() {
+        let loaded = std::cell::Cell::new(0);
+        pagination(None, 2, SortOrder::Asc)
+            .paginate(&ids(), |k| {
+                loaded.set(loaded.get() + 1);
+                Some(*k)
+            })
+            .unwrap();
+        assert_eq!(loaded.get(), 3);
+    }
+
+    #[test]
+    fn paginate_rejects_invalid_cursors() {
+        assert!(pagination(Some("zz".to_string()), 2, SortOrder::Asc)
+            .paginate(&ids(), |k| Some(*k))
+            .is_err());
+    }
+
+    #[test]
+    fn accepts_the_creation_range() {
+        let mut pag = pagination(None, 10, SortOrder::Asc);
+        pag.filter = Some(PaginationFilter {
+            created_from: Some(10),
+            created_to: Some(20),
+        });
+        assert!(!pag.accepts(9));
+        assert!(pag.accepts(10));
+        assert!(pag.accepts(20));
+        assert!(!pag.accepts(21));
+    }
+}
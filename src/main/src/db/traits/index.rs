@@ -0,0 +1,182 @@
+use candid::{CandidType, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde::Deserialize;
+use crate::db::memory::{Memory, Stored};
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IndexValue {
+    Text(String),
+    Principal(Principal),
+    Nat(u64),
+}
+
+// composite keys have more than one value
+pub type IndexKey = Vec<IndexValue>;
+
+/*
+ * secondary index over a table's rows. The closure returns the keys a row must be indexed by:
+ * none if the row shouldn't be indexed, or more than one to index the values of a collection.
+ * Each (key, id) pair is an entry of its own, so writes don't depend on how many rows share a key
+ */
+pub struct TableIndex<K, V>
+    where
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> {
+    pub name: &'static str,
+    pub unique: bool,
+    pub key: fn(&V) -> Vec<IndexKey>,
+    // the id is only None in the bounds of the range scans, as None sorts before any id
+    map: StableBTreeMap<Stored<(IndexKey, Option<K>)>, (), Memory>,
+    memory: Memory,
+}
+
+impl<K, V> TableIndex<K, V>
+    where
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a> {
+    pub fn new(
+        name: &'static str,
+        unique: bool,
+        key: fn(&V) -> Vec<IndexKey>,
+        memory: Memory
+    ) -> Self {
+        Self {
+            name,
+            unique,
+            key,
+            map: StableBTreeMap::init(memory.clone()),
+            memory,
+        }
+    }
+
+    pub fn is_empty(
+        &self
+    ) -> bool {
+        self.map.is_empty()
+    }
+
+    // returns the ids indexed by key
+    pub fn get<'a>(
+        &'a self,
+        key: &'a IndexKey
+    ) -> impl Iterator<Item = K> + 'a {
+        self.map.range(Stored((key.clone(), None))..)
+            .take_while(move |(entry, _)| entry.0.0 == *key)
+            .filter_map(|(entry, _)| entry.0.1)
+    }
+
+    // returns the ids indexed by the keys in [from, to)
+    pub fn range(
+        &self,
+        from: &IndexKey,
+        to: &IndexKey
+    ) -> impl Iterator<Item = K> + '_ {
+        self.map.range(Stored((from.clone(), None))..Stored((to.clone(), None)))
+            .filter_map(|(entry, _)| entry.0.1)
+    }
+
+    pub fn add(
+        &mut self,
+        key: IndexKey,
+        id: K
+    ) {
+        self.map.insert(Stored((key, Some(id))), ());
+    }
+
+    pub fn remove(
+        &mut self,
+        key: IndexKey,
+        id: &K
+    ) {
+        self.map.remove(&Stored((key, Some(id.clone()))));
+    }
+
+    // returns true if the row can be stored without duplicating a key of an unique index
+    pub fn accepts(
+        &self,
+        id: &K,
+        v: &V
+    ) -> bool {
+        !self.unique || (self.key)(v).iter()
+            .all(|key| self.get(key).all(|other| other == *id))
+    }
+
+    // drops all entries at once, by reinitialising the memory
+    pub fn reset(
+        &mut self
+    ) {
+        self.map = StableBTreeMap::new(self.memory.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_stable_structures::memory_manager::MemoryId;
+    use crate::db::memory::get_memory;
+    use super::*;
+
+    fn text(
+        s: &str
+    ) -> IndexKey {
+        vec![IndexValue::Text(s.to_string())]
+    }
+
+    fn index(
+        unique: bool
+    ) -> TableIndex<u32, String> {
+        TableIndex::new("name", unique, |v: &String| vec![text(v)], get_memory(MemoryId::new(200)))
+    }
+
+    #[test]
+    fn get_returns_only_the_ids_of_the_key() {
+        let mut index = index(false);
+        index.add(text("a"), 1);
+        index.add(text("b"), 2);
+        index.add(text("b"), 3);
+        index.add(text("c"), 4);
+
+        assert_eq!(index.get(&text("b")).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(index.get(&text("d")).count(), 0);
+
+        index.remove(text("b"), &2);
+        assert_eq!(index.get(&text("b")).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn keys_prefixed_by_another_are_not_mixed() {
+        let mut index = index(false);
+        index.add(text("a"), 1);
+        index.add(vec![IndexValue::Text("a".to_string()), IndexValue::Nat(0)], 2);
+
+        assert_eq!(index.get(&text("a")).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn range_excludes_the_upper_bound() {
+        let mut index = index(false);
+        for (i, name) in ["ana", "anb", "bob", "carl"].iter().enumerate() {
+            index.add(text(name), i as u32);
+        }
+
+        assert_eq!(index.range(&text("an"), &text("bob")).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(index.range(&text("b"), &text("z")).collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn unique_indexes_accept_only_the_owner_of_the_key() {
+        let mut index = index(true);
+        index.add(text("a"), 1);
+
+        assert!(index.accepts(&1, &"a".to_string()));
+        assert!(!index.accepts(&2, &"a".to_string()));
+        assert!(index.accepts(&2, &"b".to_string()));
+    }
+
+    #[test]
+    fn reset_drops_all_entries() {
+        let mut index = index(false);
+        index.add(text("a"), 1);
+        index.reset();
+
+        assert!(index.is_empty());
+        assert_eq!(index.get(&text("a")).count(), 0);
+    }
+}
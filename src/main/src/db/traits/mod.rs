@@ -1,2 +1,4 @@
 pub mod crud;
-pub mod table;
\ No newline at end of file
+pub mod index;
+pub mod table;
+pub mod transaction;
\ No newline at end of file
@@ -1,7 +1,12 @@
-use std::{collections::BTreeMap, cell::RefCell, rc::Rc};
-use candid::{CandidType, ser::IDLBuilder, utils::ArgumentEncoder, Principal};
-use ic_cdk::api::stable::{StableWriter, StableReader};
+use std::{collections::BTreeMap, cell::RefCell, rc::Rc, ops::Bound};
+use candid::{CandidType, Principal, ser::IDLBuilder, utils::ArgumentEncoder};
+use ic_stable_structures::{StableBTreeMap, Storable};
 use serde::Deserialize;
+use crate::db::memory::{count_write, Memory, Stored};
+use crate::db::migrations::Migratable;
+use crate::models::error::Error;
+use super::index::{IndexKey, TableIndex};
+use super::transaction::Transactional;
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum TableEventKind {
@@ -25,14 +30,330 @@ pub struct TableEvent<'a, TN> {
     //pub caller: Principal,
 }
 
+// heap representation of a table, used by migrations and to load the data dumped by older versions
 #[derive(CandidType, Deserialize)]
 pub struct TableData<K, V> (pub BTreeMap<K, V>)
     where 
         K: Ord + CandidType, 
         V: CandidType;
 
+// stable memory representation of a table: rows are only decoded when accessed
+pub struct TableStorage<K, V>
+    where 
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
+        V: CandidType + for<'a> Deserialize<'a> {
+    pub map: StableBTreeMap<Stored<K>, Stored<V>, Memory>,
+    memory: Memory,
+    // kept up to date on every write, including the ones undone by a rollback
+    indexes: Vec<TableIndex<K, V>>,
+    // previous values of the rows written while a transaction is open, to be restored on rollback
+    journal: Vec<(Stored<K>, Option<Stored<V>>)>,
+    savepoints: Vec<usize>,
+}
+
+impl<K, V> TableStorage<K, V>
+    where 
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
+        V: CandidType + for<'a> Deserialize<'a> {
+    pub fn new(
+        memory: Memory
+    ) -> Self {
+        Self {
+            map: StableBTreeMap::init(memory.clone()),
+            memory,
+            indexes: vec![],
+            journal: vec![],
+            savepoints: vec![],
+        }
+    }
+
+    // declares a secondary index, stored in its own memory. It's built from the rows
+    // if it's empty, so indexes can be added to tables that already have data
+    pub fn with_index(
+        mut self,
+        name: &'static str,
+        unique: bool,
+        memory: Memory,
+        key: fn(&V) -> Vec<IndexKey>
+    ) -> Self {
+        let mut index = TableIndex::new(name, unique, key, memory);
+        if index.is_empty() {
+            for (k, v) in self.map.iter() {
+                for key in (index.key)(&v.0) {
+                    index.add(key, k.0.clone());
+                }
+            }
+        }
+        self.indexes.push(index);
+        self
+    }
+
+    pub fn contains_key(
+        &self,
+        k: &K
+    ) -> bool {
+        self.map.contains_key(&Stored(k.clone()))
+    }
+
+    pub fn get(
+        &self,
+        k: &K
+    ) -> Option<V> {
+        self.map.get(&Stored(k.clone())).map(|v| v.0)
+    }
+
+    pub fn insert(
+        &mut self,
+        k: K,
+        v: V
+    ) -> Option<V> {
+        let k = Stored(k);
+        self.log(&k);
+        self.write(k, Some(Stored(v))).map(|v| v.0)
+    }
+
+    pub fn remove(
+        &mut self,
+        k: &K
+    ) -> Option<V> {
+        let k = Stored(k.clone());
+        self.log(&k);
+        self.write(k, None).map(|v| v.0)
+    }
+
+    // returns the ids of the rows indexed by key. Unknown indexes have no rows
+    pub fn find_ids(
+        &self,
+        index: &str,
+        key: &IndexKey
+    ) -> Vec<K> {
+        match self.indexes.iter().find(|e| e.name == index) {
+            None => vec![],
+            Some(index) => index.get(key).collect(),
+        }
+    }
+
+    // same as find_ids, but stops after the first limit ids
+    pub fn find_ids_limited(
+        &self,
+        index: &str,
+        key: &IndexKey,
+        limit: usize
+    ) -> Vec<K> {
+        match self.indexes.iter().find(|e| e.name == index) {
+            None => vec![],
+            Some(index) => index.get(key).take(limit).collect(),
+        }
+    }
+
+    pub fn find_ids_in_range(
+        &self,
+        index: &str,
+        from: &IndexKey,
+        to: &IndexKey,
+        limit: usize
+    ) -> Vec<K> {
+        match self.indexes.iter().find(|e| e.name == index) {
+            None => vec![],
+            Some(index) => index.range(from, to).take(limit).collect(),
+        }
+    }
+
+    // returns the name of the first unique index that already has one of the row's keys
+    pub fn check_unique(
+        &self,
+        k: &K,
+        v: &V
+    ) -> Result<(), &'static str> {
+        match self.indexes.iter().find(|e| !e.accepts(k, v)) {
+            None => Ok(()),
+            Some(index) => Err(index.name),
+        }
+    }
+
+    pub fn rebuild_indexes(
+        &mut self
+    ) {
+        for index in self.indexes.iter_mut() {
+            index.reset();
+        }
+        for (k, v) in self.map.iter() {
+            for index in self.indexes.iter_mut() {
+                for key in (index.key)(&v.0) {
+                    index.add(key, k.0.clone());
+                }
+            }
+        }
+    }
+
+    // drops the index entries, so they can be rebuilt by reindex_chunk()
+    pub fn reset_indexes(
+        &mut self
+    ) {
+        for index in self.indexes.iter_mut() {
+            index.reset();
+        }
+        count_write();
+    }
+
+    // indexes up to limit rows after the cursor, returning them with the cursor of the next ones,
+    // encoded as export_chunk() does. The cursor is None once all rows were indexed
+    pub fn reindex_chunk(
+        &mut self,
+        cursor: &Option<Vec<u8>>,
+        limit: usize
+    ) -> Result<(Vec<(K, V)>, Option<Vec<u8>>), String> {
+        let start = match cursor {
+            None => Bound::Unbounded,
+            Some(cursor) => Bound::Excluded(Stored(
+                candid::decode_one::<K>(cursor).map_err(|e| format!("{:?}", e))?
+            )),
+        };
+
+        let mut rows: Vec<(K, V)> = self.map.range((start, Bound::Unbounded))
+            .take(limit + 1)
+            .map(|(k, v)| (k.0, v.0))
+            .collect();
+
+        let next = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|(k, _)| Stored(k.clone()).to_bytes().to_vec())
+        }
+        else {
+            None
+        };
+
+        for (k, v) in &rows {
+            for index in self.indexes.iter_mut() {
+                for key in (index.key)(v) {
+                    index.add(key, k.clone());
+                }
+            }
+        }
+
+        Ok((rows, next))
+    }
+
+    pub fn len(
+        &self
+    ) -> u64 {
+        self.map.len()
+    }
+
+    pub fn iter(
+        &self
+    ) -> impl Iterator<Item = (K, V)> + '_ {
+        self.map.iter().map(|(k, v)| (k.0, v.0))
+    }
+
+    pub fn values(
+        &self
+    ) -> impl Iterator<Item = V> + '_ {
+        self.map.iter().map(|(_, v)| v.0)
+    }
+
+    pub fn clear(
+        &mut self
+    ) {
+        let keys: Vec<Stored<K>> = self.map.iter().map(|(k, _)| k).collect();
+        for k in keys {
+            self.log(&k);
+            self.write(k, None);
+        }
+    }
+
+    // drops all rows and index entries at once, by reinitialising their memories. Unlike clear(),
+    // it can't be undone, so it must not be called inside a transaction
+    pub fn reset(
+        &mut self
+    ) {
+        self.map = StableBTreeMap::new(self.memory.clone());
+        for index in self.indexes.iter_mut() {
+            index.reset();
+        }
+        count_write();
+    }
+
+    // writes (or removes, if v is None) a row, updating the indexes
+    fn write(
+        &mut self,
+        k: Stored<K>,
+        v: Option<Stored<V>>
+    ) -> Option<Stored<V>> {
+        let keys: Vec<Vec<IndexKey>> = self.indexes.iter()
+            .map(|index| v.as_ref().map(|v| (index.key)(&v.0)).unwrap_or_default())
+            .collect();
+
+        let prev = match v {
+            Some(v) => self.map.insert(k.clone(), v),
+            None => self.map.remove(&k),
+        };
+        count_write();
+
+        for (index, keys) in self.indexes.iter_mut().zip(keys) {
+            if let Some(prev) = &prev {
+                for key in (index.key)(&prev.0) {
+                    index.remove(key, &k.0);
+                }
+            }
+            for key in keys {
+                index.add(key, k.0.clone());
+            }
+        }
+
+        prev
+    }
+
+    fn log(
+        &mut self,
+        k: &Stored<K>
+    ) {
+        if !self.savepoints.is_empty() {
+            self.journal.push((k.clone(), self.map.get(k)));
+        }
+    }
+}
+
+impl<K, V> Transactional for TableStorage<K, V>
+    where 
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
+        V: CandidType + for<'a> Deserialize<'a> {
+    fn begin(
+        &mut self
+    ) {
+        self.savepoints.push(self.journal.len());
+    }
+
+    fn commit(
+        &mut self
+    ) {
+        self.savepoints.pop();
+        // changes can only be discarded by an outer transaction
+        if self.savepoints.is_empty() {
+            self.journal.clear();
+        }
+    }
+
+    fn rollback(
+        &mut self
+    ) {
+        let savepoint = self.savepoints.pop().unwrap_or(0);
+        while self.journal.len() > savepoint {
+            match self.journal.pop() {
+                Some((k, Some(v))) => {
+                    self.write(k, Some(v));
+                },
+                Some((k, None)) => {
+                    self.write(k, None);
+                },
+                None => (),
+            }
+        }
+    }
+}
+
 pub struct TableSchema<TN> {
-    pub version: f32,
+    pub version: u32,
     pub name: TN,
 }
 
@@ -40,8 +361,8 @@ pub struct TableSubs<TN> (pub Vec<Rc<RefCell<dyn TableSubscriber<TN>>>>);
 
 pub trait Table<TN, K, V>
     where 
-        K: Ord + CandidType, 
-        V: CandidType {
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
+        V: CandidType + for<'a> Deserialize<'a> {
 
     fn new(
     ) -> Self;
@@ -52,87 +373,128 @@ pub trait Table<TN, K, V>
         
     fn get_data(
         &self
-    ) -> &TableData<K, V>;
+    ) -> &TableStorage<K, V>;
 
     fn get_data_mut(
         &mut self
-    ) -> &mut TableData<K, V>;
+    ) -> &mut TableStorage<K, V>;
 
     fn set_data(
         &mut self,
         data: TableData<K, V>
-    );
+    ) {
+        let storage = self.get_data_mut();
+        storage.clear();
+        for (k, v) in data.0 {
+            storage.insert(k, v);
+        }
+    }
 }
 
 pub trait TableVersioned<TN, K, V>
     where 
-        K: Ord + CandidType, 
-        V: CandidType,
+        TN: Migratable,
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
+        V: CandidType + for<'a> Deserialize<'a>,
         Self: Table<TN, K, V> {
+    // converts a whole table, stored with an older version, using the migrations registered for it.
+    // Nothing is written, so it can also be used to validate the data
     fn migrate(
         &self,
-        _from_version: f32,
-        _buf: &[u8]
+        from_version: u32,
+        buf: &[u8]
     ) -> Result<TableData<K, V>, String> {
-        panic!("Not supported")
+        let buf = self.get_schema().name.migrate_table(from_version, buf.to_vec())?;
+        Ok(candid::decode_args::<'_, (TableData<K, V>, )>(&buf)
+            .map_err(|e| format!("{:?}", e))?.0)
     }
 }
 
-pub trait TableSerializable<TN, K, V>
+pub trait TableDeserializable<TN, K, V>
     where 
-        K: Ord + CandidType, 
-        V: CandidType,
-        Self: Table<TN, K, V> {
-    fn serialize(
-        &self,
-        writer: &mut StableWriter
+        TN: Migratable,
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
+        V: CandidType + for<'a> Deserialize<'a>,
+        Self: Table<TN, K, V> + TableVersioned<TN, K, V> {
+    // loads a table dumped to stable memory by the versions prior to the stable structures
+    fn deserialize(
+        &mut self, 
+        version: u32,
+        buf: &[u8]
     ) -> Result<(), String> {
-        let mut ser = IDLBuilder::new();
-        (&self.get_data().0, ).encode(&mut ser).map_err(|e| format!("{:?}", e))?;
-        let arr = ser.serialize_to_vec().unwrap();
-        // store version
-        writer.write(&f32::to_le_bytes(self.get_schema().version)).map_err(|e| format!("{:?}", e))?;
-        // store size
-        writer.write(&u64::to_le_bytes(arr.len() as u64)).map_err(|e| format!("{:?}", e))?;
-        // store table
-        writer.write(&arr).map_err(|e| format!("{:?}", e))?;
+        let data = if version == self.get_schema().version {
+            candid::decode_args::<'_, (TableData<K, V>, )>(buf)
+                .map_err(|e| format!("{:?}", e))?.0
+        }
+        else {
+            self.migrate(version, buf)?
+        };
+        self.set_data(data);
         Ok(())
     }
 }
 
-pub trait TableDeserializable<TN, K, V>
+pub trait TableSnapshotable<TN, K, V>
     where 
-        K: Ord + CandidType + for<'a> Deserialize<'a>, 
+        TN: Migratable,
+        K: Ord + Clone + CandidType + for<'a> Deserialize<'a>, 
         V: CandidType + for<'a> Deserialize<'a>,
         Self: Table<TN, K, V> + TableVersioned<TN, K, V> {
-    fn deserialize(
-        &mut self, 
-        reader: &mut StableReader,
-        decode_data: bool
-    ) -> Result<(), String> {
-        // load version
-        let mut version_buf = [0u8; 4];
-        reader.read(&mut version_buf).map_err(|e| format!("{:?}", e))?;
-        let version = f32::from_le_bytes(version_buf);
-        // load size
-        let mut size_buf = [0u8; 8];
-        reader.read(&mut size_buf).map_err(|e| format!("{:?}", e))?;
-        let size = u64::from_le_bytes(size_buf);
-        // load table
-        let mut table_buf = vec![0u8; size as usize];
-        reader.read(&mut table_buf).map_err(|e| format!("{:?}", e))?;
-        // decode table
-        if decode_data {
-            let data = if version == self.get_schema().version {
-                candid::decode_args::<'_, (TableData<K, V>, )>(&table_buf)
-                    .map_err(|e| format!("{:?}", e))?.0
+    // encodes the rows following the (Candid-encoded) cursor key, until max_size is reached.
+    // Returns the chunk, the number of rows in it and the cursor of the next chunk, if any
+    fn export_chunk(
+        &self,
+        cursor: &Option<Vec<u8>>,
+        max_size: usize
+    ) -> Result<(Vec<u8>, u32, Option<Vec<u8>>), String> {
+        let start = match cursor {
+            None => Bound::Unbounded,
+            Some(cursor) => Bound::Excluded(Stored(
+                candid::decode_one::<K>(cursor).map_err(|e| format!("{:?}", e))?
+            )),
+        };
+
+        let mut rows = BTreeMap::new();
+        let mut size = 0usize;
+        let mut next = None;
+        for (k, v) in self.get_data().map.range((start, Bound::Unbounded)) {
+            if size > 0 && size >= max_size {
+                next = rows.keys().next_back().map(|k: &K| Stored(k.clone()).to_bytes().to_vec());
+                break;
             }
-            else {
-                self.migrate(version, &table_buf)?
-            };
-            self.set_data(data);
+            size += k.to_bytes().len() + v.to_bytes().len();
+            rows.insert(k.0, v.0);
         }
-        Ok(())
+
+        let count = rows.len() as u32;
+        let mut ser = IDLBuilder::new();
+        (&TableData(rows), ).encode(&mut ser).map_err(|e| format!("{:?}", e))?;
+        let buf = ser.serialize_to_vec().map_err(|e| format!("{:?}", e))?;
+
+        Ok((buf, count, next))
+    }
+
+    // inserts the rows of a chunk created by export_chunk(), migrating them if needed
+    fn import_chunk(
+        &mut self,
+        version: u32,
+        buf: &[u8]
+    ) -> Result<u32, String> {
+        let data = if version == self.get_schema().version {
+            candid::decode_args::<'_, (TableData<K, V>, )>(buf)
+                .map_err(|e| format!("{:?}", e))?.0
+        }
+        else {
+            self.migrate(version, buf)?
+        };
+
+        let count = data.0.len() as u32;
+        let storage = self.get_data_mut();
+        for (k, v) in data.0 {
+            storage.insert(k, v);
+        }
+
+        Ok(count)
     }
 }
 
@@ -140,7 +502,7 @@ pub trait TableSubscriber<TN> {
     fn on(
         &mut self,
         event: &TableEvent<TN>
-    );
+    ) -> Result<(), Error>;
 }
 
 pub trait TableSubscribable<TN, K, V>
@@ -170,12 +532,138 @@ pub trait TableSubscribable<TN, K, V>
         self.get_subs_mut().0.push(tb);
     }
 
+    // a subscriber failing leaves the others half-updated, so this must run inside a transaction
     fn notify (
         &self,
         event: &TableEvent<TN>
-    ) {
-        self.get_subs().0.iter()
-            .for_each(|c| c.borrow_mut().on(event));
+    ) -> Result<(), Error> {
+        for sub in self.get_subs().0.iter() {
+            sub.borrow_mut().on(event)?;
+        }
+        Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use ic_stable_structures::memory_manager::MemoryId;
+    use crate::db::memory::get_memory;
+    use crate::db::traits::index::IndexValue;
+    use super::*;
+
+    fn storage(
+    ) -> TableStorage<u32, String> {
+        TableStorage::new(get_memory(MemoryId::new(201)))
+            .with_index("name", true, get_memory(MemoryId::new(202)), |v| vec![vec![IndexValue::Text(v.clone())]])
+    }
+
+    fn key(
+        s: &str
+    ) -> IndexKey {
+        vec![IndexValue::Text(s.to_string())]
+    }
+
+    #[test]
+    fn rollback_restores_the_rows_and_indexes() {
+        let mut table = storage();
+        table.insert(1, "a".to_string());
+        table.insert(2, "b".to_string());
+
+        table.begin();
+        table.insert(1, "c".to_string());
+        table.remove(&2);
+        table.insert(3, "d".to_string());
+        table.rollback();
+
+        assert_eq!(table.iter().collect::<Vec<_>>(), vec![(1, "a".to_string()), (2, "b".to_string())]);
+        assert_eq!(table.find_ids("name", &key("a")), vec![1]);
+        assert_eq!(table.find_ids("name", &key("b")), vec![2]);
+        assert!(table.find_ids("name", &key("c")).is_empty());
+        assert!(table.find_ids("name", &key("d")).is_empty());
+    }
+
+    #[test]
+    fn commit_keeps_the_changes() {
+        let mut table = storage();
+        table.begin();
+        table.insert(1, "a".to_string());
+        table.commit();
+        table.rollback();
+
+        assert_eq!(table.get(&1), Some("a".to_string()));
+    }
+
+    #[test]
+    fn inner_transactions_are_undone_by_the_outer_one() {
+        let mut table = storage();
+        table.begin();
+        table.insert(1, "a".to_string());
+        table.begin();
+        table.insert(2, "b".to_string());
+        table.commit();
+        table.rollback();
+
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn inner_rollbacks_keep_the_outer_changes() {
+        let mut table = storage();
+        table.begin();
+        table.insert(1, "a".to_string());
+        table.begin();
+        table.insert(2, "b".to_string());
+        table.rollback();
+        table.commit();
+
+        assert_eq!(table.iter().collect::<Vec<_>>(), vec![(1, "a".to_string())]);
+    }
+
+    #[test]
+    fn transaction_rolls_back_on_errors() {
+        let mut table = storage();
+        let res: Result<(), &str> = table.transaction(|table| {
+            table.insert(1, "a".to_string());
+            Err("failed")
+        });
+        assert_eq!(res, Err("failed"));
+        assert_eq!(table.len(), 0);
+
+        let res: Result<(), &str> = table.transaction(|table| {
+            table.insert(1, "a".to_string());
+            Ok(())
+        });
+        assert_eq!(res, Ok(()));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn unique_indexes_reject_keys_of_other_rows() {
+        let mut table = storage();
+        table.insert(1, "a".to_string());
+
+        assert_eq!(table.check_unique(&2, &"a".to_string()), Err("name"));
+        assert_eq!(table.check_unique(&1, &"a".to_string()), Ok(()));
+        assert_eq!(table.check_unique(&2, &"b".to_string()), Ok(()));
+    }
+
+    #[test]
+    fn reindex_chunk_resumes_from_the_cursor() {
+        let mut table = storage();
+        table.insert(1, "a".to_string());
+        table.insert(2, "b".to_string());
+        table.insert(3, "c".to_string());
+        table.reset_indexes();
+        assert!(table.find_ids("name", &key("a")).is_empty());
+
+        let (rows, next) = table.reindex_chunk(&None, 2).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(table.find_ids("name", &key("c")).is_empty());
+
+        let (rows, next) = table.reindex_chunk(&next, 2).unwrap();
+        assert_eq!(rows, vec![(3, "c".to_string())]);
+        assert_eq!(next, None);
+        assert_eq!(table.find_ids("name", &key("a")), vec![1]);
+        assert_eq!(table.find_ids("name", &key("c")), vec![3]);
+    }
+}
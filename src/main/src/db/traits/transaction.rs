@@ -0,0 +1,31 @@
+pub trait Transactional {
+    // transactions can be nested: each begin() must be matched by a commit() or a rollback()
+    fn begin(
+        &mut self
+    );
+
+    fn commit(
+        &mut self
+    );
+
+    // discards the changes made since the matching begin()
+    fn rollback(
+        &mut self
+    );
+
+    // runs f as a unit of work: all its changes are kept if it succeeds, or discarded otherwise
+    fn transaction<T, E>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, E>
+    ) -> Result<T, E>
+        where
+            Self: Sized {
+        self.begin();
+        let res = f(self);
+        match res {
+            Ok(_) => self.commit(),
+            Err(_) => self.rollback(),
+        }
+        res
+    }
+}
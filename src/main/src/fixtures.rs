@@ -0,0 +1,249 @@
+// rows and certificates shared by the unit tests. Tests override the fields they check
+
+use std::collections::BTreeSet;
+use candid::Principal;
+use crate::models::access_log::AccessLog;
+use crate::models::crl::Crl;
+use crate::models::prescription::{Prescription, PrescriptionState};
+use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthKind, PrescriptionAuthSubject, PrescriptionOperation};
+use crate::models::prescription_template::PrescriptionTemplate;
+use crate::models::user::{User, UserKind};
+use crate::utils::x509::{X509Cert, pem_to_der};
+
+// self-signed P-256 CA, signed with SHA-256. The certificates, CRL and CMS below were generated with openssl
+pub const ROOT_PEM: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIBhjCCASugAwIBAgIUZAEvRk/mkjBwIJ8Nlf8GFIf8sDUwCgYIKoZIzj0EAwIw
+FzEVMBMGA1UEAwwMVGVzdCBSb290IENBMCAXDTI2MTAxODExNDE1OVoYDzIxMjYw
+OTI0MTE0MTU5WjAXMRUwEwYDVQQDDAxUZXN0IFJvb3QgQ0EwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAARkwPiQiNzcX3kKsrmtEJR3eB9v6542BqUsrUcayHaoyW+s
+L6ndbeSelDS+8l3oBL6Ndycuj5Hd0CC2FaRqnH3do1MwUTAdBgNVHQ4EFgQUgFnu
+ZhIGCPorEmuIKXlp9OXVlV0wHwYDVR0jBBgwFoAUgFnuZhIGCPorEmuIKXlp9OXV
+lV0wDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNJADBGAiEArki7pw9iGVdx
+icy11t6wziteoUnpgZOqbGTF2hUsznACIQCkrxhZKpOhyz0tM8NEYCMO/K5Ng5tf
+nFEVLr9/XsAVfQ==
+-----END CERTIFICATE-----
+";
+
+// self-signed P-384 CA, signed with SHA-384
+pub const P384_PEM: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIBxDCCAUqgAwIBAgIUGrYm5ToosZO3en4O4TWK+6I5ByIwCgYIKoZIzj0EAwMw
+GDEWMBQGA1UEAwwNVGVzdCBQLTM4NCBDQTAgFw0yNjEwMTgxMTQ0MTlaGA8yMTI2
+MDkyNDExNDQxOVowGDEWMBQGA1UEAwwNVGVzdCBQLTM4NCBDQTB2MBAGByqGSM49
+AgEGBSuBBAAiA2IABCZszze/6+Fyy8D3kkp+wiblgkUr9ExQJlglLLczROCaSVJW
+IBophx4Lyr2Ux6ufLuyTKPaPp3reQVEGs5YhLx4uH+o2c41OQwWD2SgnklkR+j2+
+XpSlMM8b7xzJXAqXXqNTMFEwHQYDVR0OBBYEFDvmh3ByX1sGU36X34pkBEcXcG2A
+MB8GA1UdIwQYMBaAFDvmh3ByX1sGU36X34pkBEcXcG2AMA8GA1UdEwEB/wQFMAMB
+Af8wCgYIKoZIzj0EAwMDaAAwZQIwKI9O4rIV8OrhFFuXUxcrApZdaPkoXw6WyzQU
+inaCLTKXBcV+h4lnK6aixqV6AdhoAjEAv1BW0qPivv0lAaJw7m6gcJTFl2p7eimO
+n86u3f+R/ScKUTPyIO93DZFM8+HdUugK
+-----END CERTIFICATE-----
+";
+
+// self-signed RSA CA, signed with SHA-512
+pub const RSA_PEM: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIDDzCCAfegAwIBAgIUc/OfYPtPh3pzqL6tcxcmAfm92lAwDQYJKoZIhvcNAQEN
+BQAwFjEUMBIGA1UEAwwLVGVzdCBSU0EgQ0EwIBcNMjYxMDE4MTE0NDIwWhgPMjEy
+NjA5MjQxMTQ0MjBaMBYxFDASBgNVBAMMC1Rlc3QgUlNBIENBMIIBIjANBgkqhkiG
+9w0BAQEFAAOCAQ8AMIIBCgKCAQEA2jL72HJoapDEeWqa93AWRXlHTzbTlYVApzuw
+FDWeD9vceduVz/uVI/Z01taMmLJMCTrdrFvB3mHoV+lNR6HWIIj4KP8zl3WY8AiY
+ceOXiq/pE1mZ8u7V9t3+fA+Y+IDUAyjajZaGBUJhoI1sopFoqEv2RJCBxZtx6KB+
+JdhuNkv3SwJxPClWwBgxkZeTQgNcLfJ+zk4APA0lQwWdYGD4/pwk9xL5e4B0MWX6
+g5ekadhf3fziyclmlv6BW1NXay6ObQtSYZK9IRsWRAneMnyuz15JYfaj2CY7R3EY
+zL4T3rsPlsvaFmcmY1OBUct9o8x73Ecm/h2E4TvbLKHh8I4YNQIDAQABo1MwUTAd
+BgNVHQ4EFgQUL95CRbq1xOyFydKB9NiMRvU9kk0wHwYDVR0jBBgwFoAUL95CRbq1
+xOyFydKB9NiMRvU9kk0wDwYDVR0TAQH/BAUwAwEB/zANBgkqhkiG9w0BAQ0FAAOC
+AQEAj/PXxRq/FhuN6UyHOAnbPaioA3ZjuqTVK89GmkU4TwSzamWLM7+isyyyElAf
+LeL2QvZeUZwj7bWpr9EL51phyGKb1Ts6qeq7RNBnzIqt+nhzwb9nTU6+rBlS+fn6
+5F01tEVJxjzLAqC60x3s4AKB7RJpd8wg7lZaeRT3iAysKH74VL4RdIlyL1q8Ol8L
+BzstXriyz3vtBwitxHiwBYlketDrbWSJpB/o+x33l/dmcqPd0Rx0KIw3c852LkJO
+00Svm84zleWYMqOPp1/gd51315Bx5mpMFNya/1Of8Jmwsg288aE1vwWyfrBgKhjs
+udO4t/mBLJsiPkPmISe1ShMTFA==
+-----END CERTIFICATE-----
+";
+
+// CRL issued by the ROOT_PEM CA, revoking the serial 0A1B
+pub const CRL_PEM: &[u8] = b"-----BEGIN X509 CRL-----
+MIHHMG8CAQEwCgYIKoZIzj0EAwIwFzEVMBMGA1UEAwwMVGVzdCBSb290IENBFw0y
+NjEwMTgxMTQ1MTZaGA8yMTI2MDkyNDExNDUxNlowFTATAgIKGxcNMjYxMDE4MDAw
+MDAwWqAOMAwwCgYDVR0UBAMCAQEwCgYIKoZIzj0EAwIDSAAwRQIgXsY2yOpEzlm6
+Cmp5GophxuIRzyvT1N8GQMIqfXN+LTICIQDvOn0xrsIcrLiG9uwbTm61zXc1CYyM
+758/ZYbieYEGTg==
+-----END X509 CRL-----
+";
+
+pub const CMS_CONTENT: &[u8] = b"prescription";
+
+// CMS_CONTENT signed by the ROOT_PEM key with openssl cms -sign, detached
+pub const CMS_DETACHED_PEM: &[u8] = b"-----BEGIN CMS-----
+MIIDRQYJKoZIhvcNAQcCoIIDNjCCAzICAQExDTALBglghkgBZQMEAgEwCwYJKoZI
+hvcNAQcBoIIBijCCAYYwggEroAMCAQICFGQBL0ZP5pIwcCCfDZX/BhSH/LA1MAoG
+CCqGSM49BAMCMBcxFTATBgNVBAMMDFRlc3QgUm9vdCBDQTAgFw0yNjEwMTgxMTQx
+NTlaGA8yMTI2MDkyNDExNDE1OVowFzEVMBMGA1UEAwwMVGVzdCBSb290IENBMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEZMD4kIjc3F95CrK5rRCUd3gfb+ueNgal
+LK1HGsh2qMlvrC+p3W3knpQ0vvJd6AS+jXcnLo+R3dAgthWkapx93aNTMFEwHQYD
+VR0OBBYEFIBZ7mYSBgj6KxJriCl5afTl1ZVdMB8GA1UdIwQYMBaAFIBZ7mYSBgj6
+KxJriCl5afTl1ZVdMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSQAwRgIh
+AK5Iu6cPYhlXcYnMtdbesM4rXqFJ6YGTqmxkxdoVLM5wAiEApK8YWSqTocs9LTPD
+RGAjDvyuTYObX5xRFS6/f17AFX0xggGBMIIBfQIBATAvMBcxFTATBgNVBAMMDFRl
+c3QgUm9vdCBDQQIUZAEvRk/mkjBwIJ8Nlf8GFIf8sDUwCwYJYIZIAWUDBAIBoIHk
+MBgGCSqGSIb3DQEJAzELBgkqhkiG9w0BBwEwHAYJKoZIhvcNAQkFMQ8XDTI2MTAx
+ODExNDQ0OVowLwYJKoZIhvcNAQkEMSIEIHWHaMPKJh2FheawFfM0dgDaSUB8PPF/
+ChWMTjSH93L0MHkGCSqGSIb3DQEJDzFsMGowCwYJYIZIAWUDBAEqMAsGCWCGSAFl
+AwQBFjALBglghkgBZQMEAQIwCgYIKoZIhvcNAwcwDgYIKoZIhvcNAwICAgCAMA0G
+CCqGSIb3DQMCAgFAMAcGBSsOAwIHMA0GCCqGSIb3DQMCAgEoMAoGCCqGSM49BAMC
+BEcwRQIhAJ9Z8LVgVH2+6baUnM6htiS5Loo9DK5YqngKc3mwkKR1AiBQlsrBeHBQ
+24dA1YEjNF8KJPfNgIWf0ns0MdzBVGL/SQ==
+-----END CMS-----
+";
+
+// the same, with the content embedded (-nodetach)
+pub const CMS_ATTACHED_PEM: &[u8] = b"-----BEGIN CMS-----
+MIIDVgYJKoZIhvcNAQcCoIIDRzCCA0MCAQExDTALBglghkgBZQMEAgEwGwYJKoZI
+hvcNAQcBoA4EDHByZXNjcmlwdGlvbqCCAYowggGGMIIBK6ADAgECAhRkAS9GT+aS
+MHAgnw2V/wYUh/ywNTAKBggqhkjOPQQDAjAXMRUwEwYDVQQDDAxUZXN0IFJvb3Qg
+Q0EwIBcNMjYxMDE4MTE0MTU5WhgPMjEyNjA5MjQxMTQxNTlaMBcxFTATBgNVBAMM
+DFRlc3QgUm9vdCBDQTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABGTA+JCI3Nxf
+eQqyua0QlHd4H2/rnjYGpSytRxrIdqjJb6wvqd1t5J6UNL7yXegEvo13Jy6Pkd3Q
+ILYVpGqcfd2jUzBRMB0GA1UdDgQWBBSAWe5mEgYI+isSa4gpeWn05dWVXTAfBgNV
+HSMEGDAWgBSAWe5mEgYI+isSa4gpeWn05dWVXTAPBgNVHRMBAf8EBTADAQH/MAoG
+CCqGSM49BAMCA0kAMEYCIQCuSLunD2IZV3GJzLXW3rDOK16hSemBk6psZMXaFSzO
+cAIhAKSvGFkqk6HLPS0zw0RgIw78rk2Dm1+cURUuv39ewBV9MYIBgjCCAX4CAQEw
+LzAXMRUwEwYDVQQDDAxUZXN0IFJvb3QgQ0ECFGQBL0ZP5pIwcCCfDZX/BhSH/LA1
+MAsGCWCGSAFlAwQCAaCB5DAYBgkqhkiG9w0BCQMxCwYJKoZIhvcNAQcBMBwGCSqG
+SIb3DQEJBTEPFw0yNjEwMTgxMTQ0NDlaMC8GCSqGSIb3DQEJBDEiBCB1h2jDyiYd
+hYXmsBXzNHYA2klAfDzxfwoVjE40h/dy9DB5BgkqhkiG9w0BCQ8xbDBqMAsGCWCG
+SAFlAwQBKjALBglghkgBZQMEARYwCwYJYIZIAWUDBAECMAoGCCqGSIb3DQMHMA4G
+CCqGSIb3DQMCAgIAgDANBggqhkiG9w0DAgIBQDAHBgUrDgMCBzANBggqhkiG9w0D
+AgIBKDAKBggqhkjOPQQDAgRIMEYCIQD4gIUlTAoi/Wu1RR+4NZJh+lRTiTG3hNl0
+u6pUXaqxxgIhAIJGbYn12Ovp9qJDxkEbRaIen5CkX6AKvFcR+NmZeRoD
+-----END CMS-----
+";
+
+pub fn principal(
+    n: u8
+) -> Principal {
+    Principal::from_slice(&[n])
+}
+
+pub fn user(
+    kind: UserKind
+) -> User {
+    User {
+        id: principal(1),
+        name: "John Doe".to_string(),
+        email: "john@example.com".to_string(),
+        credits: 0,
+        active: true,
+        banned: false,
+        kind,
+        created_at: 0,
+        created_by: principal(1),
+        updated_at: None,
+        updated_by: None,
+        deleted_at: None,
+        deleted_by: None,
+    }
+}
+
+pub fn prescription(
+    id: &str
+) -> Prescription {
+    Prescription {
+        id: id.to_string(),
+        state: PrescriptionState::Signed,
+        doctor: principal(1),
+        patient: principal(2),
+        plain_text_hash: vec![],
+        cipher_text_hash: None,
+        cipher_text: None,
+        signature: None,
+        signature_algorithm: None,
+        cert: None,
+        cms: None,
+        signed_at: None,
+        created_at: 0,
+        created_by: principal(1),
+        deleted_at: None,
+        deleted_by: None,
+    }
+}
+
+// granted by the patient of prescription("p1")
+pub fn auth(
+    id: &str,
+    kind: PrescriptionAuthKind
+) -> PrescriptionAuth {
+    PrescriptionAuth {
+        id: id.to_string(),
+        prescription_id: "p1".to_string(),
+        kind,
+        from: principal(2),
+        to: PrescriptionAuthSubject::Group("g1".to_string()),
+        expires_at: None,
+        expired_at: None,
+        chain: vec![],
+        created_at: 0,
+        created_by: principal(2),
+        updated_at: None,
+        updated_by: None,
+        deleted_at: None,
+        deleted_by: None,
+    }
+}
+
+pub fn template(
+    id: &str
+) -> PrescriptionTemplate {
+    PrescriptionTemplate {
+        id: id.to_string(),
+        title: "title".to_string(),
+        body: "body".to_string(),
+        created_at: 0,
+        created_by: principal(1),
+        updated_at: None,
+        updated_by: None,
+        deleted_at: None,
+        deleted_by: None,
+    }
+}
+
+// a View of prescription("p1") by a grantee
+pub fn access_log(
+    id: &str,
+    patient: Principal,
+    doctor: Principal
+) -> AccessLog {
+    AccessLog {
+        id: id.to_string(),
+        prescription_id: "p1".to_string(),
+        patient,
+        doctor,
+        operation: PrescriptionOperation::View,
+        auth_id: None,
+        group_id: None,
+        accessed_at: 0,
+        accessed_by: principal(3),
+    }
+}
+
+pub fn crl(
+    next_update: Option<u64>
+) -> Crl {
+    Crl {
+        id: "00".to_string(),
+        issuer: "CN=CA".to_string(),
+        this_update: 10,
+        next_update,
+        revoked: BTreeSet::new(),
+        created_at: 10,
+        created_by: principal(1),
+        updated_at: None,
+        updated_by: None,
+    }
+}
+
+pub fn cert(
+    pem: &[u8]
+) -> X509Cert {
+    X509Cert::from_der(&pem_to_der(pem).unwrap()[0], &vec![]).unwrap()
+}
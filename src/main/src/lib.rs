@@ -2,37 +2,71 @@ pub mod models;
 pub mod db;
 pub mod utils;
 pub mod services;
+#[cfg(test)]
+mod fixtures;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 use candid::{Principal, CandidType};
-use db::traits::crud::Pagination;
+use db::traits::crud::{Page, Pagination};
+use db::traits::transaction::Transactional;
 use ic_cdk::api::stable;
 use ic_cdk::{caller, trap};
+use ic_stable_structures::reader::Reader;
+use ic_stable_structures::writer::Writer;
+use models::error::Error;
 use models::group::{GroupRequest, GroupResponse, Group, GroupId};
 use serde::Deserialize;
 use db::DB;
+use db::migrations;
+use db::memory::{get_memory, is_legacy_layout, STATE_MEMORY_ID};
 use models::prescription_auth::{PrescriptionAuthRequest, PrescriptionAuthResponse, 
-    PrescriptionAuth, PrescriptionAuthId};
+    PrescriptionAuth, PrescriptionAuthId, PrescriptionOperation, GrantsSweepStatus};
 use models::key::{KeyRequest, KeyResponse, Key, KeyId, KeyKind};
-use models::prescription::{PrescriptionResponse, Prescription, PrescriptionId, PrescriptionPreRequest, PrescriptionPostRequest};
+use models::search::SearchRequest;
+use models::access_log::AccessLogResponse;
+use models::prescription::{PrescriptionResponse, Prescription, PrescriptionId, PrescriptionPreRequest, PrescriptionPostRequest, 
+    PrescriptionVerificationReport, PrescriptionFilter, SharedPrescriptionResponse};
+use models::prescription_template::{PrescriptionTemplateRequest, PrescriptionTemplateResponse, 
+    PrescriptionTemplate, PrescriptionTemplateId};
+use models::consistency::{ConsistencyReport, RebuildStatus};
+use models::crl::{CrlRequest, CrlResponse, CrlId};
+use models::retention::RetentionConfig;
+use models::snapshot::{SnapshotManifest, SnapshotExportRequest, SnapshotChunk, SnapshotImportStatus};
+use models::trusted_cert::{TrustedCertRequest, TrustedCertResponse, TrustedCertId};
 use models::user::{UserResponse, UserId, UserRequest, User};
+use services::crls::CrlsService;
 use services::groups::GroupsService;
+use services::prescription_templates::PrescriptionTemplatesService;
+use services::retention::RetentionService;
+use services::snapshots::SnapshotsService;
+use services::maintenance::MaintenanceService;
+use services::relations::RelationsService;
+use services::search::SearchService;
+use services::access_logs::AccessLogsService;
+use services::trusted_certs::TrustedCertsService;
 use services::{users::UsersService, prescriptions::PrescriptionsService, keys::KeysService, prescription_auths::PrescriptionAuthsService};
 use utils::random::Xoshiro256ss;
 use utils::{serdeser::{serialize, deserialize}, vetkd::VetKdUtil};
 
-const STATE_VERSION: f32 = 0.1;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const GRANTS_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Default, CandidType, Deserialize)]
 struct State {
     owner: Option<Principal>,
     vetkd: VetKdUtil,
     rand: Xoshiro256ss,
+    // optional, so the STATE saved by older versions can still be decoded
+    retention: Option<RetentionConfig>,
+    grants_sweep: Option<GrantsSweepStatus>,
 }
 
 thread_local! {
     static STATE: RefCell<State> = RefCell::default();
     static DB: RefCell<DB> = RefCell::new(DB::new());    
+    // not kept across upgrades, like the timers
+    static TIMERS_STARTED_AT: Cell<u64> = Cell::new(0);
 }
 
 fn _gen_id(
@@ -44,6 +78,85 @@ fn _gen_id(
     ulid::Ulid::from_parts(ic_cdk::api::time() / 1000000, (msu64 as u128) << 64 | (lsu64 as u128)).to_string()
 }
 
+fn _is_owner(
+    caller: &Principal
+) -> bool {
+    STATE.with(|rc| rc.borrow().owner == Some(caller.clone()))
+}
+
+fn _get_retention(
+) -> RetentionConfig {
+    STATE.with(|rc| rc.borrow().retention.clone().unwrap_or_default())
+}
+
+fn _purge_deleted(
+) {
+    // skipped while the DB is read-only: the next run will catch up
+    if MaintenanceService::get().is_some() {
+        return;
+    }
+
+    let deleted_before = ic_cdk::api::time().saturating_sub(_get_retention().purge_after);
+    DB.with(|db| {
+        RetentionService::purge(deleted_before, &mut db.borrow_mut());
+    });
+}
+
+fn _expire_grants(
+) {
+    // skipped while the DB is read-only: the next run will catch up
+    if MaintenanceService::get().is_some() {
+        return;
+    }
+
+    let now = ic_cdk::api::time();
+    let res = DB.with(|db| {
+        db.borrow_mut().transaction(|db| PrescriptionAuthsService::expire(now, db))
+    });
+
+    STATE.with(|rc| {
+        let mut state = rc.borrow_mut();
+        let status = state.grants_sweep.get_or_insert_with(GrantsSweepStatus::default);
+        status.last_run_at = Some(now);
+        match res {
+            Ok(expired) => {
+                status.last_expired = expired as u64;
+                status.total_expired += expired as u64;
+                status.last_error = None;
+            },
+            Err(err) => {
+                status.last_expired = 0;
+                status.last_error = Some(format!("{:?}", err));
+            },
+        }
+    });
+}
+
+// timers don't survive upgrades, so they must be set again by post_upgrade
+fn _start_timers(
+) {
+    ic_cdk_timers::set_timer_interval(PURGE_INTERVAL, _purge_deleted);
+    ic_cdk_timers::set_timer_interval(GRANTS_SWEEP_INTERVAL, _expire_grants);
+
+    TIMERS_STARTED_AT.with(|at| at.set(ic_cdk::api::time()));
+}
+
+fn _get_grants_sweep_status(
+) -> GrantsSweepStatus {
+    let interval = GRANTS_SWEEP_INTERVAL.as_nanos() as u64;
+    let started_at = TIMERS_STARTED_AT.with(|at| at.get());
+    let now = ic_cdk::api::time();
+
+    // interval timers run at every multiple of the interval since they were set
+    let next_run_at = started_at + ((now - started_at) / interval + 1) * interval;
+
+    GrantsSweepStatus {
+        interval,
+        next_run_at: Some(next_run_at),
+        ..STATE.with(|rc| rc.borrow().grants_sweep.clone().unwrap_or_default())
+    }
+}
+
 #[derive(CandidType, Deserialize)]
 struct InitArg {
     vetkd_canister_id: String,
@@ -62,24 +175,20 @@ fn init(
         state.vetkd = VetKdUtil::new(arg.vetkd_canister_id, arg.key_name);
         state.rand = Xoshiro256ss::new(ic_cdk::api::time());
     });
+
+    migrations::record_versions();
+
+    _start_timers();
 }
 
 #[ic_cdk::pre_upgrade]
 fn pre_upgrade() {
-    let mut writter = stable::StableWriter::default();
-    
-    // must be the first
-    DB.with(|db| {
-        if let Err(err) = db.borrow().serialize(&mut writter) {
-            trap(&format!(
-                "An error occurred when saving DB to stable memory (pre_upgrade): {:?}",
-                err
-            ));
-        }
-    });
+    // tables already live in stable memory, only the STATE must be saved
+    let mut memory = get_memory(STATE_MEMORY_ID);
+    let mut writter = Writer::new(&mut memory, 0);
 
     STATE.with(|state| {
-        if let Err(err) = serialize(&state.take(), STATE_VERSION, &mut writter) {
+        if let Err(err) = serialize(&state.take(), migrations::state::VERSION, &mut writter) {
             trap(&format!(
                 "An error occurred when saving STATE to stable memory (pre_upgrade): {:?}",
                 err
@@ -90,19 +199,65 @@ fn pre_upgrade() {
 
 #[ic_cdk::post_upgrade]
 fn post_upgrade() {
-    let mut reader = stable::StableReader::default();
+    if let Err(err) = migrations::validate_registry() {
+        trap(&format!("Invalid migrations (post_upgrade): {}", err));
+    }
 
-    DB.with(|db| {
-        if let Err(err) = db.borrow_mut().deserialize(&mut reader) {
-            trap(&format!(
+    _start_timers();
+
+    if is_legacy_layout() {
+        // must be read before the memory manager gets initialized
+        let mut reader = stable::StableReader::default();
+
+        let tables = match DB::read_legacy(&mut reader) {
+            Err(err) => trap(&format!(
                 "An error occurred when loading DB from stable memory (post_upgrade): {:?}",
                 err
-            ));
-        }
-    });
+            )),
+            Ok(tables) => tables
+        };
+
+        let state_ = match deserialize(migrations::state::VERSION, migrations::migrate_state, &mut reader) {
+            Err(err) => trap(&format!(
+                "An error occurred when loading STATE from stable memory (post_upgrade): {:?}",
+                err
+            )),
+            Ok(state_) => state_
+        };
+
+        DB.with(|db| {
+            if let Err(err) = db.borrow_mut().deserialize_legacy(&tables) {
+                trap(&format!(
+                    "An error occurred when migrating DB to stable structures (post_upgrade): {:?}",
+                    err
+                ));
+            }
+        });
+
+        migrations::record_versions();
+
+        STATE.with(|state| state.replace(state_));
+        return;
+    }
+
+    // the rows must be migrated before the tables are loaded
+    let migrated = match migrations::migrate_stored() {
+        Err(err) => trap(&format!(
+            "An error occurred when migrating DB (post_upgrade): {:?}",
+            err
+        )),
+        Ok(migrated) => migrated
+    };
+
+    if !migrated.is_empty() {
+        DB.with(|db| db.borrow_mut().rebuild_indexes());
+    }
+
+    let memory = get_memory(STATE_MEMORY_ID);
+    let mut reader = Reader::new(&memory, 0);
 
     STATE.with(|state| {
-        match deserialize(STATE_VERSION, &mut reader) {
+        match deserialize(migrations::state::VERSION, migrations::migrate_state, &mut reader) {
             Err(err) =>
             trap(&format!(
                 "An error occurred when loading STATE from stable memory (post_upgrade): {:?}",
@@ -119,12 +274,13 @@ fn post_upgrade() {
 #[ic_cdk::update]
 fn user_create(
     req: UserRequest
-) -> Result<UserResponse, String> {
+) -> Result<UserResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
         let user = User::new(&req, &caller);
-        match UsersService::create(&user, &mut db.borrow_mut(), &caller) {
+        match db.borrow_mut().transaction(|db| UsersService::create(&user, db, &caller)) {
             Ok(()) => Ok(user.into()),
             Err(msg) => Err(msg)
         }
@@ -135,12 +291,13 @@ fn user_create(
 fn user_update(
     id: UserId,
     req: UserRequest
-) -> Result<UserResponse, String> {
+) -> Result<UserResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
         let user = User::new(&req, &caller);
-        match UsersService::update(&id, &user, &mut db.borrow_mut(), &caller) {
+        match db.borrow_mut().transaction(|db| UsersService::update(&id, &user, db, &caller)) {
             Ok(()) => Ok(user.into()),
             Err(msg) => Err(msg)
         }
@@ -150,17 +307,31 @@ fn user_update(
 #[ic_cdk::update]
 fn user_delete(
     id: UserId
-) -> Result<(), String> {
+) -> Result<(), Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| UsersService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::update]
+fn user_restore(
+    id: UserId
+) -> Result<(), Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
+    let retention = _get_retention();
 
     DB.with(|db| {
-        UsersService::delete(&id, &mut db.borrow_mut(), &caller)
+        db.borrow_mut().transaction(|db| UsersService::restore(&id, &retention, db, &caller))
     })
 }
 
  #[ic_cdk::query]
 fn user_find_me(
-) -> Result<UserResponse, String> {
+) -> Result<UserResponse, Error> {
     let caller = caller();
 
     DB.with(|db| {
@@ -174,7 +345,7 @@ fn user_find_me(
 #[ic_cdk::query]
 fn user_find_by_id(
     id: UserId
-) -> Result<UserResponse, String> {
+) -> Result<UserResponse, Error> {
     let caller = caller();
 
     DB.with(|db| {
@@ -190,7 +361,7 @@ fn user_find_by_key(
     kind: KeyKind,
     country: Option<String>,
     key: String,
-) -> Result<UserResponse, String> {
+) -> Result<UserResponse, Error> {
     let caller = caller();
 
     DB.with(|db| {
@@ -208,13 +379,89 @@ fn user_find_by_key(
 #[ic_cdk::query]
 fn user_find_prescriptions(
     id: UserId,
+    filter: Option<PrescriptionFilter>,
+    pag: Pagination
+) -> Result<Page<PrescriptionResponse>, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        match UsersService::find_prescriptions(&id, &filter.unwrap_or_default(), pag, &db.borrow(), &caller) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+// same as user_find_prescriptions, but as an update, so the accesses to the prescriptions shared
+// with the user are logged
+#[ic_cdk::update]
+fn user_view_prescriptions(
+    id: UserId,
+    filter: Option<PrescriptionFilter>,
     pag: Pagination
-) -> Result<Vec<PrescriptionResponse>, String> {
+) -> Result<Page<PrescriptionResponse>, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
-        match UsersService::find_prescriptions(&id, pag, &db.borrow(), &caller) {
-            Ok(list) => Ok(list.iter().map(|e| e.clone().into()).collect()),
+        match db.borrow_mut().transaction(|db| {
+            let page = UsersService::find_prescriptions(&id, &filter.unwrap_or_default(), pag, db, &caller)?;
+            AccessLogsService::log(&page.items, &PrescriptionOperation::View, _gen_id, db, &caller)?;
+            Ok(page)
+        }) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn user_find_shared_prescriptions(
+    pag: Pagination
+) -> Result<Page<SharedPrescriptionResponse>, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        match UsersService::find_shared_prescriptions(pag, &db.borrow(), &caller) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+// same as user_find_shared_prescriptions, but as an update, so the accesses are logged
+#[ic_cdk::update]
+fn user_view_shared_prescriptions(
+    pag: Pagination
+) -> Result<Page<SharedPrescriptionResponse>, Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        match db.borrow_mut().transaction(|db| {
+            let page = UsersService::find_shared_prescriptions(pag, db, &caller)?;
+            let prescriptions = page.items.iter()
+                .map(|e| e.prescription.clone())
+                .collect();
+            AccessLogsService::log(&prescriptions, &PrescriptionOperation::View, _gen_id, db, &caller)?;
+            Ok(page)
+        }) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn user_search(
+    req: SearchRequest,
+    pag: Pagination
+) -> Result<Page<UserResponse>, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        match SearchService::search_users(&req, pag, &db.borrow(), &caller) {
+            Ok(page) => Ok(page.map(|e| e.into())),
             Err(msg) => Err(msg)
         }
     })
@@ -226,12 +473,13 @@ fn user_find_prescriptions(
 #[ic_cdk::update]
 fn key_create(
     req: KeyRequest
-) -> Result<KeyResponse, String> {
+) -> Result<KeyResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|rc| {
         let key = Key::new(&req, &caller);
-        match KeysService::create(&key, &mut rc.borrow_mut(), &caller) {
+        match rc.borrow_mut().transaction(|db| KeysService::create(&key, db, &caller)) {
             Ok(()) => Ok(key.into()),
             Err(msg) => Err(msg)
         }
@@ -241,18 +489,32 @@ fn key_create(
 #[ic_cdk::update]
 fn key_delete(
     id: KeyId
-) -> Result<(), String> {
+) -> Result<(), Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| KeysService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::update]
+fn key_restore(
+    id: KeyId
+) -> Result<(), Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
+    let retention = _get_retention();
 
     DB.with(|db| {
-        KeysService::delete(&id, &mut db.borrow_mut(), &caller)
+        db.borrow_mut().transaction(|db| KeysService::restore(&id, &retention, db, &caller))
     })
 }
 
 #[ic_cdk::query]
 fn key_find_by_id(
     id: KeyId
-) -> Result<KeyResponse, String> {
+) -> Result<KeyResponse, Error> {
     let caller = &caller();
 
     DB.with(|db| {
@@ -268,7 +530,7 @@ fn key_find_by_value(
     kind: KeyKind,
     country: Option<String>,
     value: String
-) -> Result<KeyResponse, String> {
+) -> Result<KeyResponse, Error> {
     let caller = &caller();
 
     DB.with(|db| {
@@ -283,12 +545,12 @@ fn key_find_by_value(
 fn key_find_all_by_user(
     id: UserId,
     pag: Pagination
-) -> Result<Vec<KeyResponse>, String> {
+) -> Result<Page<KeyResponse>, Error> {
     let caller = &caller();
 
     DB.with(|db| {
         match KeysService::find_all_by_user(&id, pag, &db.borrow(), &caller) {
-            Ok(list) => Ok(list.iter().map(|e| e.clone().into()).collect()),
+            Ok(page) => Ok(page.map(|e| e.into())),
             Err(msg) => Err(msg)
         }
     })
@@ -300,14 +562,15 @@ fn key_find_all_by_user(
 #[ic_cdk::update]
 fn prescription_pre_create(
     req: PrescriptionPreRequest
-) -> Result<PrescriptionResponse, String> {
+) -> Result<PrescriptionResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
         let id = _gen_id();
         let prescription = Prescription::new(&id, &req, &caller);
 
-        match PrescriptionsService::pre_create(&prescription, &mut db.borrow_mut(), &caller) {
+        match db.borrow_mut().transaction(|db| PrescriptionsService::pre_create(&prescription, db, &caller)) {
             Ok(_) => Ok(prescription.into()),
             Err(msg) => Err(msg)
         }
@@ -318,8 +581,9 @@ fn prescription_pre_create(
 fn prescription_post_create(
     id: PrescriptionId,
     req: PrescriptionPostRequest
-) -> Result<PrescriptionResponse, String> {
+) -> Result<PrescriptionResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
         match PrescriptionsService::post_create(&id, &req, &mut db.borrow_mut(), &caller) {
@@ -332,18 +596,32 @@ fn prescription_post_create(
 #[ic_cdk::update]
 fn prescription_delete(
     id: PrescriptionId
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
-        PrescriptionsService::delete(&id, &mut db.borrow_mut(), &caller)
+        db.borrow_mut().transaction(|db| PrescriptionsService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::update]
+fn prescription_restore(
+    id: PrescriptionId
+) -> Result<(), Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+    let retention = _get_retention();
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| PrescriptionsService::restore(&id, &retention, db, &caller))
     })
 }
 
 #[ic_cdk::query]
 fn prescription_find_by_id(
     id: PrescriptionId
-) -> Result<PrescriptionResponse, String> {
+) -> Result<PrescriptionResponse, Error> {
     let caller = caller();
 
     DB.with(|db| {
@@ -354,9 +632,40 @@ fn prescription_find_by_id(
     })
 }
 
+// same as prescription_find_by_id, but as an update, so the access is logged
+#[ic_cdk::update]
+fn prescription_view(
+    id: PrescriptionId
+) -> Result<PrescriptionResponse, Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        match db.borrow_mut().transaction(|db| {
+            let prescription = PrescriptionsService::find_by_id(&id, db, &caller)?;
+            AccessLogsService::log(&vec![prescription.clone()], &PrescriptionOperation::View, _gen_id, db, &caller)?;
+            Ok(prescription)
+        }) {
+            Ok(e) => Ok(e.into()),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn prescription_verify(
+    id: PrescriptionId
+) -> Result<PrescriptionVerificationReport, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        PrescriptionsService::verify(&id, &db.borrow(), &caller)
+    })
+}
+
 #[ic_cdk::update]
 async fn prescription_get_public_key(
-) -> Result<String, String> {
+) -> Result<String, Error> {
     let caller = caller();
 
     match DB.with(|db| {
@@ -377,8 +686,9 @@ async fn prescription_get_public_key(
 async fn prescription_get_encrypted_symmetric_key(
     id: String,
     encryption_public_key: Vec<u8>
-) -> Result<String, String> {
+) -> Result<String, Error> {
     let caller = &caller();
+    MaintenanceService::check_writable()?;
 
     let prescription = match DB.with(|db| {
         let db = &db.borrow();
@@ -386,19 +696,61 @@ async fn prescription_get_encrypted_symmetric_key(
             return Err(err);
         }
 
-        PrescriptionsService::find_by_id(&id, db, caller)
+        // an explicit read capable grant is required to decrypt the prescription
+        PrescriptionsService::find_for(&id, &PrescriptionOperation::FetchKey, db, caller)
     }) {
         Err(msg) => return Err(msg),
         Ok(pres) => pres
     };
 
-    STATE.with(move |state| {
+    let key = STATE.with(|state| {
         PrescriptionsService::get_encrypted_symmetric_key(
-            prescription,
+            prescription.clone(),
             encryption_public_key,
             state.borrow().vetkd.clone()
         )
-    }).await
+    }).await?;
+
+    // only the keys actually delivered are logged. A maintenance may have started while the key was derived
+    MaintenanceService::check_writable()?;
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| 
+            AccessLogsService::log(&vec![prescription], &PrescriptionOperation::FetchKey, _gen_id, db, caller)
+        )
+    })?;
+
+    Ok(key)
+}
+
+/*
+ * prescriptions access log facade
+ */
+#[ic_cdk::query]
+fn access_log_find_by_patient(
+    pag: Pagination
+) -> Result<Page<AccessLogResponse>, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        match AccessLogsService::find_by_patient(pag, &db.borrow(), &caller) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn access_log_find_by_doctor(
+    pag: Pagination
+) -> Result<Page<AccessLogResponse>, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        match AccessLogsService::find_by_doctor(pag, &db.borrow(), &caller) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
 }
 
 /*
@@ -407,14 +759,15 @@ async fn prescription_get_encrypted_symmetric_key(
 #[ic_cdk::update]
 fn prescription_auth_create(
     req: PrescriptionAuthRequest
-) -> Result<PrescriptionAuthResponse, String> {
+) -> Result<PrescriptionAuthResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|rc| {
         let id = _gen_id();
         let auth = PrescriptionAuth::new(&id, &req, &caller);
-        match PrescriptionAuthsService::create(&auth, &mut rc.borrow_mut(), &caller) {
-            Ok(()) => Ok(auth.into()),
+        match rc.borrow_mut().transaction(|db| PrescriptionAuthsService::create(&auth, db, &caller)) {
+            Ok(auth) => Ok(auth.into()),
             Err(msg) => Err(msg)
         }
     })
@@ -423,18 +776,32 @@ fn prescription_auth_create(
 #[ic_cdk::update]
 fn prescription_auth_delete(
     id: PrescriptionAuthId
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|rc| {
-        PrescriptionAuthsService::delete(&id, &mut rc.borrow_mut(), &caller)
+        rc.borrow_mut().transaction(|db| PrescriptionAuthsService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::update]
+fn prescription_auth_restore(
+    id: PrescriptionAuthId
+) -> Result<(), Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+    let retention = _get_retention();
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| PrescriptionAuthsService::restore(&id, &retention, db, &caller))
     })
 }
 
 #[ic_cdk::query]
 fn prescription_auth_find_by_id(
     id: PrescriptionAuthId
-) -> Result<PrescriptionAuthResponse, String> {
+) -> Result<PrescriptionAuthResponse, Error> {
     DB.with(|db| {
         match PrescriptionAuthsService::find_by_id(&id, &db.borrow(), &caller()) {
             Ok(e) => Ok(e.into()),
@@ -445,29 +812,42 @@ fn prescription_auth_find_by_id(
 
 #[ic_cdk::query]
 fn prescription_auth_find_by_prescription(
-    id: PrescriptionId
-) -> Result<Vec<PrescriptionAuthResponse>, String> {
+    id: PrescriptionId,
+    pag: Pagination
+) -> Result<Page<PrescriptionAuthResponse>, Error> {
     DB.with(|db| {
-        match PrescriptionAuthsService::find_by_prescription(&id, &db.borrow(), &caller()) {
-            Ok(list) => Ok(list.iter().map(|e| e.clone().into()).collect()),
+        match PrescriptionAuthsService::find_by_prescription(&id, pag, &db.borrow(), &caller()) {
+            Ok(page) => Ok(page.map(|e| e.into())),
             Err(msg) => Err(msg)
         }
     })
 }
 
+#[ic_cdk::query]
+fn prescription_auth_sweep_status(
+) -> Result<GrantsSweepStatus, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(_get_grants_sweep_status())
+}
+
 /*
  * groups facade
  */
 #[ic_cdk::update]
 fn group_create(
     req: GroupRequest
-) -> Result<GroupResponse, String> {
+) -> Result<GroupResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
         let id = _gen_id();
         let group = Group::new(&id, &req, &caller);
-        match GroupsService::create(&group, &mut db.borrow_mut(), &caller) {
+        match db.borrow_mut().transaction(|db| GroupsService::create(&group, db, &caller)) {
             Ok(()) => Ok(group.into()),
             Err(msg) => Err(msg)
         }
@@ -478,12 +858,13 @@ fn group_create(
 fn group_update(
     id: GroupId,
     req: GroupRequest
-) -> Result<GroupResponse, String> {
+) -> Result<GroupResponse, Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
         let group = Group::new(&id, &req, &caller);
-        match GroupsService::update(&id, &group, &mut db.borrow_mut(), &caller) {
+        match db.borrow_mut().transaction(|db| GroupsService::update(&id, &group, db, &caller)) {
             Ok(()) => Ok(group.into()),
             Err(msg) => Err(msg)
         }
@@ -493,18 +874,19 @@ fn group_update(
 #[ic_cdk::update]
 fn group_delete(
     id: GroupId
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let caller = caller();
+    MaintenanceService::check_writable()?;
 
     DB.with(|db| {
-        GroupsService::delete(&id, &mut db.borrow_mut(), &caller)
+        db.borrow_mut().transaction(|db| GroupsService::delete(&id, db, &caller))
     })
 }
 
 #[ic_cdk::query]
 fn group_find_by_id(
     id: GroupId
-) -> Result<GroupResponse, String> {
+) -> Result<GroupResponse, Error> {
     DB.with(|db| {
         match GroupsService::find_by_id(&id, &db.borrow()) {
             Ok(e) => Ok(e.into()),
@@ -517,13 +899,378 @@ fn group_find_by_id(
 fn group_find_all_by_user(
     id: UserId,
     pag: Pagination
-) -> Result<Vec<GroupResponse>, String> {
+) -> Result<Page<GroupResponse>, Error> {
     let caller = &caller();
 
     DB.with(|db| {
         match GroupsService::find_all_by_user(&id, pag, &db.borrow(), &caller) {
-            Ok(list) => Ok(list.iter().map(|e| e.clone().into()).collect()),
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+/*
+ * prescription templates facade
+ */
+#[ic_cdk::update]
+fn template_create(
+    req: PrescriptionTemplateRequest
+) -> Result<PrescriptionTemplateResponse, Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        let id = _gen_id();
+        let template = PrescriptionTemplate::new(&id, &req, &caller);
+        match db.borrow_mut().transaction(|db| PrescriptionTemplatesService::create(&template, db, &caller)) {
+            Ok(()) => Ok(template.into()),
             Err(msg) => Err(msg)
         }
     })
-}
\ No newline at end of file
+}
+
+#[ic_cdk::update]
+fn template_update(
+    id: PrescriptionTemplateId,
+    req: PrescriptionTemplateRequest
+) -> Result<PrescriptionTemplateResponse, Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        let template = PrescriptionTemplate::new(&id, &req, &caller);
+        match db.borrow_mut().transaction(|db| PrescriptionTemplatesService::update(&id, &template, db, &caller)) {
+            Ok(template) => Ok(template.into()),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::update]
+fn template_delete(
+    id: PrescriptionTemplateId
+) -> Result<(), Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| PrescriptionTemplatesService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::update]
+fn template_restore(
+    id: PrescriptionTemplateId
+) -> Result<(), Error> {
+    let caller = caller();
+    MaintenanceService::check_writable()?;
+    let retention = _get_retention();
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| PrescriptionTemplatesService::restore(&id, &retention, db, &caller))
+    })
+}
+
+#[ic_cdk::query]
+fn template_find_by_id(
+    id: PrescriptionTemplateId
+) -> Result<PrescriptionTemplateResponse, Error> {
+    let caller = caller();
+
+    DB.with(|db| {
+        match PrescriptionTemplatesService::find_by_id(&id, &db.borrow(), &caller) {
+            Ok(e) => Ok(e.into()),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn template_find_all_by_doctor(
+    id: UserId,
+    pag: Pagination
+) -> Result<Page<PrescriptionTemplateResponse>, Error> {
+    let caller = &caller();
+
+    DB.with(|db| {
+        match PrescriptionTemplatesService::find_all_by_doctor(&id, pag, &db.borrow(), &caller) {
+            Ok(page) => Ok(page.map(|e| e.into())),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+/*
+ * trusted root certificates facade
+ */
+#[ic_cdk::update]
+fn trusted_cert_create(
+    req: TrustedCertRequest
+) -> Result<TrustedCertResponse, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        match db.borrow_mut().transaction(|db| TrustedCertsService::create(&req, db, &caller)) {
+            Ok(e) => Ok(e.into()),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::update]
+fn trusted_cert_delete(
+    id: TrustedCertId
+) -> Result<(), Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| TrustedCertsService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::query]
+fn trusted_cert_find_all(
+) -> Result<Vec<TrustedCertResponse>, Error> {
+    DB.with(|db| {
+        Ok(TrustedCertsService::find_all(&db.borrow()).into_iter().map(|e| e.into()).collect())
+    })
+}
+
+/*
+ * certificate revocation lists facade
+ */
+#[ic_cdk::update]
+fn crl_upload(
+    req: CrlRequest
+) -> Result<CrlResponse, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        match db.borrow_mut().transaction(|db| CrlsService::create(&req, db, &caller)) {
+            Ok(e) => Ok(e.into()),
+            Err(msg) => Err(msg)
+        }
+    })
+}
+
+#[ic_cdk::update]
+fn crl_delete(
+    id: CrlId
+) -> Result<(), Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    MaintenanceService::check_writable()?;
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| CrlsService::delete(&id, db, &caller))
+    })
+}
+
+#[ic_cdk::query]
+fn crl_find_all(
+) -> Result<Vec<CrlResponse>, Error> {
+    DB.with(|db| {
+        Ok(CrlsService::find_all(&db.borrow()).into_iter().map(|e| e.into()).collect())
+    })
+}
+
+/*
+ * retention facade
+ */
+#[ic_cdk::query]
+fn retention_get(
+) -> Result<RetentionConfig, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(_get_retention())
+}
+
+#[ic_cdk::update]
+fn retention_set(
+    config: RetentionConfig
+) -> Result<RetentionConfig, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    MaintenanceService::check_writable()?;
+
+    config.validate()?;
+
+    STATE.with(|rc| rc.borrow_mut().retention = Some(config.clone()));
+
+    Ok(config)
+}
+
+/*
+ * relations facade
+ */
+#[ic_cdk::query]
+fn relations_check(
+) -> Result<ConsistencyReport, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        Ok(db.borrow().check_relations())
+    })
+}
+
+#[ic_cdk::update]
+fn relations_rebuild(
+) -> Result<RebuildStatus, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    // runs in chunks: it must be called until the status returned is done
+    DB.with(|db| {
+        RelationsService::rebuild(&mut db.borrow_mut(), ic_cdk::api::time())
+    })
+}
+
+/*
+ * snapshots facade
+ */
+#[ic_cdk::query]
+fn snapshot_manifest(
+) -> Result<SnapshotManifest, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        Ok(SnapshotsService::get_manifest(&db.borrow()))
+    })
+}
+
+#[ic_cdk::update]
+fn snapshot_export_begin(
+) -> Result<SnapshotManifest, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        SnapshotsService::export_begin(&db.borrow(), ic_cdk::api::time())
+    })
+}
+
+#[ic_cdk::query]
+fn snapshot_export(
+    req: SnapshotExportRequest
+) -> Result<SnapshotChunk, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        SnapshotsService::export(&req, &db.borrow())
+    })
+}
+
+#[ic_cdk::update]
+fn snapshot_export_finish(
+) -> Result<(), Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    SnapshotsService::export_finish()
+}
+
+#[ic_cdk::update]
+fn snapshot_import_begin(
+    manifest: SnapshotManifest
+) -> Result<SnapshotImportStatus, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        SnapshotsService::import_begin(&manifest, &mut db.borrow_mut())
+    })
+}
+
+#[ic_cdk::update]
+fn snapshot_import_chunk(
+    chunk: SnapshotChunk
+) -> Result<SnapshotImportStatus, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        db.borrow_mut().transaction(|db| SnapshotsService::import_chunk(&chunk, db))
+    })
+}
+
+#[ic_cdk::update]
+fn snapshot_import_finish(
+) -> Result<RebuildStatus, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    // the chunks of the rebuild run in their own transactions
+    DB.with(|db| {
+        SnapshotsService::import_finish(&mut db.borrow_mut())
+    })
+}
+
+#[ic_cdk::update]
+fn snapshot_import_abort(
+) -> Result<(), Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    DB.with(|db| {
+        SnapshotsService::import_abort(&mut db.borrow_mut())
+    })
+}
+
+#[ic_cdk::query]
+fn snapshot_import_status(
+) -> Result<SnapshotImportStatus, Error> {
+    let caller = caller();
+    if !_is_owner(&caller) {
+        return Err(Error::Forbidden);
+    }
+
+    SnapshotsService::import_status()
+}
@@ -0,0 +1,74 @@
+use candid::{Principal, CandidType};
+use serde::Deserialize;
+use super::group::GroupId;
+use super::prescription::{Prescription, PrescriptionId};
+use super::prescription_auth::{PrescriptionAuth, PrescriptionAuthId, PrescriptionAuthSubject, PrescriptionOperation};
+use super::user::UserId;
+
+pub type AccessLogId = String;
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct AccessLog {
+    pub id: AccessLogId,
+    pub prescription_id: PrescriptionId,
+    pub patient: UserId,
+    pub doctor: UserId,
+    pub operation: PrescriptionOperation,
+    // the grant used, and the group it was granted to, if any
+    pub auth_id: Option<PrescriptionAuthId>,
+    pub group_id: Option<GroupId>,
+    pub accessed_at: u64,
+    pub accessed_by: Principal,
+}
+
+#[derive(CandidType)]
+pub struct AccessLogResponse {
+    id: AccessLogId,
+    prescription_id: PrescriptionId,
+    operation: PrescriptionOperation,
+    auth_id: Option<PrescriptionAuthId>,
+    group_id: Option<GroupId>,
+    accessed_at: u64,
+    accessed_by: Principal,
+}
+
+impl AccessLog {
+    pub fn new(
+        id: &String,
+        prescription: &Prescription,
+        operation: &PrescriptionOperation,
+        auth: Option<&PrescriptionAuth>,
+        caller: &Principal
+    ) -> Self {
+        Self {
+            id: id.clone(),
+            prescription_id: prescription.id.clone(),
+            patient: prescription.patient,
+            doctor: prescription.doctor,
+            operation: operation.clone(),
+            auth_id: auth.map(|e| e.id.clone()),
+            group_id: auth.and_then(|e| match &e.to {
+                PrescriptionAuthSubject::Group(group) => Some(group.clone()),
+                PrescriptionAuthSubject::User(_) => None,
+            }),
+            accessed_at: ic_cdk::api::time(),
+            accessed_by: caller.clone(),
+        }
+    }
+}
+
+impl From<AccessLog> for AccessLogResponse {
+    fn from(
+        e: AccessLog
+    ) -> Self {
+        Self {
+            id: e.id,
+            prescription_id: e.prescription_id,
+            operation: e.operation,
+            auth_id: e.auth_id,
+            group_id: e.group_id,
+            accessed_at: e.accessed_at,
+            accessed_by: e.accessed_by,
+        }
+    }
+}
\ No newline at end of file
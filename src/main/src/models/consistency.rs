@@ -0,0 +1,67 @@
+use candid::CandidType;
+use serde::Deserialize;
+use crate::db::TableName;
+
+// max number of divergent keys listed per table
+pub const MAX_DIVERGENCES: usize = 32;
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct ConsistencyTableReport {
+    pub table: TableName,
+    pub rows: u64,
+    pub expected_rows: u64,
+    pub missing: u64,
+    pub unexpected: u64,
+    pub different: u64,
+    pub divergences: Vec<String>,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct ConsistencyReport {
+    pub consistent: bool,
+    pub tables: Vec<ConsistencyTableReport>,
+    pub checked_at: u64,
+}
+
+impl ConsistencyTableReport {
+    pub fn new(
+        table: TableName
+    ) -> Self {
+        Self {
+            table,
+            rows: 0,
+            expected_rows: 0,
+            missing: 0,
+            unexpected: 0,
+            different: 0,
+            divergences: vec![],
+        }
+    }
+
+    pub fn add_divergence(
+        &mut self,
+        divergence: String
+    ) {
+        if self.divergences.len() < MAX_DIVERGENCES {
+            self.divergences.push(divergence);
+        }
+    }
+
+    pub fn is_consistent(
+        &self
+    ) -> bool {
+        self.missing == 0 && self.unexpected == 0 && self.different == 0
+    }
+}
+
+// progress of a rebuild of the relation tables and indexes, which runs in many messages
+#[derive(CandidType, Clone, Deserialize)]
+pub struct RebuildStatus {
+    // primary table whose rows are being processed
+    pub table: TableName,
+    // key of the last row processed, None when starting on the table
+    pub cursor: Option<Vec<u8>>,
+    pub rows: u64,
+    pub done: bool,
+    pub started_at: u64,
+}
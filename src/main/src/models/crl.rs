@@ -0,0 +1,82 @@
+use std::collections::BTreeSet;
+use candid::{Principal, CandidType};
+use serde::Deserialize;
+
+pub type CrlId = String;
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct Crl {
+    pub id: CrlId,
+    pub issuer: String,
+    pub this_update: u64,
+    pub next_update: Option<u64>,
+    pub revoked: BTreeSet<String>,
+    pub created_at: u64,
+    pub created_by: Principal,
+    pub updated_at: Option<u64>,
+    pub updated_by: Option<Principal>,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct CrlRequest {
+    pub crl: Vec<u8>,
+    pub issuer_cert: Option<String>,
+}
+
+#[derive(CandidType)]
+pub struct CrlResponse {
+    id: CrlId,
+    issuer: String,
+    this_update: u64,
+    next_update: Option<u64>,
+    revoked_count: u32,
+    stale: bool,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+impl Crl {
+    pub fn is_stale(
+        &self,
+        now: u64
+    ) -> bool {
+        match self.next_update {
+            None => false,
+            Some(next_update) => now > next_update,
+        }
+    }
+}
+
+impl From<Crl> for CrlResponse {
+    fn from(
+        e: Crl
+    ) -> Self {
+        Self {
+            stale: e.is_stale(ic_cdk::api::time()),
+            id: e.id,
+            issuer: e.issuer,
+            this_update: e.this_update,
+            next_update: e.next_update,
+            revoked_count: e.revoked.len() as u32,
+            created_at: e.created_at,
+            updated_at: e.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::crl;
+
+    #[test]
+    fn is_stale_after_the_next_update() {
+        assert!(!crl(Some(100)).is_stale(99));
+        assert!(!crl(Some(100)).is_stale(100));
+        assert!(crl(Some(100)).is_stale(101));
+    }
+
+    #[test]
+    fn is_never_stale_without_a_next_update() {
+        assert!(!crl(None).is_stale(u64::MAX));
+    }
+}
@@ -3,16 +3,27 @@ use serde::Deserialize;
 
 use crate::models::prescription_template::PrescriptionTemplateId;
 
+#[derive(CandidType, Clone, Deserialize, PartialEq, Debug)]
+pub enum LicenseAuthority {
+    IcpBrasil,
+    SubjectSerialNumber,
+    Eidas,
+}
+
 #[derive(CandidType, Clone, Deserialize)]
 pub struct Doctor {
     pub license_num: String,
-    pub prescription_template: Option<String>,
+    pub country: Option<String>,
+    pub license_authority: Option<LicenseAuthority>,
+    pub prescription_template: Option<PrescriptionTemplateId>,
     pub cert: String,
 }
 
 #[derive(CandidType, Deserialize, Clone)]
 pub struct DoctorRequest {
     license_num: String,
+    country: String,
+    license_authority: LicenseAuthority,
     prescription_template: Option<PrescriptionTemplateId>,
     cert: String,
 }
@@ -20,6 +31,8 @@ pub struct DoctorRequest {
 #[derive(CandidType)]
 pub struct DoctorResponse {
     license_num: String,
+    country: Option<String>,
+    license_authority: Option<LicenseAuthority>,
     prescription_template: Option<PrescriptionTemplateId>,
 }
 
@@ -29,7 +42,9 @@ impl Doctor {
     ) -> Self {
         Self {
             license_num: e.license_num.clone(),
-            prescription_template: None,
+            country: Some(e.country.clone()),
+            license_authority: Some(e.license_authority.clone()),
+            prescription_template: e.prescription_template.clone(),
             cert: e.cert.clone(),
         }
     }
@@ -47,6 +62,8 @@ impl From<Doctor> for DoctorResponse {
     ) -> Self {
         Self { 
             license_num: e.license_num,
+            country: e.country,
+            license_authority: e.license_authority,
             prescription_template: e.prescription_template,
         }
     }
@@ -58,6 +75,8 @@ impl From<DoctorRequest> for Doctor {
     ) -> Self {
         Self {
             license_num: e.license_num,
+            country: Some(e.country),
+            license_authority: Some(e.license_authority),
             prescription_template: e.prescription_template,
             cert: e.cert,
         }
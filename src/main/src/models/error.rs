@@ -0,0 +1,121 @@
+use std::fmt;
+use candid::CandidType;
+use serde::Deserialize;
+
+#[derive(CandidType, Clone, Deserialize, Debug, PartialEq)]
+pub enum Error {
+    NotFound {
+        entity: String,
+        id: String,
+    },
+    Forbidden,
+    Unauthenticated,
+    Validation {
+        field: String,
+        reason: String,
+    },
+    Conflict {
+        entity: String,
+        id: String,
+    },
+    InvalidState {
+        reason: String,
+    },
+    CryptoFailure {
+        reason: String,
+    },
+    Internal {
+        reason: String,
+    },
+}
+
+impl Error {
+    pub fn not_found(
+        entity: &str,
+        id: &impl ToString
+    ) -> Self {
+        Self::NotFound {
+            entity: entity.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    pub fn conflict(
+        entity: &str,
+        id: &impl ToString
+    ) -> Self {
+        Self::Conflict {
+            entity: entity.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    pub fn validation(
+        field: &str,
+        reason: &str
+    ) -> Self {
+        Self::Validation {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_state(
+        reason: &str
+    ) -> Self {
+        Self::InvalidState {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn crypto_failure(
+        reason: &str
+    ) -> Self {
+        Self::CryptoFailure {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn internal(
+        reason: &str
+    ) -> Self {
+        Self::Internal {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound { entity, id } => write!(f, "Not found: {} {}", entity, id),
+            Error::Forbidden => write!(f, "Forbidden"),
+            Error::Unauthenticated => write!(f, "Anonymous not allowed"),
+            Error::Validation { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+            Error::Conflict { entity, id } => write!(f, "Duplicated {}: {}", entity, id),
+            Error::InvalidState { reason } => write!(f, "Invalid state: {}", reason),
+            Error::CryptoFailure { reason } => write!(f, "Crypto failure: {}", reason),
+            Error::Internal { reason } => write!(f, "Internal error: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helpers_build_the_variants() {
+        assert_eq!(Error::not_found("user", &"u1"), Error::NotFound { entity: "user".to_string(), id: "u1".to_string() });
+        assert_eq!(Error::conflict("key", &1), Error::Conflict { entity: "key".to_string(), id: "1".to_string() });
+        assert_eq!(Error::validation("title", "Empty"), Error::Validation { field: "title".to_string(), reason: "Empty".to_string() });
+    }
+
+    #[test]
+    fn errors_are_displayed_with_their_details() {
+        assert_eq!(Error::not_found("user", &"u1").to_string(), "Not found: user u1");
+        assert_eq!(Error::validation("title", "Empty").to_string(), "Invalid title: Empty");
+        assert_eq!(Error::invalid_state("Expired").to_string(), "Invalid state: Expired");
+        assert_eq!(Error::Unauthenticated.to_string(), "Anonymous not allowed");
+    }
+}
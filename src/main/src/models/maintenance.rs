@@ -0,0 +1,28 @@
+use std::fmt;
+
+use candid::CandidType;
+use serde::Deserialize;
+
+#[derive(CandidType, Clone, Deserialize, Eq, PartialEq, Debug)]
+pub enum MaintenanceKind {
+    SnapshotExport,
+    SnapshotImport,
+    RelationsRebuild,
+}
+
+impl fmt::Display for MaintenanceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MaintenanceKind::SnapshotExport => write!(f, "snapshot export"),
+            MaintenanceKind::SnapshotImport => write!(f, "snapshot import"),
+            MaintenanceKind::RelationsRebuild => write!(f, "relations rebuild"),
+        }
+    }
+}
+
+// while a maintenance is in progress the DB is read-only, except for the maintenance itself
+#[derive(CandidType, Clone, Deserialize)]
+pub struct Maintenance {
+    pub kind: MaintenanceKind,
+    pub started_at: u64,
+}
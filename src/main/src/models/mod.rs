@@ -1,4 +1,5 @@
 pub mod doctor;
+pub mod error;
 pub mod key;
 pub mod patient;
 pub mod prescription_auth;
@@ -7,4 +8,12 @@ pub mod prescription;
 pub mod staff;
 pub mod thirdparty;
 pub mod user;
-pub mod group;
\ No newline at end of file
+pub mod group;
+pub mod trusted_cert;
+pub mod crl;
+pub mod snapshot;
+pub mod consistency;
+pub mod retention;
+pub mod search;
+pub mod access_log;
+pub mod maintenance;
\ No newline at end of file
@@ -1,6 +1,8 @@
 use candid::{Principal, CandidType};
 use serde::Deserialize;
 
+use super::group::GroupId;
+use super::prescription_auth::{PrescriptionAuth, PrescriptionAuthId, PrescriptionAuthKind, PrescriptionAuthSubject};
 use super::user::UserId;
 
 pub type PrescriptionId = String;
@@ -12,6 +14,19 @@ pub enum PrescriptionState {
     Deleted,
 }
 
+#[derive(CandidType, Clone, Deserialize, PartialEq, Debug)]
+pub enum SignatureAlgorithm {
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha384,
+    RsaPkcs1Sha512,
+    RsaPssSha256,
+    RsaPssSha384,
+    RsaPssSha512,
+    EcdsaSha256,
+    EcdsaSha384,
+    EcdsaSha512,
+}
+
 #[derive(CandidType, Clone, Deserialize)]
 pub struct Prescription {
     pub id: PrescriptionId,
@@ -22,7 +37,10 @@ pub struct Prescription {
     pub cipher_text_hash: Option<Vec<u8>>,
     pub cipher_text: Option<Vec<u8>>,
     pub signature: Option<Vec<u8>>,
+    pub signature_algorithm: Option<SignatureAlgorithm>,
     pub cert: Option<String>,
+    pub cms: Option<Vec<u8>>,
+    pub signed_at: Option<u64>,
     pub created_at: u64,
     pub created_by: Principal,
     pub deleted_at: Option<u64>,
@@ -35,12 +53,36 @@ pub struct PrescriptionPreRequest {
     pub plain_text_hash: Vec<u8>,
 }
 
+#[derive(CandidType, Clone, Deserialize)]
+pub enum PrescriptionSignature {
+    Raw {
+        signature: Vec<u8>,
+        algorithm: SignatureAlgorithm,
+        cert: String,
+    },
+    Cms(Vec<u8>),
+}
+
 #[derive(CandidType, Clone, Deserialize)]
 pub struct PrescriptionPostRequest {
     pub cipher_text_hash: Vec<u8>,
     pub cipher_text: Vec<u8>,
-    pub signature: Vec<u8>,
-    pub cert: String,
+    pub signature: PrescriptionSignature,
+}
+
+#[derive(CandidType, Clone, Deserialize, Default)]
+pub struct PrescriptionFilter {
+    pub created_from: Option<u64>,
+    pub created_to: Option<u64>,
+    // the doctor or the patient of the prescription
+    pub counterparty: Option<UserId>,
+    // Signed if None. Prescriptions in other states are only listed to their doctor
+    pub state: Option<PrescriptionState>,
+    // prescriptions with an unexpired share to this subject
+    pub shared_with: Option<PrescriptionAuthSubject>,
+    // prescriptions with an unexpired share that expires in [share_expires_from, share_expires_to]
+    pub share_expires_from: Option<u64>,
+    pub share_expires_to: Option<u64>,
 }
 
 #[derive(CandidType, Clone)]
@@ -53,6 +95,51 @@ pub struct PrescriptionResponse {
     created_at: u64,
 }
 
+// a prescription shared with the user, with the grants that give access to it
+#[derive(CandidType, Clone)]
+pub struct SharedPrescription {
+    pub prescription: Prescription,
+    pub grants: Vec<PrescriptionAuth>,
+}
+
+#[derive(CandidType)]
+pub struct SharedGrantResponse {
+    id: PrescriptionAuthId,
+    kind: PrescriptionAuthKind,
+    from: Principal,
+    // the group the prescription was shared with, if not with the user directly
+    group: Option<GroupId>,
+    expires_at: Option<u64>,
+}
+
+#[derive(CandidType)]
+pub struct SharedPrescriptionResponse {
+    prescription: PrescriptionResponse,
+    grants: Vec<SharedGrantResponse>,
+}
+
+#[derive(CandidType, Clone)]
+pub struct PrescriptionVerificationCheck {
+    name: String,
+    passed: bool,
+    reason: Option<String>,
+}
+
+#[derive(CandidType, Clone)]
+pub struct PrescriptionVerificationReport {
+    pub id: PrescriptionId,
+    pub valid: bool,
+    pub signer: Option<String>,
+    pub license_num: Option<String>,
+    pub serial: Option<String>,
+    pub algorithm: Option<SignatureAlgorithm>,
+    pub signed_at: Option<u64>,
+    pub not_before: Option<u64>,
+    pub not_after: Option<u64>,
+    pub verified_at: u64,
+    pub checks: Vec<PrescriptionVerificationCheck>,
+}
+
 impl Prescription {
     pub fn new(
         id: &String,
@@ -66,7 +153,10 @@ impl Prescription {
             patient: e.patient, 
             plain_text_hash: e.plain_text_hash.clone(),
             signature: None,
+            signature_algorithm: None,
             cert: None,
+            cms: None,
+            signed_at: None,
             cipher_text_hash: None,
             cipher_text: None, 
             created_at: ic_cdk::api::time(), 
@@ -77,6 +167,59 @@ impl Prescription {
     }
 }
 
+impl PrescriptionFilter {
+    // checks the prescription's own fields, as listed to user
+    pub fn accepts(
+        &self,
+        e: &Prescription,
+        user: &UserId
+    ) -> bool {
+        let state = self.state.clone().unwrap_or(PrescriptionState::Signed);
+        
+        e.state == state &&
+            (state == PrescriptionState::Signed || e.doctor == *user) &&
+            self.created_from.map_or(true, |from| e.created_at >= from) &&
+            self.created_to.map_or(true, |to| e.created_at <= to) &&
+            self.counterparty.map_or(true, |other| e.doctor == other || e.patient == other)
+    }
+
+    pub fn filters_shares(
+        &self
+    ) -> bool {
+        self.shared_with.is_some() || 
+            self.share_expires_from.is_some() || 
+                self.share_expires_to.is_some()
+    }
+
+    // checks one of the prescription's shares, not expired at now
+    pub fn accepts_share(
+        &self,
+        auth: &PrescriptionAuth,
+        now: u64
+    ) -> bool {
+        if auth.is_expired(now) {
+            return false;
+        }
+
+        if let Some(shared_with) = &self.shared_with {
+            if *shared_with != auth.to {
+                return false;
+            }
+        }
+
+        if self.share_expires_from.is_some() || self.share_expires_to.is_some() {
+            return match auth.expires_at {
+                None => false,
+                Some(at) => 
+                    self.share_expires_from.map_or(true, |from| at >= from) &&
+                    self.share_expires_to.map_or(true, |to| at <= to),
+            };
+        }
+
+        true
+    }
+}
+
 impl From<Prescription> for PrescriptionResponse {
     fn from(
         e: Prescription
@@ -90,4 +233,187 @@ impl From<Prescription> for PrescriptionResponse {
             created_at: e.created_at,
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<PrescriptionAuth> for SharedGrantResponse {
+    fn from(
+        e: PrescriptionAuth
+    ) -> Self {
+        Self {
+            id: e.id,
+            kind: e.kind,
+            from: e.from,
+            group: match e.to {
+                PrescriptionAuthSubject::Group(group) => Some(group),
+                PrescriptionAuthSubject::User(_) => None,
+            },
+            expires_at: e.expires_at,
+        }
+    }
+}
+
+impl From<SharedPrescription> for SharedPrescriptionResponse {
+    fn from(
+        e: SharedPrescription
+    ) -> Self {
+        Self {
+            prescription: e.prescription.into(),
+            grants: e.grants.into_iter().map(|g| g.into()).collect(),
+        }
+    }
+}
+
+impl PrescriptionVerificationReport {
+    pub fn new(
+        e: &Prescription
+    ) -> Self {
+        Self {
+            id: e.id.clone(),
+            valid: true,
+            signer: None,
+            license_num: None,
+            serial: None,
+            algorithm: e.signature_algorithm.clone(),
+            signed_at: e.signed_at,
+            not_before: None,
+            not_after: None,
+            verified_at: ic_cdk::api::time(),
+            checks: vec![],
+        }
+    }
+
+    pub fn add_check(
+        &mut self,
+        name: &str,
+        res: Result<(), String>
+    ) -> bool {
+        let passed = res.is_ok();
+        
+        self.checks.push(PrescriptionVerificationCheck { 
+            name: name.to_string(), 
+            passed, 
+            reason: res.err(),
+        });
+        self.valid = self.valid && passed;
+        
+        passed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{auth, prescription, principal};
+    use super::*;
+
+    fn doctor(
+    ) -> UserId {
+        principal(1)
+    }
+
+    fn patient(
+    ) -> UserId {
+        principal(2)
+    }
+
+    fn with(
+        state: PrescriptionState,
+        created_at: u64
+    ) -> Prescription {
+        Prescription {
+            state,
+            created_at,
+            ..prescription("p1")
+        }
+    }
+
+    fn group(
+    ) -> PrescriptionAuthSubject {
+        PrescriptionAuthSubject::Group("g1".to_string())
+    }
+
+    fn share(
+        to: PrescriptionAuthSubject,
+        expires_at: Option<u64>
+    ) -> PrescriptionAuth {
+        PrescriptionAuth {
+            to,
+            expires_at,
+            ..auth("a1", PrescriptionAuthKind::Read)
+        }
+    }
+
+    #[test]
+    fn lists_only_signed_prescriptions_by_default() {
+        let filter = PrescriptionFilter::default();
+        assert!(filter.accepts(&with(PrescriptionState::Signed, 0), &patient()));
+        assert!(!filter.accepts(&with(PrescriptionState::Created, 0), &doctor()));
+    }
+
+    #[test]
+    fn unsigned_prescriptions_are_listed_to_their_doctor_only() {
+        let filter = PrescriptionFilter {
+            state: Some(PrescriptionState::Created),
+            ..Default::default()
+        };
+        assert!(filter.accepts(&with(PrescriptionState::Created, 0), &doctor()));
+        assert!(!filter.accepts(&with(PrescriptionState::Created, 0), &patient()));
+    }
+
+    #[test]
+    fn creation_range_is_inclusive() {
+        let filter = PrescriptionFilter {
+            created_from: Some(10),
+            created_to: Some(20),
+            ..Default::default()
+        };
+        for (created_at, accepted) in [(9, false), (10, true), (20, true), (21, false)] {
+            assert_eq!(filter.accepts(&with(PrescriptionState::Signed, created_at), &patient()), accepted);
+        }
+    }
+
+    #[test]
+    fn counterparty_is_the_doctor_or_the_patient() {
+        let e = prescription("p1");
+        for (counterparty, accepted) in [(doctor(), true), (patient(), true), (Principal::anonymous(), false)] {
+            let filter = PrescriptionFilter {
+                counterparty: Some(counterparty),
+                ..Default::default()
+            };
+            assert_eq!(filter.accepts(&e, &patient()), accepted);
+        }
+    }
+
+    #[test]
+    fn expired_shares_are_never_accepted() {
+        let filter = PrescriptionFilter::default();
+        assert!(filter.accepts_share(&share(group(), None), 100));
+        assert!(filter.accepts_share(&share(group(), Some(101)), 100));
+        assert!(!filter.accepts_share(&share(group(), Some(100)), 100));
+    }
+
+    #[test]
+    fn shares_are_filtered_by_subject() {
+        let filter = PrescriptionFilter {
+            shared_with: Some(group()),
+            ..Default::default()
+        };
+        assert!(filter.filters_shares());
+        assert!(filter.accepts_share(&share(group(), None), 0));
+        assert!(!filter.accepts_share(&share(PrescriptionAuthSubject::User(patient()), None), 0));
+    }
+
+    #[test]
+    fn shares_are_filtered_by_expiration_range() {
+        let filter = PrescriptionFilter {
+            share_expires_from: Some(10),
+            share_expires_to: Some(20),
+            ..Default::default()
+        };
+        assert!(filter.filters_shares());
+        assert!(!filter.accepts_share(&share(group(), None), 0));
+        assert!(!filter.accepts_share(&share(group(), Some(9)), 0));
+        assert!(filter.accepts_share(&share(group(), Some(10)), 0));
+        assert!(filter.accepts_share(&share(group(), Some(20)), 0));
+        assert!(!filter.accepts_share(&share(group(), Some(21)), 0));
+    }
+}
@@ -7,12 +7,38 @@ use super::{user::UserId, group::GroupId};
 
 pub type PrescriptionAuthId = String;
 
+// max number of grants a grant can be delegated from
+pub const MAX_DELEGATION_DEPTH: usize = 4;
+
 #[derive(CandidType, Clone, Deserialize, Eq, PartialEq, PartialOrd)]
 pub enum PrescriptionAuthKind {
     Read,
     Write,
     ReadWrite,
     All,
+    Share,
+}
+
+// operations on a prescription, checked against the kind of the grants
+#[derive(CandidType, Clone, Deserialize, Eq, PartialEq, Debug)]
+pub enum PrescriptionOperation {
+    View,
+    FetchKey,
+    Reshare,
+    Annotate,
+    Dispense,
+    Delete,
+}
+
+impl PrescriptionOperation {
+    pub const ALL: [PrescriptionOperation; 6] = [
+        PrescriptionOperation::View,
+        PrescriptionOperation::FetchKey,
+        PrescriptionOperation::Reshare,
+        PrescriptionOperation::Annotate,
+        PrescriptionOperation::Dispense,
+        PrescriptionOperation::Delete,
+    ];
 }
 
 #[derive(CandidType, Clone, Deserialize, Eq, PartialEq, PartialOrd)]
@@ -29,6 +55,10 @@ pub struct PrescriptionAuth {
     pub from: Principal,
     pub to: PrescriptionAuthSubject,
     pub expires_at: Option<u64>,
+    // set by the sweeper when the grant is deleted for being expired
+    pub expired_at: Option<u64>,
+    // grants this one was delegated from, starting with the one created by the patient
+    pub chain: Vec<PrescriptionAuthId>,
     pub created_at: u64,
     pub created_by: Principal,
     pub updated_at: Option<u64>,
@@ -53,6 +83,8 @@ pub struct PrescriptionAuthResponse {
     from: Principal,
     to: PrescriptionAuthSubject,
     expires_at: Option<u64>,
+    expired_at: Option<u64>,
+    chain: Vec<PrescriptionAuthId>,
     created_at: u64,
     updated_at: Option<u64>,
 }
@@ -100,6 +132,48 @@ impl Ord for PrescriptionAuth {
     }
 }
 
+#[derive(CandidType, Clone, Deserialize, Default)]
+pub struct GrantsSweepStatus {
+    // in nanoseconds
+    pub interval: u64,
+    pub last_run_at: Option<u64>,
+    pub last_expired: u64,
+    pub total_expired: u64,
+    pub last_error: Option<String>,
+    pub next_run_at: Option<u64>,
+}
+
+impl PrescriptionAuthKind {
+    // All and Share allow re-sharing the prescription with others
+    pub fn allows(
+        &self,
+        op: &PrescriptionOperation
+    ) -> bool {
+        match self {
+            PrescriptionAuthKind::Read => 
+                matches!(op, PrescriptionOperation::View | PrescriptionOperation::FetchKey),
+            PrescriptionAuthKind::Write => 
+                matches!(op, PrescriptionOperation::Annotate | PrescriptionOperation::Dispense),
+            PrescriptionAuthKind::ReadWrite => 
+                matches!(op, PrescriptionOperation::View | PrescriptionOperation::FetchKey | 
+                    PrescriptionOperation::Annotate | PrescriptionOperation::Dispense),
+            PrescriptionAuthKind::All => 
+                true,
+            PrescriptionAuthKind::Share => 
+                matches!(op, PrescriptionOperation::View | PrescriptionOperation::FetchKey | PrescriptionOperation::Reshare),
+        }
+    }
+
+    // true if every operation allowed by other is allowed by this kind too
+    pub fn covers(
+        &self,
+        other: &PrescriptionAuthKind
+    ) -> bool {
+        PrescriptionOperation::ALL.iter()
+            .all(|op| !other.allows(op) || self.allows(op))
+    }
+}
+
 impl PrescriptionAuth {
     pub fn new(
         id: &String,
@@ -113,6 +187,8 @@ impl PrescriptionAuth {
             from: caller.clone(),
             to: e.to.clone(),
             expires_at: e.expires_at,
+            expired_at: None,
+            chain: vec![],
             created_at: ic_cdk::api::time(),
             created_by: caller.clone(),
             updated_at: None,
@@ -122,6 +198,54 @@ impl PrescriptionAuth {
         }
     }
 
+    // checks if a grant of kind, expiring at expires_at, can be delegated from this one
+    pub fn can_delegate(
+        &self,
+        kind: &PrescriptionAuthKind,
+        expires_at: Option<u64>
+    ) -> bool {
+        self.kind.allows(&PrescriptionOperation::Reshare) &&
+            self.kind.covers(kind) &&
+            self.chain.len() < MAX_DELEGATION_DEPTH &&
+            match (self.expires_at, expires_at) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(parent), Some(child)) => child <= parent,
+            }
+    }
+
+    // the grant delegated from this one
+    pub fn delegate(
+        &self,
+        auth: &PrescriptionAuth
+    ) -> PrescriptionAuth {
+        let mut chain = self.chain.clone();
+        chain.push(self.id.clone());
+
+        PrescriptionAuth {
+            chain,
+            ..auth.clone()
+        }
+    }
+
+    // grants are expired from the instant of their expiration on
+    pub fn is_expired(
+        &self,
+        now: u64
+    ) -> bool {
+        self.expires_at.map_or(false, |at| at <= now)
+    }
+
+    pub fn expire(
+        &self,
+        now: u64
+    ) -> Self {
+        Self {
+            expired_at: Some(now),
+            ..self.clone()
+        }
+    }
+
     pub fn update(
         &self,
         caller: &Principal
@@ -145,8 +269,158 @@ impl From<PrescriptionAuth> for PrescriptionAuthResponse {
             from: e.from, 
             to: e.to, 
             expires_at: e.expires_at,
+            expired_at: e.expired_at,
+            chain: e.chain,
             created_at: e.created_at,
             updated_at: e.updated_at,
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::auth;
+    use super::*;
+
+    fn grant(
+        kind: PrescriptionAuthKind,
+        expires_at: Option<u64>,
+        chain: Vec<PrescriptionAuthId>
+    ) -> PrescriptionAuth {
+        PrescriptionAuth {
+            expires_at,
+            chain,
+            ..auth("a1", kind)
+        }
+    }
+
+    fn allowed(
+        kind: PrescriptionAuthKind
+    ) -> Vec<PrescriptionOperation> {
+        PrescriptionOperation::ALL.iter()
+            .filter(|op| kind.allows(op))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn read_allows_viewing_only() {
+        assert_eq!(allowed(PrescriptionAuthKind::Read), vec![
+            PrescriptionOperation::View,
+            PrescriptionOperation::FetchKey,
+        ]);
+    }
+
+    #[test]
+    fn write_allows_annotating_and_dispensing() {
+        assert_eq!(allowed(PrescriptionAuthKind::Write), vec![
+            PrescriptionOperation::Annotate,
+            PrescriptionOperation::Dispense,
+        ]);
+    }
+
+    #[test]
+    fn read_write_allows_both() {
+        assert_eq!(allowed(PrescriptionAuthKind::ReadWrite), vec![
+            PrescriptionOperation::View,
+            PrescriptionOperation::FetchKey,
+            PrescriptionOperation::Annotate,
+            PrescriptionOperation::Dispense,
+        ]);
+    }
+
+    #[test]
+    fn share_allows_resharing_but_not_writing() {
+        assert_eq!(allowed(PrescriptionAuthKind::Share), vec![
+            PrescriptionOperation::View,
+            PrescriptionOperation::FetchKey,
+            PrescriptionOperation::Reshare,
+        ]);
+    }
+
+    #[test]
+    fn all_allows_everything() {
+        assert_eq!(allowed(PrescriptionAuthKind::All), PrescriptionOperation::ALL.to_vec());
+    }
+
+    #[test]
+    fn covers_the_kinds_with_fewer_operations() {
+        assert!(PrescriptionAuthKind::All.covers(&PrescriptionAuthKind::Share));
+        assert!(PrescriptionAuthKind::ReadWrite.covers(&PrescriptionAuthKind::Read));
+        assert!(PrescriptionAuthKind::ReadWrite.covers(&PrescriptionAuthKind::Write));
+        assert!(PrescriptionAuthKind::Share.covers(&PrescriptionAuthKind::Read));
+        assert!(PrescriptionAuthKind::Read.covers(&PrescriptionAuthKind::Read));
+        assert!(!PrescriptionAuthKind::Read.covers(&PrescriptionAuthKind::ReadWrite));
+        assert!(!PrescriptionAuthKind::Share.covers(&PrescriptionAuthKind::Write));
+        assert!(!PrescriptionAuthKind::ReadWrite.covers(&PrescriptionAuthKind::Share));
+    }
+
+    #[test]
+    fn only_grants_that_allow_resharing_can_be_delegated() {
+        for kind in [PrescriptionAuthKind::Read, PrescriptionAuthKind::Write, PrescriptionAuthKind::ReadWrite] {
+            assert!(!grant(kind, None, vec![]).can_delegate(&PrescriptionAuthKind::Read, None));
+        }
+        assert!(grant(PrescriptionAuthKind::Share, None, vec![]).can_delegate(&PrescriptionAuthKind::Read, None));
+        assert!(grant(PrescriptionAuthKind::All, None, vec![]).can_delegate(&PrescriptionAuthKind::ReadWrite, None));
+    }
+
+    #[test]
+    fn delegated_grants_cant_allow_more_than_their_parent() {
+        let parent = grant(PrescriptionAuthKind::Share, None, vec![]);
+        assert!(parent.can_delegate(&PrescriptionAuthKind::Share, None));
+        assert!(!parent.can_delegate(&PrescriptionAuthKind::Write, None));
+        assert!(!parent.can_delegate(&PrescriptionAuthKind::All, None));
+    }
+
+    #[test]
+    fn delegated_grants_cant_outlive_their_parent() {
+        let parent = grant(PrescriptionAuthKind::Share, Some(100), vec![]);
+        assert!(parent.can_delegate(&PrescriptionAuthKind::Read, Some(100)));
+        assert!(!parent.can_delegate(&PrescriptionAuthKind::Read, Some(101)));
+        assert!(!parent.can_delegate(&PrescriptionAuthKind::Read, None));
+
+        let parent = grant(PrescriptionAuthKind::Share, None, vec![]);
+        assert!(parent.can_delegate(&PrescriptionAuthKind::Read, Some(u64::MAX)));
+    }
+
+    #[test]
+    fn delegation_chains_are_limited() {
+        let chain: Vec<PrescriptionAuthId> = (0..MAX_DELEGATION_DEPTH).map(|i| i.to_string()).collect();
+        assert!(grant(PrescriptionAuthKind::Share, None, chain[1..].to_vec()).can_delegate(&PrescriptionAuthKind::Read, None));
+        assert!(!grant(PrescriptionAuthKind::Share, None, chain).can_delegate(&PrescriptionAuthKind::Read, None));
+    }
+
+    #[test]
+    fn delegate_appends_the_parent_to_the_chain() {
+        let parent = PrescriptionAuth {
+            chain: vec!["a1".to_string()],
+            ..auth("a2", PrescriptionAuthKind::Share)
+        };
+        let child = parent.delegate(&PrescriptionAuth {
+            expires_at: Some(50),
+            ..auth("a3", PrescriptionAuthKind::Read)
+        });
+        assert_eq!(child.id, "a3");
+        assert_eq!(child.chain, vec!["a1".to_string(), "a2".to_string()]);
+        assert!(child.kind == PrescriptionAuthKind::Read);
+        assert_eq!(child.expires_at, Some(50));
+    }
+
+    #[test]
+    fn grants_are_expired_at_their_expiration() {
+        assert!(!grant(PrescriptionAuthKind::Read, None, vec![]).is_expired(u64::MAX));
+        assert!(!grant(PrescriptionAuthKind::Read, Some(100), vec![]).is_expired(99));
+        assert!(grant(PrescriptionAuthKind::Read, Some(100), vec![]).is_expired(100));
+        assert!(grant(PrescriptionAuthKind::Read, Some(100), vec![]).is_expired(101));
+    }
+
+    #[test]
+    fn expire_records_when_the_grant_expired() {
+        let grant = grant(PrescriptionAuthKind::Read, Some(100), vec![]);
+        let expired = grant.expire(150);
+        assert_eq!(expired.expired_at, Some(150));
+        assert_eq!(expired.expires_at, Some(100));
+        assert_eq!(expired.deleted_at, None);
+        assert!(expired == grant);
+    }
 }
\ No newline at end of file
@@ -15,3 +15,68 @@ pub struct PrescriptionTemplate {
     pub deleted_at: Option<u64>,
     pub deleted_by: Option<Principal>,
 }
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct PrescriptionTemplateRequest {
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(CandidType, Clone)]
+pub struct PrescriptionTemplateResponse {
+    id: PrescriptionTemplateId,
+    title: String,
+    body: String,
+    created_at: u64,
+    created_by: Principal,
+    updated_at: Option<u64>,
+}
+
+impl PrescriptionTemplate {
+    pub fn new(
+        id: &String,
+        e: &PrescriptionTemplateRequest,
+        caller: &Principal
+    ) -> Self {
+        Self {
+            id: id.clone(),
+            title: e.title.clone(),
+            body: e.body.clone(),
+            created_at: ic_cdk::api::time(),
+            created_by: caller.clone(),
+            updated_at: None,
+            updated_by: None,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    pub fn update(
+        &self,
+        e: &PrescriptionTemplate,
+        caller: &Principal
+    ) -> Self {
+        Self {
+            title: e.title.clone(),
+            body: e.body.clone(),
+            updated_at: Some(ic_cdk::api::time()),
+            updated_by: Some(caller.clone()),
+            ..self.clone()
+        }
+    }
+}
+
+impl From<PrescriptionTemplate> for PrescriptionTemplateResponse {
+    fn from(
+        e: PrescriptionTemplate
+    ) -> Self {
+        Self {
+            id: e.id,
+            title: e.title,
+            body: e.body,
+            created_at: e.created_at,
+            created_by: e.created_by,
+            updated_at: e.updated_at,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use candid::CandidType;
+use serde::Deserialize;
+use super::error::Error;
+
+const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// periods in nanoseconds, counted from the deletion
+#[derive(CandidType, Clone, Deserialize)]
+pub struct RetentionConfig {
+    pub restore_window: u64,
+    pub purge_after: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default(
+    ) -> Self {
+        Self {
+            restore_window: 30 * DAY,
+            purge_after: 20 * 365 * DAY,
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn validate(
+        &self
+    ) -> Result<(), Error> {
+        if self.restore_window > self.purge_after {
+            return Err(Error::validation("restore_window", "Can't be longer than the retention period"));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_restore(
+        &self,
+        deleted_at: Option<u64>
+    ) -> Result<(), Error> {
+        self.check_restore_at(deleted_at, ic_cdk::api::time())
+    }
+
+    pub fn check_restore_at(
+        &self,
+        deleted_at: Option<u64>,
+        now: u64
+    ) -> Result<(), Error> {
+        match deleted_at {
+            Some(deleted_at) if now <= deleted_at.saturating_add(self.restore_window) => Ok(()),
+            _ => Err(Error::invalid_state("The restore window has expired")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+    ) -> RetentionConfig {
+        RetentionConfig {
+            restore_window: 10,
+            purge_after: 100,
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(RetentionConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn restore_window_cant_outlast_the_retention() {
+        let config = RetentionConfig {
+            restore_window: 101,
+            ..config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rows_can_be_restored_within_the_window() {
+        assert_eq!(config().check_restore_at(Some(50), 50), Ok(()));
+        assert_eq!(config().check_restore_at(Some(50), 60), Ok(()));
+        assert!(config().check_restore_at(Some(50), 61).is_err());
+    }
+
+    #[test]
+    fn rows_not_deleted_cant_be_restored() {
+        assert!(config().check_restore_at(None, 0).is_err());
+    }
+
+    #[test]
+    fn restore_window_doesnt_overflow() {
+        let config = RetentionConfig {
+            restore_window: u64::MAX,
+            purge_after: u64::MAX,
+        };
+        assert_eq!(config.check_restore_at(Some(u64::MAX), u64::MAX), Ok(()));
+    }
+}
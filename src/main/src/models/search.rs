@@ -0,0 +1,229 @@
+use candid::CandidType;
+use serde::Deserialize;
+use crate::db::traits::index::{IndexKey, IndexValue};
+use super::key::Key;
+use super::thirdparty::ThirdPartyKind;
+use super::user::{User, UserKind};
+
+// index that the users and keys tables declare, with the tokens of their searchable fields
+pub const SEARCH_INDEX: &str = "search";
+// terms shorter than this only match whole tokens
+pub const MIN_PREFIX_LEN: usize = 3;
+pub const MAX_QUERY_TERMS: usize = 4;
+// max number of ids matched per term and field
+pub const MAX_MATCHES: usize = 1000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SearchField {
+    Name,
+    Email,
+    LicenseNum,
+    ThirdPartyKind,
+    KeyValue,
+}
+
+pub const ALL_FIELDS: [SearchField; 5] = [
+    SearchField::Name,
+    SearchField::Email,
+    SearchField::LicenseNum,
+    SearchField::ThirdPartyKind,
+    SearchField::KeyValue,
+];
+
+#[derive(CandidType, Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    // None to search all fields
+    pub fields: Option<Vec<SearchField>>,
+}
+
+impl SearchField {
+    fn code(
+        &self
+    ) -> &'static str {
+        match self {
+            SearchField::Name => "name",
+            SearchField::Email => "email",
+            SearchField::LicenseNum => "license",
+            SearchField::ThirdPartyKind => "kind",
+            SearchField::KeyValue => "key",
+        }
+    }
+
+    // fields matched by prefix. The others must match whole, so their values can't be enumerated
+    pub fn is_prefix(
+        &self
+    ) -> bool {
+        match self {
+            SearchField::Name | SearchField::Email | SearchField::LicenseNum => true,
+            SearchField::ThirdPartyKind | SearchField::KeyValue => false,
+        }
+    }
+
+    /*
+     * privacy rules: fields through which each kind of user can be found. Patients can only be
+     * found by the whole value of one of their keys
+     */
+    pub fn searchable_by(
+        kind: &UserKind
+    ) -> &'static [SearchField] {
+        match kind {
+            UserKind::Doctor(_) => &[SearchField::Name, SearchField::LicenseNum, SearchField::KeyValue],
+            UserKind::ThirdParty(_) => &[SearchField::Name, SearchField::Email, SearchField::ThirdPartyKind, SearchField::KeyValue],
+            UserKind::Staff(_) => &[SearchField::Name, SearchField::Email],
+            UserKind::Patient(_) => &[SearchField::KeyValue],
+        }
+    }
+
+    // splits the value into the tokens stored in the index
+    pub fn tokens(
+        &self,
+        value: &str
+    ) -> Vec<String> {
+        let value = value.trim().to_lowercase();
+        match self {
+            SearchField::Name => value
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+            SearchField::LicenseNum => vec![value.chars().filter(|c| c.is_alphanumeric()).collect()],
+            SearchField::KeyValue => vec![value.chars().filter(|c| !c.is_whitespace() && !"-()".contains(*c)).collect()],
+            SearchField::Email | SearchField::ThirdPartyKind => vec![value],
+        }.into_iter()
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    pub fn key(
+        &self,
+        token: &str
+    ) -> IndexKey {
+        vec![IndexValue::Text(self.code().to_string()), IndexValue::Text(token.to_string())]
+    }
+
+    // upper bound of the keys starting with token
+    pub fn key_prefix_end(
+        &self,
+        token: &str
+    ) -> IndexKey {
+        vec![IndexValue::Text(self.code().to_string()), IndexValue::Text(format!("{}{}", token, char::MAX))]
+    }
+
+    fn keys(
+        &self,
+        value: &str
+    ) -> Vec<IndexKey> {
+        self.tokens(value).iter()
+            .map(|token| self.key(token))
+            .collect()
+    }
+}
+
+impl SearchRequest {
+    pub fn terms(
+        &self
+    ) -> Vec<&str> {
+        self.query.split_whitespace().collect()
+    }
+
+    pub fn fields(
+        &self
+    ) -> Vec<SearchField> {
+        self.fields.clone().unwrap_or(ALL_FIELDS.to_vec())
+    }
+}
+
+// keys of the users' search index. Fields that aren't searchable for the user's kind are not indexed
+pub fn user_search_keys(
+    user: &User
+) -> Vec<IndexKey> {
+    let mut keys = vec![];
+    for field in SearchField::searchable_by(&user.kind) {
+        match field {
+            SearchField::Name => keys.extend(field.keys(&user.name)),
+            SearchField::Email => keys.extend(field.keys(&user.email)),
+            SearchField::LicenseNum => if let UserKind::Doctor(doctor) = &user.kind {
+                keys.extend(field.keys(&doctor.license_num));
+            },
+            SearchField::ThirdPartyKind => if let UserKind::ThirdParty(third) = &user.kind {
+                keys.extend(field.keys(match third.kind {
+                    ThirdPartyKind::Hospital => "hospital",
+                    ThirdPartyKind::DrugStore => "drugstore",
+                    ThirdPartyKind::Other => "other",
+                }));
+            },
+            // indexed by the keys table
+            SearchField::KeyValue => (),
+        }
+    }
+    keys
+}
+
+// keys of the keys' search index. The owner's kind is checked when searching
+pub fn key_search_keys(
+    key: &Key
+) -> Vec<IndexKey> {
+    SearchField::KeyValue.keys(&key.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::user;
+    use crate::models::patient::Patient;
+    use super::*;
+
+    fn patient(
+    ) -> User {
+        user(UserKind::Patient(Patient { birth_date: 0 }))
+    }
+
+    #[test]
+    fn names_are_split_into_lowercase_words() {
+        assert_eq!(SearchField::Name.tokens("  Maria da Silva-Souza "), vec!["maria", "da", "silva", "souza"]);
+        assert!(SearchField::Name.tokens(" - ").is_empty());
+    }
+
+    #[test]
+    fn license_numbers_keep_only_alphanumerics() {
+        assert_eq!(SearchField::LicenseNum.tokens("CRM/SP 123.456"), vec!["crmsp123456"]);
+    }
+
+    #[test]
+    fn key_values_drop_the_formatting() {
+        assert_eq!(SearchField::KeyValue.tokens("+55 (11) 99999-0000"), vec!["+5511999990000"]);
+    }
+
+    #[test]
+    fn emails_are_kept_whole() {
+        assert_eq!(SearchField::Email.tokens(" John.Doe@Example.com"), vec!["john.doe@example.com"]);
+        assert!(SearchField::Email.tokens("  ").is_empty());
+    }
+
+    #[test]
+    fn prefix_keys_bound_the_tokens_starting_with_it() {
+        let from = SearchField::Name.key("mar");
+        let to = SearchField::Name.key_prefix_end("mar");
+        for token in ["mar", "maria", "marzzz"] {
+            let key = SearchField::Name.key(token);
+            assert!(from <= key && key < to);
+        }
+        assert!(SearchField::Name.key("mas") > to);
+        assert!(SearchField::Email.key("maria") < from);
+    }
+
+    #[test]
+    fn patients_can_only_be_found_by_their_keys() {
+        assert!(SearchField::searchable_by(&patient().kind) == &[SearchField::KeyValue]);
+        assert!(user_search_keys(&patient()).is_empty());
+    }
+
+    #[test]
+    fn only_exact_fields_are_not_matched_by_prefix() {
+        let exact: Vec<SearchField> = ALL_FIELDS.iter()
+            .filter(|e| !e.is_prefix())
+            .cloned()
+            .collect();
+        assert!(exact == vec![SearchField::ThirdPartyKind, SearchField::KeyValue]);
+    }
+}
\ No newline at end of file
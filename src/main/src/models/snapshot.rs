@@ -0,0 +1,74 @@
+use candid::CandidType;
+use serde::Deserialize;
+use crate::db::TableName;
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct SnapshotTableInfo {
+    pub table: TableName,
+    pub version: u32,
+    pub rows: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u32,
+    pub created_at: u64,
+    // rows written to the DB when the manifest was created
+    pub db_version: u64,
+    pub tables: Vec<SnapshotTableInfo>,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct SnapshotExportRequest {
+    pub table: TableName,
+    pub index: u32,
+    pub cursor: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct SnapshotChunk {
+    pub table: TableName,
+    pub version: u32,
+    pub index: u32,
+    pub rows: u32,
+    pub data: Vec<u8>,
+    pub checksum: String,
+    // cursor to be passed to export the next chunk. None if this is the last one
+    pub next: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct SnapshotTableStatus {
+    pub table: TableName,
+    pub chunks: u32,
+    pub rows: u64,
+    pub expected_rows: u64,
+    pub done: bool,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct SnapshotImportStatus {
+    pub manifest: SnapshotManifest,
+    pub tables: Vec<SnapshotTableStatus>,
+    pub started_at: u64,
+}
+
+impl SnapshotImportStatus {
+    pub fn new(
+        manifest: &SnapshotManifest
+    ) -> Self {
+        Self {
+            manifest: manifest.clone(),
+            tables: manifest.tables.iter()
+                .map(|e| SnapshotTableStatus {
+                    table: e.table.clone(),
+                    chunks: 0,
+                    rows: 0,
+                    expected_rows: e.rows,
+                    done: false,
+                })
+                .collect(),
+            started_at: ic_cdk::api::time(),
+        }
+    }
+}
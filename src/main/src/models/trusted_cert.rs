@@ -0,0 +1,54 @@
+use candid::{Principal, CandidType};
+use serde::Deserialize;
+
+pub type TrustedCertId = String;
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct TrustedCert {
+    pub id: TrustedCertId,
+    pub subject: String,
+    pub der: Vec<u8>,
+    pub created_at: u64,
+    pub created_by: Principal,
+}
+
+#[derive(CandidType, Clone, Deserialize)]
+pub struct TrustedCertRequest {
+    pub cert: String,
+}
+
+#[derive(CandidType)]
+pub struct TrustedCertResponse {
+    id: TrustedCertId,
+    subject: String,
+    created_at: u64,
+}
+
+impl TrustedCert {
+    pub fn new(
+        id: &String,
+        subject: &String,
+        der: &Vec<u8>,
+        caller: &Principal
+    ) -> Self {
+        Self {
+            id: id.clone(),
+            subject: subject.clone(),
+            der: der.clone(),
+            created_at: ic_cdk::api::time(),
+            created_by: caller.clone(),
+        }
+    }
+}
+
+impl From<TrustedCert> for TrustedCertResponse {
+    fn from(
+        e: TrustedCert
+    ) -> Self {
+        Self {
+            id: e.id,
+            subject: e.subject,
+            created_at: e.created_at,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+use candid::Principal;
+use crate::db::DB;
+use crate::db::tables::access_logs::AccessLogsTable;
+use crate::db::traits::crud::{Crud, Page, Pagination};
+use crate::db::traits::index::{IndexKey, IndexValue};
+use crate::db::traits::table::Table;
+use crate::models::access_log::AccessLog;
+use crate::models::error::Error;
+use crate::models::prescription::Prescription;
+use crate::models::prescription_auth::PrescriptionOperation;
+
+use super::prescriptions::PrescriptionsService;
+
+// accesses kept for each patient. The oldest are dropped past this, so the entries of a patient in
+// the indexes, and the ids loaded to page through them, stay bounded. Doctors aren't capped, as
+// dropping the oldest accesses to their prescriptions would drop them from their patients' logs too
+pub const MAX_ACCESS_LOGS_PER_USER: usize = 1000;
+
+pub struct AccessLogsService {}
+
+impl AccessLogsService {
+    // appends the accesses to the prescriptions by users other than their patient and doctor
+    pub fn log(
+        prescriptions: &Vec<Prescription>,
+        op: &PrescriptionOperation,
+        gen_id: impl Fn() -> String,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        for prescription in prescriptions {
+            if prescription.patient == *caller || prescription.doctor == *caller {
+                continue;
+            }
+
+            let grant = PrescriptionsService::find_grant(db, &prescription.id, op, caller);
+            let log = AccessLog::new(&gen_id(), prescription, op, grant.as_ref(), caller);
+            let mut logs = db.access_logs.borrow_mut();
+            logs.insert(log.id.clone(), log.clone())?;
+            Self::trim(&mut *logs, &log.patient);
+        }
+
+        Ok(())
+    }
+
+    // accesses to the caller's prescriptions, as their patient
+    pub fn find_by_patient(
+        pag: Pagination,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Page<AccessLog>, Error> {
+        Self::find_by_index("patient", pag, db, caller)
+    }
+
+    // accesses to the prescriptions the caller created, as their doctor
+    pub fn find_by_doctor(
+        pag: Pagination,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Page<AccessLog>, Error> {
+        Self::find_by_index("doctor", pag, db, caller)
+    }
+
+    fn find_by_index(
+        index: &str,
+        pag: Pagination,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Page<AccessLog>, Error> {
+        if db.users.borrow().find_by_id(caller).is_none() {
+            return Err(Error::not_found("user", caller));
+        }
+
+        let logs = db.access_logs.borrow();
+        let ids = logs.get_data().find_ids(index, &Self::user_key(caller))
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+
+        pag.paginate(&ids, |id| logs.find_by_id(id)
+            .filter(|e| pag.accepts(e.accessed_at))
+        )
+    }
+
+    // ids are ULIDs, so the oldest accesses come first. As the logs are trimmed on every insert,
+    // there's usually a single one to drop, and only the first MAX_ACCESS_LOGS_PER_USER + 1 ids are read
+    fn trim(
+        logs: &mut AccessLogsTable,
+        patient: &Principal
+    ) {
+        let key = Self::user_key(patient);
+        loop {
+            let ids = logs.get_data().find_ids_limited("patient", &key, MAX_ACCESS_LOGS_PER_USER + 1);
+            if ids.len() <= MAX_ACCESS_LOGS_PER_USER {
+                break;
+            }
+            logs.get_data_mut().remove(&ids[0]);
+        }
+    }
+
+    fn user_key(
+        user: &Principal
+    ) -> IndexKey {
+        vec![IndexValue::Principal(user.clone())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{access_log, principal};
+    use super::*;
+
+    #[test]
+    fn trim_drops_the_oldest_accesses_of_the_user() {
+        let mut logs = AccessLogsTable::new();
+        // another patient, with the oldest access of all
+        logs.insert("0000".to_string(), access_log("0000", principal(2), principal(3))).unwrap();
+        for i in 1..=MAX_ACCESS_LOGS_PER_USER + 2 {
+            let id = format!("{:04}", i);
+            logs.insert(id.clone(), access_log(&id, principal(1), principal(3))).unwrap();
+        }
+
+        AccessLogsService::trim(&mut logs, &principal(1));
+
+        assert_eq!(logs.get_data().len(), MAX_ACCESS_LOGS_PER_USER as u64 + 1);
+        assert!(logs.find_by_id(&"0000".to_string()).is_some());
+        assert!(logs.find_by_id(&"0001".to_string()).is_none());
+        assert!(logs.find_by_id(&"0002".to_string()).is_none());
+        assert!(logs.find_by_id(&"0003".to_string()).is_some());
+    }
+
+    #[test]
+    fn trim_keeps_the_accesses_under_the_limit() {
+        let mut logs = AccessLogsTable::new();
+        logs.insert("0001".to_string(), access_log("0001", principal(1), principal(3))).unwrap();
+
+        AccessLogsService::trim(&mut logs, &principal(1));
+
+        assert_eq!(logs.get_data().len(), 1);
+    }
+
+    #[test]
+    fn trim_keeps_the_accesses_of_other_patients_of_the_doctor() {
+        let mut logs = AccessLogsTable::new();
+        // the doctor has more accesses than the cap, split between two patients
+        for i in 1..=MAX_ACCESS_LOGS_PER_USER + 2 {
+            let id = format!("{:04}", i);
+            let patient = if i % 2 == 0 { principal(1) } else { principal(2) };
+            logs.insert(id.clone(), access_log(&id, patient, principal(3))).unwrap();
+            AccessLogsService::trim(&mut logs, &patient);
+        }
+
+        assert_eq!(logs.get_data().len(), MAX_ACCESS_LOGS_PER_USER as u64 + 2);
+        assert!(logs.find_by_id(&"0001".to_string()).is_some());
+        assert!(logs.find_by_id(&"0002".to_string()).is_some());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,144 @@
+use candid::Principal;
+use crate::db::DB;
+use crate::db::traits::crud::Crud;
+use crate::db::traits::table::Table;
+use crate::models::crl::{Crl, CrlId, CrlRequest};
+use crate::models::error::Error;
+use crate::utils::x509::{X509Cert, X509CertChain, X509Crl, pem_to_der};
+use super::trusted_certs::TrustedCertsService;
+
+pub struct CrlsService {}
+
+impl CrlsService {
+    pub fn create(
+        req: &CrlRequest,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<Crl, Error> {
+        let der = if req.crl.starts_with(b"-----BEGIN") {
+            let mut ders = pem_to_der(&req.crl)
+                .map_err(|err| Error::validation("crl", &err.to_string()))?;
+            if ders.len() != 1 {
+                return Err(Error::validation("crl", "Exactly one CRL must be informed"));
+            }
+            ders.remove(0)
+        }
+        else {
+            req.crl.clone()
+        };
+
+        let x509_crl = X509Crl::from_der(&der)
+            .map_err(|err| Error::validation("crl", &err.to_string()))?;
+
+        let issuer = Self::find_issuer(&x509_crl, &req.issuer_cert, db)?;
+
+        x509_crl.verify(&issuer)
+            .map_err(|err| Error::validation("crl", &err.to_string()))?;
+
+        let id = hex::encode(&x509_crl.raw_issuer);
+        let now = ic_cdk::api::time();
+
+        let mut crls = db.crls.borrow_mut();
+
+        match crls.find_by_id(&id) {
+            Some(current) => {
+                if x509_crl.this_update <= current.this_update {
+                    return Err(Error::validation("crl", "A newer CRL for this issuer is already stored"));
+                }
+
+                let crl = Crl {
+                    issuer: x509_crl.issuer,
+                    this_update: x509_crl.this_update,
+                    next_update: x509_crl.next_update,
+                    revoked: x509_crl.revoked,
+                    updated_at: Some(now),
+                    updated_by: Some(caller.clone()),
+                    ..current
+                };
+                crls.update(id, crl.clone())?;
+                Ok(crl)
+            },
+            None => {
+                let crl = Crl {
+                    id: id.clone(),
+                    issuer: x509_crl.issuer,
+                    this_update: x509_crl.this_update,
+                    next_update: x509_crl.next_update,
+                    revoked: x509_crl.revoked,
+                    created_at: now,
+                    created_by: caller.clone(),
+                    updated_at: None,
+                    updated_by: None,
+                };
+                crls.insert(id, crl.clone())?;
+                Ok(crl)
+            }
+        }
+    }
+
+    pub fn delete(
+        id: &CrlId,
+        db: &mut DB,
+        _caller: &Principal
+    ) -> Result<(), Error> {
+        db.crls.borrow_mut().delete(id)
+    }
+
+    pub fn find_all(
+        db: &DB
+    ) -> Vec<Crl> {
+        db.crls.borrow().get_data().values()
+            .collect()
+    }
+
+    pub fn check_revocation(
+        certs: &Vec<X509Cert>,
+        db: &DB
+    ) -> Result<(), Error> {
+        let crls = db.crls.borrow();
+        let now = ic_cdk::api::time();
+
+        for cert in certs {
+            if let Some(crl) = crls.find_by_id(&hex::encode(&cert.raw_issuer)) {
+                // past its nextUpdate, the CRL can be missing revocations issued since
+                if crl.is_stale(now) {
+                    return Err(Error::validation("cert", &format!("The CRL of {} is stale and must be updated", crl.issuer)));
+                }
+                if crl.revoked.contains(&cert.serial) {
+                    return Err(Error::validation("cert", &format!("Certificate {} was revoked", cert.subject)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn find_issuer(
+        crl: &X509Crl,
+        issuer_cert: &Option<String>,
+        db: &DB
+    ) -> Result<X509Cert, Error> {
+        let roots = TrustedCertsService::get_roots(db);
+
+        match issuer_cert {
+            // the CRL must have been issued by one of the trusted CAs
+            None => roots.into_iter()
+                .find(|root| root.raw_subject == crl.raw_issuer)
+                .ok_or(Error::validation("issuer_cert", "CRL issuer is not a trusted CA")),
+            // or by an intermediate CA that chains to one of them
+            Some(pem) => {
+                let chain = X509CertChain::new(&pem.as_bytes().to_vec(), &vec![])
+                    .map_err(|err| Error::validation("issuer_cert", &err.to_string()))?;
+                let ca = &chain.chain[0];
+
+                ca.check_validity()
+                    .map_err(|err| Error::validation("issuer_cert", &err.to_string()))?;
+
+                chain.verify_path(ca, &roots)
+                    .map_err(|err| Error::validation("issuer_cert", &err.to_string()))?;
+
+                Ok(ca.clone())
+            }
+        }
+    }
+}
@@ -1,49 +1,102 @@
-use x509_parser::der_parser::oid;
-use x509_parser::oid_registry::Oid;
+use crate::db::DB;
 use crate::models::doctor::Doctor;
+use crate::models::error::Error;
 use crate::utils::x509::{X509CertChain, X509Cert};
+use super::crls::CrlsService;
+use super::licenses::LicenseExtractorRegistry;
+use super::trusted_certs::TrustedCertsService;
 
 pub struct DoctorsService {}
 
-const ICP_BRASIL_PERSON_DATA: Oid<'static> = oid!(2.16.76.1.3.1);
-
 impl DoctorsService {
+    pub fn validate(
+        doctor: &Doctor
+    ) -> Result<(), Error> {
+        if let Some(country) = &doctor.country {
+            if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+                return Err(Error::validation("country", "Country must be an ISO 3166-1 alpha-2 code"));
+            }
+        }
+
+        if doctor.license_num.len() == 0 || doctor.license_num.len() > 64 {
+            return Err(Error::validation("license_num", "License number length must be between 1 and 64"));
+        }
+
+        Ok(())
+    }
+
     pub fn validate_cert(
         x509: &Vec<u8>,
-        doctor: &Doctor
-    ) -> Result<X509Cert, String> {
-        let chain = X509CertChain::new(
-            x509, 
-            &vec![
-                ICP_BRASIL_PERSON_DATA
-            ]
-        );
+        doctor: &Doctor,
+        db: &DB
+    ) -> Result<X509Cert, Error> {
+        let chain = Self::get_chain(x509)?;
+
+        Self::validate_chain(&chain, doctor, db)
+    }
+
+    pub fn validate_cert_ders(
+        ders: &Vec<Vec<u8>>,
+        doctor: &Doctor,
+        db: &DB
+    ) -> Result<X509Cert, Error> {
+        let chain = Self::get_chain_ders(ders)?;
+
+        Self::validate_chain(&chain, doctor, db)
+    }
+
+    fn validate_chain(
+        chain: &X509CertChain,
+        doctor: &Doctor,
+        db: &DB
+    ) -> Result<X509Cert, Error> {
+        let roots = TrustedCertsService::get_roots(db);
         
-        let cert = match chain.validate() {
+        let cert = match chain.validate(&roots) {
             Ok(cert) => cert,
-            Err(err) => return Err(err),
+            Err(err) => return Err(Error::validation("cert", &err.to_string())),
         };
 
-        //TODO: add support for other countries
-        
-        //FIXME: we should verify the doctor's license, not the subject's national ID
-        match cert.alt_names.get(&ICP_BRASIL_PERSON_DATA.to_id_string()) {
-            Some(person_data) => {
-                let license_num = String::from_utf8(person_data[4+8..4+8+11].to_vec()).unwrap_or_default();
-                if doctor.license_num != license_num {
-                    return Err("Certificate license number is different from doctor's one".to_string());
-                }
-            },
-            None => return Err("Certificate doesn't contain a license number".to_string()),
-        };
+        CrlsService::check_revocation(&chain.chain, db)?;
+
+        if doctor.license_num != Self::get_license_num(cert, doctor)? {
+            return Err(Error::validation("cert", "Certificate license number is different from doctor's one"));
+        }
 
         return Ok(cert.clone())
     }
 
+    pub fn get_chain(
+        x509: &Vec<u8>
+    ) -> Result<X509CertChain, Error> {
+        X509CertChain::new(
+            x509, 
+            &LicenseExtractorRegistry::alt_name_oids()
+        ).map_err(|err| Error::validation("cert", &err.to_string()))
+    }
+
+    pub fn get_chain_ders(
+        ders: &Vec<Vec<u8>>
+    ) -> Result<X509CertChain, Error> {
+        X509CertChain::from_ders(
+            ders, 
+            &LicenseExtractorRegistry::alt_name_oids()
+        ).map_err(|err| Error::validation("cert", &err.to_string()))
+    }
+
+    pub fn get_license_num(
+        cert: &X509Cert,
+        doctor: &Doctor
+    ) -> Result<String, Error> {
+        LicenseExtractorRegistry::extract(cert, doctor)
+            .map_err(|err| Error::validation("cert", &err))
+    }
+
     pub fn get_top_cert(
         x509: &Vec<u8>
-    ) -> Result<X509Cert, String> {
-        let chain = X509CertChain::new(x509, &vec![]);
-        chain.get_top()
+    ) -> Result<X509Cert, Error> {
+        X509CertChain::new(x509, &vec![])
+            .and_then(|chain| chain.get_top())
+            .map_err(|err| Error::validation("cert", &err.to_string()))
     }
 }
\ No newline at end of file
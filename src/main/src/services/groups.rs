@@ -1,6 +1,7 @@
 use candid::Principal;
 use crate::db::DB;
-use crate::db::traits::crud::{CrudSubscribable, Crud, Pagination};
+use crate::db::traits::crud::{CrudSubscribable, Crud, Page, Pagination};
+use crate::models::error::Error;
 use crate::models::group::{Group, GroupId};
 use crate::models::user::UserId;
 
@@ -11,17 +12,17 @@ impl GroupsService {
         group: &Group,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         if *caller == Principal::anonymous() {
-            return Err("Anonymous not allowed".to_string());
+            return Err(Error::Unauthenticated);
         }
 
         if group.members.len() == 0 || group.members.len() > 16 {
-            return Err("Members field length must be between 1 and 16".to_string());
+            return Err(Error::validation("members", "Members field length must be between 1 and 16"));
         }
 
         if group.members.iter().any(|id| db.users.borrow().find_by_id(id).is_none()) {
-            return Err("Members field contains an invalid member id".to_string());
+            return Err(Error::validation("members", "Members field contains an invalid member id"));
         }
         
         db.groups.borrow_mut().insert_and_notify(group.id.to_owned(), group.clone())
@@ -32,16 +33,16 @@ impl GroupsService {
         req: &Group,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut groups = db.groups.borrow_mut();
 
         let group = match groups.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("group", id)),
             Some(e) => e
         };
 
         if *caller != group.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
         groups.update_and_notify(id.to_owned(), req.clone())
@@ -51,16 +52,16 @@ impl GroupsService {
         id: &GroupId,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut groups = db.groups.borrow_mut();
 
         let group = match groups.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("group", id)),
             Some(e) => e
         };
 
         if *caller != group.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
         
         groups.delete_and_notify(id)
@@ -69,10 +70,10 @@ impl GroupsService {
     pub fn find_by_id(
         id: &GroupId,
         db: &DB
-    ) -> Result<Group, String> {
+    ) -> Result<Group, Error> {
         match db.groups.borrow().find_by_id(id) {
-            None => return Err("Not found".to_string()),
-            Some(e) => Ok(e.clone())
+            None => return Err(Error::not_found("group", id)),
+            Some(e) => Ok(e)
         }
     }
 
@@ -81,28 +82,17 @@ impl GroupsService {
         pag: Pagination,
         db: &DB,
         caller: &Principal
-    ) -> Result<Vec<Group>, String> {
+    ) -> Result<Page<Group>, Error> {
         if *id != *caller {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
-        let groups_rel = db.principal_groups_rel.borrow();
+        let ids = db.principal_groups_rel.borrow().find_by_id(id)
+            .unwrap_or_default();
 
-        let list = match groups_rel.find_by_id(id) {
-            None => return Ok(vec![]),
-            Some(ids) => {
-                let groups = db.groups.borrow();
-                let mut arr: Vec<String> = ids.iter().cloned().collect();
-                arr.sort_by(|a, b| b.cmp(a));
-                arr.iter()
-                    .map(|e| groups.find_by_id(e).unwrap())
-                    .skip(pag.offset as usize)
-                    .take(pag.limit as usize)
-                    .cloned()
-                    .collect()
-            }
-        };
-
-        Ok(list)
+        let groups = db.groups.borrow();
+        pag.paginate(&ids, |id| groups.find_by_id(id)
+            .filter(|e| pag.accepts(e.created_at))
+        )
     }
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use candid::Principal;
 use crate::db::DB;
-use crate::db::traits::crud::{CrudSubscribable, Crud, Pagination};
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, Page, Pagination};
+use crate::models::error::Error;
+use crate::models::retention::RetentionConfig;
 use crate::models::key::{Key, KeyId, KeyKind};
 use crate::models::user::UserId;
 
@@ -11,9 +13,9 @@ impl KeysService {
         key: &Key,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         if *caller == Principal::anonymous() {
-            return Err("Anonymous not allowed".to_string());
+            return Err(Error::Unauthenticated);
         }
 
         db.keys.borrow_mut().insert_and_notify(key.id.clone(), key.clone())
@@ -24,16 +26,16 @@ impl KeysService {
         req: &Key,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut keys = db.keys.borrow_mut();
 
         let key = match keys.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("key", id)),
             Some(e) => e
         };
 
         if *caller != key.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
         keys.update_and_notify(id.to_owned(), req.clone())
@@ -43,34 +45,56 @@ impl KeysService {
         id: &KeyId,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut keys = db.keys.borrow_mut();
 
         let key = match keys.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("key", id)),
             Some(e) => e
         };
 
         if *caller != key.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
         
-        keys.delete_and_notify(id)
+        keys.soft_delete_and_notify(id, Some(caller.clone()))
+    }
+
+    pub fn restore(
+        id: &KeyId,
+        retention: &RetentionConfig,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        let mut keys = db.keys.borrow_mut();
+
+        let key = match keys.find_deleted(id) {
+            None => return Err(Error::not_found("key", id)),
+            Some(e) => e
+        };
+
+        if *caller != key.created_by {
+            return Err(Error::Forbidden);
+        }
+
+        retention.check_restore(key.deleted_at)?;
+        
+        keys.restore_and_notify(id)
     }
 
     pub fn find_by_id(
         id: &KeyId,
         db: &DB,
         _caller: &Principal
-    ) -> Result<Key, String> {
+    ) -> Result<Key, Error> {
         let keys = db.keys.borrow();
 
         let key = match keys.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("key", id)),
             Some(e) => e
         };
 
-        Ok(key.clone())
+        Ok(key)
     }
 
     pub fn find_by_value(
@@ -79,17 +103,17 @@ impl KeysService {
         value: &String,
         db: &DB,
         _caller: &Principal
-    ) -> Result<Key, String> {
+    ) -> Result<Key, Error> {
         let keys = db.keys.borrow();
 
         let id = Key::unique_id(kind, country, value);
 
         let key = match keys.find_by_id(&id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("key", &id)),
             Some(e) => e
         };
 
-        Ok(key.clone())
+        Ok(key)
     }
 
     pub fn find_all_by_user(
@@ -97,28 +121,21 @@ impl KeysService {
         pag: Pagination,
         db: &DB,
         caller: &Principal
-    ) -> Result<Vec<Key>, String> {
-        let keys_rel = db.principal_keys_rel.borrow();
-
-        let list: Vec<_> = match keys_rel.find_by_id(id) {
-            None => return Ok(vec![]),
-            Some(ids) => {
-                let keys = db.keys.borrow();
-                let mut arr: Vec<String> = ids.iter().cloned().collect();
-                arr.sort_by(|a, b| b.cmp(a));
-                arr.iter()
-                    .map(|e| keys.find_by_id(e).unwrap())
-                    .skip(pag.offset as usize)
-                    .take(pag.limit as usize)
-                    .cloned()
-                    .collect()
-            }
-        };
+    ) -> Result<Page<Key>, Error> {
+        let ids = db.principal_keys_rel.borrow().find_by_id(id)
+            .unwrap_or_default();
+
+        let keys = db.keys.borrow();
+        let page = pag.paginate(&ids, |id| keys.find_by_id(id)
+            .filter(|k| pag.accepts(k.created_at))
+        )?;
 
-        if list.len() > 0 && list[0].created_by != *caller {
-            return Err("Forbidden".to_string());
+        if let Some(first) = page.items.first() {
+            if first.created_by != *caller {
+                return Err(Error::Forbidden);
+            }
         }
 
-        Ok(list)
+        Ok(page)
     }
 }
\ No newline at end of file
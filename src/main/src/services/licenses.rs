@@ -0,0 +1,308 @@
+use x509_parser::der_parser::asn1_rs::{Any, FromDer};
+use x509_parser::der_parser::oid;
+use x509_parser::oid_registry::Oid;
+use crate::models::doctor::{Doctor, LicenseAuthority};
+use crate::utils::x509::X509Cert;
+
+const ICP_BRASIL_PERSON_DATA: Oid<'static> = oid!(2.16.76.1.3.1);
+const ICP_BRASIL_CRM_NUMBER: Oid<'static> = oid!(2.16.76.1.4.2.2.1);
+const ICP_BRASIL_CRM_UF: Oid<'static> = oid!(2.16.76.1.4.2.2.2);
+
+pub trait LicenseExtractor {
+    // subject alternative names (otherName) that must be kept when parsing the certificate
+    fn alt_name_oids(
+        &self
+    ) -> Vec<Oid<'static>>;
+
+    fn extract(
+        &self,
+        cert: &X509Cert,
+        country: &str
+    ) -> Result<String, String>;
+}
+
+/*
+ * ICP-Brasil: CRM number and state (UF), as "number/UF"
+ */
+pub struct IcpBrasilExtractor {}
+
+impl LicenseExtractor for IcpBrasilExtractor {
+    fn alt_name_oids(
+        &self
+    ) -> Vec<Oid<'static>> {
+        vec![ICP_BRASIL_CRM_NUMBER, ICP_BRASIL_CRM_UF]
+    }
+
+    fn extract(
+        &self,
+        cert: &X509Cert,
+        country: &str
+    ) -> Result<String, String> {
+        if country != "BR" {
+            return Err("ICP-Brasil certificates can only be used by doctors licensed in Brazil".to_string());
+        }
+
+        let number = match cert.alt_names.get(&ICP_BRASIL_CRM_NUMBER.to_id_string())
+            .and_then(|raw| decode_other_name(raw)) {
+            None => return Err("Certificate doesn't contain a CRM number".to_string()),
+            Some(number) => number,
+        };
+
+        match cert.alt_names.get(&ICP_BRASIL_CRM_UF.to_id_string())
+            .and_then(|raw| decode_other_name(raw)) {
+            None => Ok(number),
+            Some(uf) => Ok(format!("{}/{}", number, uf)),
+        }
+    }
+}
+
+/*
+ * ICP-Brasil person data: the subject's national ID (CPF). Only used by doctors registered
+ * before a license authority could be informed
+ */
+pub struct IcpBrasilPersonDataExtractor {}
+
+impl LicenseExtractor for IcpBrasilPersonDataExtractor {
+    fn alt_name_oids(
+        &self
+    ) -> Vec<Oid<'static>> {
+        vec![ICP_BRASIL_PERSON_DATA]
+    }
+
+    fn extract(
+        &self,
+        cert: &X509Cert,
+        _country: &str
+    ) -> Result<String, String> {
+        match cert.alt_names.get(&ICP_BRASIL_PERSON_DATA.to_id_string()) {
+            Some(person_data) => match person_data.get(4+8..4+8+11) {
+                Some(id) => Ok(String::from_utf8(id.to_vec()).unwrap_or_default()),
+                None => Err("Certificate person data is malformed".to_string()),
+            },
+            None => Err("Certificate doesn't contain a license number".to_string()),
+        }
+    }
+}
+
+/*
+ * generic: the subject's serialNumber attribute
+ */
+pub struct SubjectSerialNumberExtractor {}
+
+impl LicenseExtractor for SubjectSerialNumberExtractor {
+    fn alt_name_oids(
+        &self
+    ) -> Vec<Oid<'static>> {
+        vec![]
+    }
+
+    fn extract(
+        &self,
+        cert: &X509Cert,
+        country: &str
+    ) -> Result<String, String> {
+        if let Some(cert_country) = &cert.subject_country {
+            if cert_country != country {
+                return Err("Certificate country is different from doctor's one".to_string());
+            }
+        }
+
+        match &cert.subject_serial {
+            None => Err("Certificate doesn't contain a subject serial number".to_string()),
+            Some(serial) => Ok(serial.trim().to_string()),
+        }
+    }
+}
+
+/*
+ * eIDAS qualified certificates: serialNumber with the ETSI EN 319 412-1 semantics
+ * identifier ("PNO", "IDC", "TIN"... followed by the country and an hyphen)
+ */
+pub struct EidasExtractor {}
+
+impl LicenseExtractor for EidasExtractor {
+    fn alt_name_oids(
+        &self
+    ) -> Vec<Oid<'static>> {
+        vec![]
+    }
+
+    fn extract(
+        &self,
+        cert: &X509Cert,
+        country: &str
+    ) -> Result<String, String> {
+        let serial = match &cert.subject_serial {
+            None => return Err("Certificate doesn't contain a subject serial number".to_string()),
+            Some(serial) => serial,
+        };
+
+        let (ident, value) = match serial.split_once('-') {
+            Some((ident, value)) if ident.len() == 5 && value.len() > 0 => (ident, value),
+            _ => return Err("Certificate serial number has no semantics identifier".to_string()),
+        };
+
+        if !ident.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err("Certificate serial number has an invalid semantics identifier".to_string());
+        }
+
+        if &ident[3..] != country {
+            return Err("Certificate country is different from doctor's one".to_string());
+        }
+
+        Ok(value.to_string())
+    }
+}
+
+pub struct LicenseExtractorRegistry {}
+
+impl LicenseExtractorRegistry {
+    pub fn get(
+        authority: &Option<LicenseAuthority>
+    ) -> Box<dyn LicenseExtractor> {
+        match authority {
+            None => Box::new(IcpBrasilPersonDataExtractor {}),
+            Some(LicenseAuthority::IcpBrasil) => Box::new(IcpBrasilExtractor {}),
+            Some(LicenseAuthority::SubjectSerialNumber) => Box::new(SubjectSerialNumberExtractor {}),
+            Some(LicenseAuthority::Eidas) => Box::new(EidasExtractor {}),
+        }
+    }
+
+    pub fn alt_name_oids(
+    ) -> Vec<Oid<'static>> {
+        let mut oids = vec![];
+
+        for authority in [
+            None,
+            Some(LicenseAuthority::IcpBrasil),
+            Some(LicenseAuthority::SubjectSerialNumber),
+            Some(LicenseAuthority::Eidas)
+        ] {
+            oids.append(&mut Self::get(&authority).alt_name_oids());
+        }
+
+        oids
+    }
+
+    pub fn extract(
+        cert: &X509Cert,
+        doctor: &Doctor
+    ) -> Result<String, String> {
+        let country = doctor.country.clone().unwrap_or("BR".to_string());
+        Self::get(&doctor.license_authority).extract(cert, &country)
+    }
+}
+
+// otherName values are wrapped by an [0] EXPLICIT tag
+fn decode_other_name(
+    raw: &[u8]
+) -> Option<String> {
+    let (_, outer) = Any::from_der(raw).ok()?;
+    let (_, inner) = Any::from_der(outer.data).ok()?;
+    let value = std::str::from_utf8(inner.data).ok()?.trim();
+    if value.len() == 0 {
+        None
+    }
+    else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::fixtures::{cert, ROOT_PEM};
+    use super::*;
+
+    fn with(
+        subject_serial: Option<&str>,
+        subject_country: Option<&str>,
+        alt_names: Vec<(Oid<'static>, Vec<u8>)>
+    ) -> X509Cert {
+        let mut cert = cert(ROOT_PEM);
+        cert.subject_serial = subject_serial.map(|s| s.to_string());
+        cert.subject_country = subject_country.map(|s| s.to_string());
+        cert.alt_names = alt_names.into_iter()
+            .map(|(oid, raw)| (oid.to_id_string(), raw))
+            .collect::<HashMap<_, _>>();
+        cert
+    }
+
+    // [0] EXPLICIT UTF8String
+    fn other_name(
+        value: &str
+    ) -> Vec<u8> {
+        let mut raw = vec![0xa0, value.len() as u8 + 2, 0x0c, value.len() as u8];
+        raw.extend(value.as_bytes());
+        raw
+    }
+
+    #[test]
+    fn icp_brasil_reads_the_crm_number_and_uf() {
+        let cert = with(None, None, vec![
+            (ICP_BRASIL_CRM_NUMBER, other_name("123456")),
+            (ICP_BRASIL_CRM_UF, other_name("SP")),
+        ]);
+        assert_eq!(IcpBrasilExtractor {}.extract(&cert, "BR"), Ok("123456/SP".to_string()));
+    }
+
+    #[test]
+    fn icp_brasil_needs_the_crm_number() {
+        let uf_only = with(None, None, vec![(ICP_BRASIL_CRM_UF, other_name("SP"))]);
+        assert!(IcpBrasilExtractor {}.extract(&uf_only, "BR").is_err());
+
+        let number_only = with(None, None, vec![(ICP_BRASIL_CRM_NUMBER, other_name("123456"))]);
+        assert_eq!(IcpBrasilExtractor {}.extract(&number_only, "BR"), Ok("123456".to_string()));
+        assert!(IcpBrasilExtractor {}.extract(&number_only, "PT").is_err());
+    }
+
+    #[test]
+    fn icp_brasil_person_data_reads_the_national_id() {
+        // otherName header, birth date and CPF
+        let mut data = vec![0xa0, 0x21, 0x04, 0x1f];
+        data.extend(b"01011980");
+        data.extend(b"12345678901");
+        data.extend(b"000000000000");
+        let valid = with(None, None, vec![(ICP_BRASIL_PERSON_DATA, data)]);
+        assert_eq!(IcpBrasilPersonDataExtractor {}.extract(&valid, "BR"), Ok("12345678901".to_string()));
+
+        let truncated = with(None, None, vec![(ICP_BRASIL_PERSON_DATA, vec![0xa0, 0x02, 0x04, 0x00])]);
+        assert!(IcpBrasilPersonDataExtractor {}.extract(&truncated, "BR").is_err());
+
+        let missing = with(None, None, vec![]);
+        assert!(IcpBrasilPersonDataExtractor {}.extract(&missing, "BR").is_err());
+    }
+
+    #[test]
+    fn subject_serial_number_must_match_the_country() {
+        let with_serial = with(Some(" 12345 "), Some("PT"), vec![]);
+        assert_eq!(SubjectSerialNumberExtractor {}.extract(&with_serial, "PT"), Ok("12345".to_string()));
+        assert!(SubjectSerialNumberExtractor {}.extract(&with_serial, "ES").is_err());
+
+        let without_serial = with(None, Some("PT"), vec![]);
+        assert!(SubjectSerialNumberExtractor {}.extract(&without_serial, "PT").is_err());
+    }
+
+    #[test]
+    fn eidas_strips_the_semantics_identifier() {
+        let qualified = with(Some("PNOPT-12345678"), None, vec![]);
+        assert_eq!(EidasExtractor {}.extract(&qualified, "PT"), Ok("12345678".to_string()));
+        assert!(EidasExtractor {}.extract(&qualified, "ES").is_err());
+    }
+
+    #[test]
+    fn eidas_rejects_serials_without_a_semantics_identifier() {
+        for serial in ["12345678", "PNOPT-", "pnopt-12345678", "PNO-12345678"] {
+            let unqualified = with(Some(serial), None, vec![]);
+            assert!(EidasExtractor {}.extract(&unqualified, "PT").is_err());
+        }
+    }
+
+    #[test]
+    fn registry_keeps_the_alt_names_of_all_extractors() {
+        let oids = LicenseExtractorRegistry::alt_name_oids();
+        assert!(oids.contains(&ICP_BRASIL_PERSON_DATA));
+        assert!(oids.contains(&ICP_BRASIL_CRM_NUMBER));
+        assert!(oids.contains(&ICP_BRASIL_CRM_UF));
+    }
+}
\ No newline at end of file
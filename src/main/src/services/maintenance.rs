@@ -0,0 +1,90 @@
+use ic_stable_structures::StableBTreeMap;
+use crate::db::memory::{get_memory, Memory, Stored, MAINTENANCE_MEMORY_ID};
+use crate::models::error::Error;
+use crate::models::maintenance::{Maintenance, MaintenanceKind};
+
+pub struct MaintenanceService {}
+
+impl MaintenanceService {
+    pub fn begin(
+        kind: MaintenanceKind,
+        now: u64
+    ) -> Result<Maintenance, Error> {
+        if let Some(current) = Self::get() {
+            return Err(Error::invalid_state(&format!("A {} is already in progress", current.kind)));
+        }
+
+        let maintenance = Maintenance {
+            kind,
+            started_at: now,
+        };
+        Self::map().insert(0, Stored(maintenance.clone()));
+
+        Ok(maintenance)
+    }
+
+    pub fn end(
+        kind: &MaintenanceKind
+    ) -> Result<(), Error> {
+        if !Self::is_active(kind) {
+            return Err(Error::invalid_state(&format!("No {} in progress", kind)));
+        }
+
+        Self::map().remove(&0);
+
+        Ok(())
+    }
+
+    pub fn get(
+    ) -> Option<Maintenance> {
+        Self::map().get(&0).map(|maintenance| maintenance.0)
+    }
+
+    pub fn is_active(
+        kind: &MaintenanceKind
+    ) -> bool {
+        Self::get().map_or(false, |maintenance| maintenance.kind == *kind)
+    }
+
+    // writes would be left out of the snapshot being exported, or mixed with the one being imported
+    pub fn check_writable(
+    ) -> Result<(), Error> {
+        match Self::get() {
+            None => Ok(()),
+            Some(maintenance) => Err(Error::invalid_state(
+                &format!("The DB is read-only while a {} is in progress", maintenance.kind)
+            )),
+        }
+    }
+
+    // kept in stable memory, so the DB stays read-only across upgrades
+    fn map(
+    ) -> StableBTreeMap<u32, Stored<Maintenance>, Memory> {
+        StableBTreeMap::init(get_memory(MAINTENANCE_MEMORY_ID))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_are_rejected_during_a_maintenance() {
+        assert_eq!(MaintenanceService::check_writable(), Ok(()));
+
+        MaintenanceService::begin(MaintenanceKind::SnapshotExport, 10).unwrap();
+        assert!(MaintenanceService::check_writable().is_err());
+
+        MaintenanceService::end(&MaintenanceKind::SnapshotExport).unwrap();
+        assert_eq!(MaintenanceService::check_writable(), Ok(()));
+    }
+
+    #[test]
+    fn only_one_maintenance_can_run_at_a_time() {
+        MaintenanceService::begin(MaintenanceKind::SnapshotExport, 10).unwrap();
+
+        assert!(MaintenanceService::begin(MaintenanceKind::SnapshotImport, 20).is_err());
+        assert!(MaintenanceService::end(&MaintenanceKind::SnapshotImport).is_err());
+        assert!(MaintenanceService::is_active(&MaintenanceKind::SnapshotExport));
+    }
+}
@@ -3,4 +3,14 @@ pub mod prescriptions;
 pub mod prescription_auths;
 pub mod keys;
 pub mod groups;
-pub mod doctors;
\ No newline at end of file
+pub mod doctors;
+pub mod prescription_templates;
+pub mod trusted_certs;
+pub mod crls;
+pub mod licenses;
+pub mod snapshots;
+pub mod retention;
+pub mod search;
+pub mod access_logs;
+pub mod maintenance;
+pub mod relations;
\ No newline at end of file
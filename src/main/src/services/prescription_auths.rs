@@ -1,8 +1,17 @@
 use candid::Principal;
 use crate::db::DB;
-use crate::db::traits::crud::{CrudSubscribable, Crud};
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, Page, Pagination};
+use crate::db::traits::index::IndexValue;
+use crate::db::traits::table::Table;
+use crate::models::error::Error;
+use crate::models::retention::RetentionConfig;
 use crate::models::prescription::PrescriptionId;
-use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthId, PrescriptionAuthSubject};
+use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthId, PrescriptionAuthSubject, PrescriptionOperation};
+
+use super::prescriptions::PrescriptionsService;
+
+// max number of grants expired per sweep, so a run fits in the instruction limit
+const MAX_EXPIRED_PER_SWEEP: usize = 500;
 
 pub struct PrescriptionAuthsService {}
 
@@ -11,34 +20,44 @@ impl PrescriptionAuthsService {
         auth: &PrescriptionAuth,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<PrescriptionAuth, Error> {
         if db.users.borrow().find_by_id(caller).is_none() {
-            return Err("User not found".to_string());
+            return Err(Error::not_found("user", caller));
         }
 
         match &auth.to {
             PrescriptionAuthSubject::User(to) => 
                 if db.users.borrow().find_by_id(&to).is_none() {
-                    return Err("Subject user not found".to_string());
+                    return Err(Error::not_found("user", to));
                 },
             PrescriptionAuthSubject::Group(to) => 
                 if db.groups.borrow().find_by_id(&to).is_none() {
-                    return Err("Subject group not found".to_string());
+                    return Err(Error::not_found("group", to));
                 },
         };
         
 
         let prescriptions = db.prescriptions.borrow();
         let prescription = match prescriptions.find_by_id(&auth.prescription_id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("prescription", &auth.prescription_id)),
             Some(e) => e
         };
 
-        if *caller != prescription.patient {
-            return Err("Forbidden".to_string());
+        // grantees can only share what they were granted, and for no longer than that
+        let auth = if *caller == prescription.patient {
+            auth.clone()
         }
+        else {
+            let grants = PrescriptionsService::find_grants(db, &prescription.id, caller);
+            match grants.iter().find(|e| e.can_delegate(&auth.kind, auth.expires_at)) {
+                None => return Err(Error::Forbidden),
+                Some(parent) => parent.delegate(auth)
+            }
+        };
         
-        db.prescription_auths.borrow_mut().insert_and_notify(auth.id.clone(), auth.clone())
+        db.prescription_auths.borrow_mut().insert_and_notify(auth.id.clone(), auth.clone())?;
+
+        Ok(auth)
     }
 
     pub fn update(
@@ -46,16 +65,16 @@ impl PrescriptionAuthsService {
         req: &PrescriptionAuth,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut auths = db.prescription_auths.borrow_mut();
 
         let auth = match auths.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("prescription_auth", id)),
             Some(e) => e
         };
 
         if *caller != auth.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
         auths.update_and_notify(id.to_owned(), req.clone())
@@ -65,62 +84,178 @@ impl PrescriptionAuthsService {
         id: &PrescriptionAuthId,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut auths = db.prescription_auths.borrow_mut();
 
         let auth = match auths.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("prescription_auth", id)),
+            Some(e) => e
+        };
+
+        // grants can be revoked by whoever created them or any grant they were delegated from
+        if *caller != auth.created_by && !auth.chain.iter()
+            .filter_map(|id| auths.find_by_id(id))
+            .any(|e| e.created_by == *caller) {
+            return Err(Error::Forbidden);
+        }
+
+        let children = auths.find_by_index("chain", &vec![IndexValue::Text(id.clone())]);
+        
+        auths.soft_delete_and_notify(id, Some(caller.clone()))?;
+
+        // and the grants delegated from it, directly or not, are revoked with it
+        for child in &children {
+            auths.soft_delete_and_notify(&child.id, Some(caller.clone()))?;
+        }
+
+        drop(auths);
+
+        let mut revoked = children;
+        revoked.push(auth);
+        Self::relist(&revoked, db);
+
+        Ok(())
+    }
+
+    pub fn restore(
+        id: &PrescriptionAuthId,
+        retention: &RetentionConfig,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        let mut auths = db.prescription_auths.borrow_mut();
+
+        let auth = match auths.find_deleted(id) {
+            None => return Err(Error::not_found("prescription_auth", id)),
             Some(e) => e
         };
 
         if *caller != auth.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
+        }
+
+        retention.check_restore(auth.deleted_at)?;
+
+        if db.prescriptions.borrow().find_by_id(&auth.prescription_id).is_none() {
+            return Err(Error::not_found("prescription", &auth.prescription_id));
+        }
+
+        if auth.expired_at.is_some() {
+            return Err(Error::invalid_state("Expired grants can't be restored"));
+        }
+
+        if auth.chain.iter().any(|id| auths.find_by_id(id).is_none()) {
+            return Err(Error::invalid_state("A grant it was delegated from is revoked"));
         }
         
-        auths.delete_and_notify(id)
+        auths.restore_and_notify(id)
     }
 
     pub fn find_by_id(
         id: &PrescriptionAuthId,
         db: &DB,
         caller: &Principal
-    ) -> Result<PrescriptionAuth, String> {
+    ) -> Result<PrescriptionAuth, Error> {
         let auths = db.prescription_auths.borrow();
 
         let auth = match auths.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("prescription_auth", id)),
             Some(e) => e
         };
 
         if auth.created_by != *caller {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
-        Ok(auth.clone())
+        Ok(auth)
     }
 
     pub fn find_by_prescription(
         id: &PrescriptionId,
+        pag: Pagination,
         db: &DB,
         caller: &Principal
-    ) -> Result<Vec<PrescriptionAuth>, String> {
-        let rel = db.prescription_auths_rel.borrow();
+    ) -> Result<Page<PrescriptionAuth>, Error> {
+        // the grants are listed to whoever can share the prescription
+        PrescriptionsService::find_for(id, &PrescriptionOperation::Reshare, db, caller)?;
 
-        let ids = match rel.find_by_id(id) {
-            None => return Ok(vec![]),
-            Some(e) => e
-        };
+        let ids = db.prescription_auths_rel.borrow().find_by_id(id)
+            .unwrap_or_default();
 
-        let auths: Vec<PrescriptionAuth> = ids.iter().map(|id| 
-            db.prescription_auths.borrow().get(id).clone()
-        ).collect();
-        
-        if let Some(first) = auths.first() {
-            if first.created_by != *caller {
-                return Err("Forbidden".to_string());
+        let auths = db.prescription_auths.borrow();
+        pag.paginate(&ids, |id| auths.find_by_id(id)
+            .filter(|e| pag.accepts(e.created_at))
+        )
+    }
+
+    /*
+     * deletes the grants that expired until now, recording when they expired. The relation tables are 
+     * updated through the deletion events. Returns the number of grants expired
+     */
+    pub fn expire(
+        now: u64,
+        db: &mut DB
+    ) -> Result<usize, Error> {
+        let mut auths = db.prescription_auths.borrow_mut();
+
+        // the range's end is exclusive, so the grants expiring right now must be included
+        let ids = auths.get_data().find_ids_in_range(
+            "expires_at", 
+            &vec![IndexValue::Nat(0)], 
+            &vec![IndexValue::Nat(now.saturating_add(1))], 
+            MAX_EXPIRED_PER_SWEEP
+        );
+
+        let mut expired = vec![];
+        for id in &ids {
+            if let Some(auth) = auths.find_by_id(id).filter(|auth| auth.is_expired(now)) {
+                auths.update(id.clone(), auth.expire(now))?;
+                auths.soft_delete_and_notify(id, None)?;
+                expired.push(auth);
             }
         }
 
-        Ok(auths)
+        drop(auths);
+
+        Self::relist(&expired, db);
+
+        Ok(expired.len())
+    }
+
+    /*
+     * the deletion events drop the prescription from the list of the grantee, who can still be related
+     * to it: as its doctor or patient, or through another live grant. It's listed again in that case,
+     * as derive_relations() would do
+     */
+    fn relist(
+        deleted: &Vec<PrescriptionAuth>,
+        db: &DB
+    ) {
+        let prescriptions = db.prescriptions.borrow();
+        let auths = db.prescription_auths.borrow();
+        let auths_rel = db.prescription_auths_rel.borrow();
+        let mut rel = db.user_prescriptions_rel.borrow_mut();
+
+        for auth in deleted {
+            let user = match &auth.to {
+                PrescriptionAuthSubject::User(user) => user,
+                PrescriptionAuthSubject::Group(_) => continue,
+            };
+
+            let is_party = prescriptions.find_by_id(&auth.prescription_id)
+                .map_or(false, |p| p.doctor == *user || p.patient == *user);
+
+            let has_grant = auths_rel.find_by_id(&auth.prescription_id)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|id| auths.find_by_id(id))
+                .any(|e| e.to == auth.to);
+
+            if is_party || has_grant {
+                let mut ids = rel.find_by_id(user).unwrap_or_default();
+                ids.insert(auth.prescription_id.clone());
+                rel.get_data_mut().insert(user.clone(), ids);
+            }
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+use candid::Principal;
+use crate::db::DB;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud, Page, Pagination};
+use crate::models::doctor::Doctor;
+use crate::models::error::Error;
+use crate::models::retention::RetentionConfig;
+use crate::models::prescription_template::{PrescriptionTemplate, PrescriptionTemplateId};
+use crate::models::user::{UserId, UserKind, User};
+
+pub struct PrescriptionTemplatesService {}
+
+impl PrescriptionTemplatesService {
+    pub fn create(
+        template: &PrescriptionTemplate,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        if *caller == Principal::anonymous() {
+            return Err(Error::Unauthenticated);
+        }
+
+        match db.users.borrow().find_by_id(caller) {
+            None => return Err(Error::not_found("doctor", caller)),
+            Some(user) => match user.kind {
+                UserKind::Doctor(_) => (),
+                _ => return Err(Error::Forbidden)
+            }
+        };
+
+        Self::validate(template)?;
+
+        db.prescription_templates.borrow_mut()
+            .insert_and_notify(template.id.clone(), template.clone())
+    }
+
+    pub fn update(
+        id: &PrescriptionTemplateId,
+        req: &PrescriptionTemplate,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<PrescriptionTemplate, Error> {
+        let mut templates = db.prescription_templates.borrow_mut();
+
+        let template = match templates.find_by_id(id) {
+            None => return Err(Error::not_found("prescription_template", id)),
+            Some(e) => e
+        };
+
+        if *caller != template.created_by {
+            return Err(Error::Forbidden);
+        }
+
+        Self::validate(req)?;
+
+        let updated_template = template.update(req, caller);
+
+        templates.update_and_notify(id.to_owned(), updated_template.clone())?;
+
+        Ok(updated_template)
+    }
+
+    pub fn delete(
+        id: &PrescriptionTemplateId,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        let mut templates = db.prescription_templates.borrow_mut();
+
+        let template = match templates.find_by_id(id) {
+            None => return Err(Error::not_found("prescription_template", id)),
+            Some(e) => e
+        };
+
+        if *caller != template.created_by {
+            return Err(Error::Forbidden);
+        }
+
+        templates.soft_delete_and_notify(id, Some(caller.clone()))?;
+
+        // the template can't remain as the doctor's default one
+        let mut users = db.users.borrow_mut();
+        if let Some(user) = users.find_by_id(caller) {
+            if let UserKind::Doctor(doctor) = &user.kind {
+                if doctor.prescription_template.as_ref() == Some(id) {
+                    let updated_user = User {
+                        kind: UserKind::Doctor(Doctor {
+                            prescription_template: None,
+                            ..doctor.clone()
+                        }),
+                        ..user.update(caller)
+                    };
+                    users.update_and_notify(caller.to_owned(), updated_user)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn restore(
+        id: &PrescriptionTemplateId,
+        retention: &RetentionConfig,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        let mut templates = db.prescription_templates.borrow_mut();
+
+        let template = match templates.find_deleted(id) {
+            None => return Err(Error::not_found("prescription_template", id)),
+            Some(e) => e
+        };
+
+        if *caller != template.created_by {
+            return Err(Error::Forbidden);
+        }
+
+        retention.check_restore(template.deleted_at)?;
+
+        templates.restore_and_notify(id)
+    }
+
+    pub fn find_by_id(
+        id: &PrescriptionTemplateId,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<PrescriptionTemplate, Error> {
+        let templates = db.prescription_templates.borrow();
+
+        let template = match templates.find_by_id(id) {
+            None => return Err(Error::not_found("prescription_template", id)),
+            Some(e) => e
+        };
+
+        if template.created_by != *caller {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(template)
+    }
+
+    pub fn find_all_by_doctor(
+        id: &UserId,
+        pag: Pagination,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Page<PrescriptionTemplate>, Error> {
+        if *id != *caller {
+            return Err(Error::Forbidden);
+        }
+
+        let ids = db.doctor_templates_rel.borrow().find_by_id(id)
+            .unwrap_or_default();
+
+        let templates = db.prescription_templates.borrow();
+        pag.paginate(&ids, |id| templates.find_by_id(id)
+            .filter(|e| pag.accepts(e.created_at))
+        )
+    }
+
+    pub fn check_ownership(
+        id: &PrescriptionTemplateId,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        match db.prescription_templates.borrow().find_by_id(id) {
+            None => Err(Error::not_found("prescription_template", id)),
+            Some(template) => if template.created_by != *caller {
+                Err(Error::validation("prescription_template", "Prescription template doesn't belong to the doctor"))
+            }
+            else {
+                Ok(())
+            }
+        }
+    }
+
+    fn validate(
+        template: &PrescriptionTemplate
+    ) -> Result<(), Error> {
+        if template.title.len() == 0 || template.title.len() > 128 {
+            return Err(Error::validation("title", "Title field length must be between 1 and 128"));
+        }
+
+        if template.body.len() == 0 || template.body.len() > 16384 {
+            return Err(Error::validation("body", "Body field length must be between 1 and 16384"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::template;
+    use super::*;
+
+    fn with(
+        title: &str,
+        body: &str
+    ) -> PrescriptionTemplate {
+        PrescriptionTemplate {
+            title: title.to_string(),
+            body: body.to_string(),
+            ..template("t1")
+        }
+    }
+
+    #[test]
+    fn title_length_is_limited() {
+        assert_eq!(PrescriptionTemplatesService::validate(&with("x", "body")), Ok(()));
+        assert_eq!(PrescriptionTemplatesService::validate(&with(&"x".repeat(128), "body")), Ok(()));
+        assert!(PrescriptionTemplatesService::validate(&with("", "body")).is_err());
+        assert!(PrescriptionTemplatesService::validate(&with(&"x".repeat(129), "body")).is_err());
+    }
+
+    #[test]
+    fn body_length_is_limited() {
+        assert_eq!(PrescriptionTemplatesService::validate(&with("title", &"x".repeat(16384))), Ok(()));
+        assert!(PrescriptionTemplatesService::validate(&with("title", "")).is_err());
+        assert!(PrescriptionTemplatesService::validate(&with("title", &"x".repeat(16385))).is_err());
+    }
+}
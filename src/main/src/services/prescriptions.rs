@@ -1,14 +1,23 @@
 use candid::Principal;
-use ring::digest::{SHA256, Context};
-use ring::signature::{self, RsaPublicKeyComponents};
+use ring::digest::{self, SHA256, Context};
+use ring::signature::{self, RsaPublicKeyComponents, RsaParameters};
 use crate::db::DB;
-use crate::db::traits::crud::{CrudSubscribable, Crud};
-use crate::models::prescription::{Prescription, PrescriptionId, PrescriptionPostRequest, PrescriptionState};
-use crate::models::prescription_auth::PrescriptionAuthSubject;
+use crate::db::traits::transaction::Transactional;
+use crate::db::traits::crud::{CrudSubscribable, CrudSoftDeletable, Crud};
+use crate::models::error::Error;
+use crate::models::retention::RetentionConfig;
+use crate::models::prescription::{Prescription, PrescriptionId, PrescriptionPostRequest, PrescriptionState, SignatureAlgorithm, PrescriptionSignature, 
+    PrescriptionVerificationReport};
+use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthSubject, PrescriptionOperation};
 use crate::models::user::UserKind;
+use crate::utils::cms::{self, CmsSignedData};
+use crate::utils::ecdsa;
 use crate::utils::vetkd::VetKdUtil;
-use crate::utils::x509::PubKeyValue;
+use crate::utils::x509::{PubKeyValue, X509Cert, OID_RSA_ENCRYPTION, OID_RSASSA_PSS, OID_EC_PUBLIC_KEY, 
+    OID_SHA256_WITH_RSA, OID_SHA384_WITH_RSA, OID_SHA512_WITH_RSA, OID_ECDSA_WITH_SHA256, OID_ECDSA_WITH_SHA384, OID_ECDSA_WITH_SHA512};
+use super::crls::CrlsService;
 use super::doctors::DoctorsService;
+use super::trusted_certs::TrustedCertsService;
 
 pub struct PrescriptionsService {}
 
@@ -17,18 +26,18 @@ impl PrescriptionsService {
         prescription: &Prescription,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         if *caller == Principal::anonymous() {
-            return Err("Anonymous not allowed".to_string());
+            return Err(Error::Unauthenticated);
         }
 
         // validations
         match db.users.borrow().find_by_id(&caller) {
-            None => return Err("Doctor not found".to_string()),
+            None => return Err(Error::not_found("doctor", caller)),
             Some(doctor) => {
                 match doctor.kind.clone() {
                     UserKind::Doctor(doctor) => doctor,
-                    _ => return Err("User not a doctor".to_string())
+                    _ => return Err(Error::Forbidden)
                 }
             }
         };
@@ -36,11 +45,11 @@ impl PrescriptionsService {
         if let Some(patient) = db.users.borrow().find_by_id(&prescription.patient) {
             match patient.kind {
                 UserKind::Patient(_) => (),
-                _ => return Err("User not a patient".to_string())
+                _ => return Err(Error::validation("patient", "User not a patient"))
             }
         }
         else {
-            return Err("Patient not found".to_string());
+            return Err(Error::not_found("patient", &prescription.patient));
         }
 
         db.prescriptions.borrow_mut()
@@ -52,37 +61,41 @@ impl PrescriptionsService {
         req: &PrescriptionPostRequest,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<Prescription, String> {
-        let mut prescriptions = db.prescriptions.borrow_mut();
-
-        let prescription = match prescriptions.find_by_id(id) {
-            None => return Err("Not found".to_string()),
-            Some(e) => e.clone()
+    ) -> Result<Prescription, Error> {
+        let prescription = match db.prescriptions.borrow().find_by_id(id) {
+            None => return Err(Error::not_found("prescription", id)),
+            Some(e) => e
         };
         
         if *caller != prescription.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
         if prescription.state != PrescriptionState::Created {
-            return Err("Invalid prescription state".to_string());
+            return Err(Error::invalid_state("Invalid prescription state"));
         }
 
         let doctor = match db.users.borrow().find_by_id(&caller) {
-            None => return Err("Doctor not found".to_string()),
+            None => return Err(Error::not_found("doctor", caller)),
             Some(doctor) => {
                 match doctor.kind.clone() {
                     UserKind::Doctor(doctor) => doctor,
-                    _ => return Err("User not a doctor".to_string())
+                    _ => return Err(Error::Forbidden)
                 }
             }
         };
 
         // validate the certificate
-        if let Err(err) = DoctorsService::validate_cert(
-            &req.cert.as_bytes().to_vec(), &doctor) {
-            return Err(err);
-        }
+        let (cert, cms) = match &req.signature {
+            PrescriptionSignature::Raw { cert, .. } => {
+                (DoctorsService::validate_cert(&cert.as_bytes().to_vec(), &doctor, db)?, None)
+            },
+            PrescriptionSignature::Cms(der) => {
+                let cms = CmsSignedData::from_der(der)
+                    .map_err(|err| Error::validation("cms", &err))?;
+                (DoctorsService::validate_cert_ders(&cms.certs, &doctor, db)?, Some(cms))
+            }
+        };
 
         // calculate the hash of the encrypted content
         let mut hash = Context::new(&SHA256);
@@ -90,67 +103,65 @@ impl PrescriptionsService {
         let cipher_text_hash = hash.finish();
 
         if req.cipher_text_hash != cipher_text_hash.as_ref() {
-            return Err("The cipher text hash doesn't match".to_string());
+            return Err(Error::validation("cipher_text_hash", "The cipher text hash doesn't match"));
         }
 
-        let cert = match DoctorsService::get_top_cert(&req.cert.as_bytes().to_vec()) {
-            Ok(cert) => cert,
-            Err(err) => return Err(err),
-        };
-
         // validate the signature
-        let res = match cert.pub_key.value {
-            PubKeyValue::RSA(key) => {
-                let pkey = RsaPublicKeyComponents { n: key.n, e: key.e };
-                //FIXME: can't assume it's SHA256
-                if let Err(err) = pkey.verify(
-                    &signature::RSA_PKCS1_2048_8192_SHA256, 
+        let res = match (&req.signature, &cms) {
+            (PrescriptionSignature::Raw { signature, algorithm, .. }, _) => {
+                Self::verify_signature(
+                    &cert, 
+                    algorithm, 
                     cipher_text_hash.as_ref(), 
-                    &req.signature
-                ) {
-                    Some(err.to_string())
-                }
-                else {
-                    None
-                }
+                    signature
+                ).map(|_| (signature.clone(), algorithm.clone(), Some(ic_cdk::api::time())))
             },
-            PubKeyValue::EC(_) => {
-                //FIXME: can't verify EC signatures because ring tries to use assembly: https://github.com/briansmith/ring/issues/918
-                /*let pkey = UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, key.data);
-                if let Err(err) = pkey.verify(
-                    cipher_text_hash.as_ref(), 
-                    &req.signature
-                ) {
-                    return Err(err.to_string());
-                }*/
-                Some("Unsupported public key type".to_string())
-            }
-            PubKeyValue::Unknown => {
-                Some("Unsupported public key type".to_string())
-            }
+            (PrescriptionSignature::Cms(_), Some(cms)) => {
+                Self::verify_cms(
+                    &cert, 
+                    cms, 
+                    cipher_text_hash.as_ref()
+                )
+            },
+            _ => Err(Error::internal("CMS SignedData wasn't decoded")),
         };
 
         // if signature failed, remove the prescription
-        if let Some(err) = res {
-            _ = prescriptions.delete_and_notify(&prescription.id);
-            return Err(err);
-        }
+        let (signature, signature_algorithm, signed_at) = match res {
+            Ok(res) => res,
+            Err(err) => {
+                _ = db.transaction(|db| 
+                    db.prescriptions.borrow_mut().delete_and_notify(&prescription.id)
+                );
+                return Err(err);
+            }
+        };
 
         let updated_prescription = Prescription { 
             state: PrescriptionState::Signed,
             cipher_text: Some(req.cipher_text.clone()), 
             cipher_text_hash: Some(cipher_text_hash.as_ref().to_vec()),
-            cert: Some(req.cert.clone()),
+            signature: Some(signature),
+            signature_algorithm: Some(signature_algorithm),
+            cert: match &req.signature {
+                PrescriptionSignature::Raw { cert, .. } => Some(cert.clone()),
+                PrescriptionSignature::Cms(_) => None,
+            },
+            cms: match &req.signature {
+                PrescriptionSignature::Raw { .. } => None,
+                PrescriptionSignature::Cms(der) => Some(der.clone()),
+            },
+            signed_at,
             ..prescription
         };
         
         // update content and hash only
-        if let Err(err) = prescriptions.update_and_notify(
-            id.to_owned(), 
-            updated_prescription.clone()
-        ) {
-            return Err(err);
-        }
+        db.transaction(|db| 
+            db.prescriptions.borrow_mut().update_and_notify(
+                id.to_owned(), 
+                updated_prescription.clone()
+            )
+        )?;
 
         Ok(updated_prescription)
     }
@@ -159,101 +170,415 @@ impl PrescriptionsService {
         id: &PrescriptionId,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut prescriptions = db.prescriptions.borrow_mut();
 
         let prescription = match prescriptions.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("prescription", id)),
+            Some(e) => e
+        };
+
+        Self::check_access(&prescription, &PrescriptionOperation::Delete, db, caller)?;
+        
+        prescriptions.soft_delete_and_notify(id, Some(caller.clone()))
+    }
+
+    pub fn restore(
+        id: &PrescriptionId,
+        retention: &RetentionConfig,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        let mut prescriptions = db.prescriptions.borrow_mut();
+
+        let prescription = match prescriptions.find_deleted(id) {
+            None => return Err(Error::not_found("prescription", id)),
             Some(e) => e
         };
 
         if *caller != prescription.patient {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
-        
-        prescriptions.delete_and_notify(id)
+
+        retention.check_restore(prescription.deleted_at)?;
+
+        // the authorizations deleted with the prescription must be restored one by one
+        prescriptions.restore_and_notify(id)
     }
 
     pub fn find_by_id(
         id: &PrescriptionId,
         db: &DB,
         caller: &Principal
-    ) -> Result<Prescription, String> {
+    ) -> Result<Prescription, Error> {
+        Self::find_for(id, &PrescriptionOperation::View, db, caller)
+    }
+
+    // loads the prescription if the caller is allowed to do op on it
+    pub fn find_for(
+        id: &PrescriptionId,
+        op: &PrescriptionOperation,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Prescription, Error> {
         let prescriptions = db.prescriptions.borrow();
 
         let prescription = match prescriptions.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("prescription", id)),
             Some(e) => e
         };
 
-        if prescription.doctor != *caller && prescription.patient != *caller {
-            if !Self::has_access(db, &prescription.id, caller) {
-                return Err("Forbidden".to_string());
-            }
+        Self::check_access(&prescription, op, db, caller)?;
+
+        Ok(prescription)
+    }
+
+    /*
+     * the patient can do any operation, the doctor can view, fetch the key and annotate. Other users 
+     * need an unexpired grant whose kind allows the operation
+     */
+    pub fn check_access(
+        prescription: &Prescription,
+        op: &PrescriptionOperation,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        if prescription.patient == *caller {
+            return Ok(());
+        }
+
+        if prescription.doctor == *caller && matches!(op, 
+            PrescriptionOperation::View | PrescriptionOperation::FetchKey | PrescriptionOperation::Annotate) {
+            return Ok(());
         }
 
-        Ok(prescription.clone())
+        if !Self::has_access(db, &prescription.id, op, caller) {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(())
+    }
+
+    pub fn verify(
+        id: &PrescriptionId,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<PrescriptionVerificationReport, Error> {
+        let prescription = Self::find_by_id(id, db, caller)?;
+
+        let mut report = PrescriptionVerificationReport::new(&prescription);
+
+        if !report.add_check("state", match prescription.state {
+            PrescriptionState::Signed => Ok(()),
+            _ => Err("Prescription is not signed".to_string()),
+        }) {
+            return Ok(report);
+        }
+
+        // load the certificate chain stored at signing time
+        let (chain, cms) = match (&prescription.cms, &prescription.cert) {
+            (Some(der), _) => {
+                match CmsSignedData::from_der(der)
+                    .map_err(|err| Error::validation("cms", &err))
+                    .and_then(|cms| DoctorsService::get_chain_ders(&cms.certs).map(|chain| (chain, Some(cms)))) {
+                    Ok(res) => res,
+                    Err(err) => {
+                        report.add_check("chain", Err(err.to_string()));
+                        return Ok(report);
+                    }
+                }
+            },
+            (None, Some(pem)) => match DoctorsService::get_chain(&pem.as_bytes().to_vec()) {
+                Ok(chain) => (chain, None),
+                Err(err) => {
+                    report.add_check("chain", Err(err.to_string()));
+                    return Ok(report);
+                }
+            },
+            (None, None) => {
+                report.add_check("chain", Err("No certificate was stored".to_string()));
+                return Ok(report);
+            }
+        };
+
+        let cert = match chain.get_top() {
+            Ok(cert) => cert,
+            Err(err) => {
+                report.add_check("chain", Err(err.to_string()));
+                return Ok(report);
+            }
+        };
+
+        report.signer = Some(cert.subject.clone());
+        report.serial = Some(cert.serial.clone());
+        report.not_before = Some((cert.validity.not_before.timestamp().max(0) as u64) * 1_000_000_000);
+        report.not_after = Some((cert.validity.not_after.timestamp().max(0) as u64) * 1_000_000_000);
+
+        let roots = TrustedCertsService::get_roots(db);
+        report.add_check("chain", chain.verify_path(&cert, &roots)
+            .map_err(|err| err.to_string()));
+
+        report.add_check("revocation", CrlsService::check_revocation(&chain.chain, db)
+            .map_err(|err| err.to_string()));
+
+        // prescriptions stored without a signing time were signed right after being created
+        let signed_at = prescription.signed_at.unwrap_or(prescription.created_at);
+        report.add_check("validity_at_signing", cert.check_validity_at(signed_at)
+            .map_err(|err| err.to_string()));
+
+        report.add_check("validity_now", cert.check_validity()
+            .map_err(|err| err.to_string()));
+
+        let res = match db.users.borrow().find_by_id(&prescription.doctor) {
+            None => Err("Doctor not found".to_string()),
+            Some(user) => match &user.kind {
+                UserKind::Doctor(doctor) => {
+                    report.license_num = DoctorsService::get_license_num(&cert, doctor).ok();
+                    if report.license_num.as_ref() == Some(&doctor.license_num) {
+                        Ok(())
+                    }
+                    else {
+                        Err("Certificate license number is different from doctor's one".to_string())
+                    }
+                },
+                _ => Err("User not a doctor".to_string()),
+            }
+        };
+        report.add_check("license", res);
+
+        // the stored hash must match the stored content
+        let cipher_text_hash = prescription.cipher_text_hash.clone().unwrap_or_default();
+        report.add_check("hash", match &prescription.cipher_text {
+            None => Err("No cipher text was stored".to_string()),
+            Some(cipher_text) => if digest::digest(&SHA256, cipher_text).as_ref() == cipher_text_hash.as_slice() {
+                Ok(())
+            }
+            else {
+                Err("The cipher text hash doesn't match".to_string())
+            }
+        });
+
+        let res = match &cms {
+            Some(cms) => Self::verify_cms(&cert, cms, &cipher_text_hash)
+                .map(|_| ()),
+            None => match &prescription.signature {
+                None => Err(Error::validation("signature", "No signature was stored")),
+                // prescriptions signed before the algorithm was declared were always PKCS#1 v1.5 with SHA-256
+                Some(signature) => Self::verify_signature(
+                    &cert, 
+                    prescription.signature_algorithm.as_ref().unwrap_or(&SignatureAlgorithm::RsaPkcs1Sha256), 
+                    &cipher_text_hash, 
+                    signature
+                ),
+            }
+        };
+        report.add_check("signature", res.map_err(|err| err.to_string()));
+
+        Ok(report)
     }
 
     const DERIVATION_PATH: &[u8; 13] = b"prescriptions";
 
     pub async fn get_public_key(
         vetkd: VetKdUtil
-    ) -> Result<String, String> {
+    ) -> Result<String, Error> {
         vetkd.get_public_key(vec![Self::DERIVATION_PATH.to_vec()])
             .await
+            .map_err(|err| Error::crypto_failure(&err))
     }
 
     pub async fn get_encrypted_symmetric_key(
         prescription: Prescription,
         encryption_public_key: Vec<u8>,
         vetkd: VetKdUtil
-    ) -> Result<String, String> {
+    ) -> Result<String, Error> {
         vetkd.get_encrypted_symmetric_key(
             vec![Self::DERIVATION_PATH.to_vec()], 
             prescription.id.as_bytes().to_owned(),
             encryption_public_key
         ).await
+        .map_err(|err| Error::crypto_failure(&err))
     }
 
     pub fn has_access(
         db: &DB, 
         prescription_id: &PrescriptionId, 
+        op: &PrescriptionOperation,
         user: &Principal
     ) -> bool {
+        Self::find_grant(db, prescription_id, op, user).is_some()
+    }
+
+    // an unexpired grant of the prescription, held by the user, that allows op
+    pub fn find_grant(
+        db: &DB, 
+        prescription_id: &PrescriptionId, 
+        op: &PrescriptionOperation,
+        user: &Principal
+    ) -> Option<PrescriptionAuth> {
+        Self::find_grants(db, prescription_id, user).into_iter()
+            .find(|e| e.kind.allows(op))
+    }
+
+    // unexpired grants of the prescription held by the user, directly or through a group
+    pub fn find_grants(
+        db: &DB, 
+        prescription_id: &PrescriptionId, 
+        user: &Principal
+    ) -> Vec<PrescriptionAuth> {
+        let ids = match db.prescription_auths_rel.borrow().find_by_id(prescription_id) {
+            None => return vec![],
+            Some(ids) => ids
+        };
+
         let now = ic_cdk::api::time();
-        match db.prescription_auths_rel.borrow().find_by_id(prescription_id) {
-            None => return false,
-            Some(ids) => {
-                if !ids.iter().map(|id| 
-                    db.prescription_auths.borrow().get(id).clone()
-                    ).any(|e| {
-                        if let Some(expiration) = e.expires_at {
-                            if now > expiration {
-                                return false;
-                            }
-                        }
-                        
-                        match e.to {
-                            PrescriptionAuthSubject::User(to) => 
-                                if to != *user {
-                                    return false;
-                                },
-                            PrescriptionAuthSubject::Group(to) => {
-                                match db.groups.borrow().find_by_id(&to) {
-                                    None => return false,
-                                    Some(group) => return group.members.contains(user)
-                                }
-                            },
+        let auths = db.prescription_auths.borrow();
+        ids.iter()
+            .filter_map(|id| auths.find_by_id(id))
+            .filter(|e| {
+                if e.is_expired(now) {
+                    return false;
+                }
+                
+                match &e.to {
+                    PrescriptionAuthSubject::User(to) => 
+                        *to == *user,
+                    PrescriptionAuthSubject::Group(to) => {
+                        match db.groups.borrow().find_by_id(to) {
+                            None => false,
+                            Some(group) => group.members.contains(user)
                         }
+                    },
+                }
+            })
+            .collect()
+    }
 
-                        true
-                    }) {
-                        return false;
+    fn verify_cms(
+        cert: &X509Cert,
+        cms: &CmsSignedData,
+        msg: &[u8]
+    ) -> Result<(Vec<u8>, SignatureAlgorithm, Option<u64>), Error> {
+        let signer = &cms.signer;
+
+        if cert.raw_issuer != signer.raw_issuer || cert.serial != signer.serial {
+            return Err(Error::validation("cms", "Signer doesn't match the signing certificate"));
+        }
+
+        // if attached, the signed content must be the cipher text hash
+        if let Some(content) = &cms.content {
+            if content.as_slice() != msg {
+                return Err(Error::validation("cms", "Signed content doesn't match the cipher text hash"));
+            }
+        }
+
+        let signed_attrs = match &signer.signed_attrs {
+            None => return Err(Error::validation("cms", "Signed attributes are missing")),
+            Some(attrs) => attrs,
+        };
+
+        let alg = signer.get_digest()
+            .map_err(|err| Error::validation("cms", &err))?;
+
+        match &signer.message_digest {
+            None => return Err(Error::validation("cms", "Message digest attribute is missing")),
+            Some(message_digest) => {
+                if message_digest.as_slice() != digest::digest(alg, msg).as_ref() {
+                    return Err(Error::crypto_failure("Message digest doesn't match the cipher text hash"));
                 }
             }
         }
-        true
+
+        // the signingTime attribute is optional: without it, the prescription is signed when it's uploaded, like the raw ones
+        let now = ic_cdk::api::time();
+        let signed_at = signer.signing_time.unwrap_or(now);
+        let not_before = (cert.validity.not_before.timestamp().max(0) as u64) * 1_000_000_000;
+        let not_after = (cert.validity.not_after.timestamp().max(0) as u64) * 1_000_000_000;
+        if signed_at > now || signed_at < not_before || signed_at > not_after {
+            return Err(Error::validation("cms", "Signing time is outside the certificate validity"));
+        }
+
+        let algorithm = Self::get_cms_algorithm(&signer.sig_algorithm, &signer.digest_algorithm)?;
+
+        Self::verify_signature(cert, &algorithm, signed_attrs, &signer.signature)?;
+
+        Ok((signer.signature.clone(), algorithm, Some(signed_at)))
+    }
+
+    fn get_cms_algorithm(
+        sig_algorithm: &str,
+        digest_algorithm: &str
+    ) -> Result<SignatureAlgorithm, Error> {
+        let algorithm = match (sig_algorithm, digest_algorithm) {
+            (OID_RSA_ENCRYPTION, cms::OID_SHA256) | (OID_SHA256_WITH_RSA, _) => SignatureAlgorithm::RsaPkcs1Sha256,
+            (OID_RSA_ENCRYPTION, cms::OID_SHA384) | (OID_SHA384_WITH_RSA, _) => SignatureAlgorithm::RsaPkcs1Sha384,
+            (OID_RSA_ENCRYPTION, cms::OID_SHA512) | (OID_SHA512_WITH_RSA, _) => SignatureAlgorithm::RsaPkcs1Sha512,
+            (OID_EC_PUBLIC_KEY, cms::OID_SHA256) | (OID_ECDSA_WITH_SHA256, _) => SignatureAlgorithm::EcdsaSha256,
+            (OID_EC_PUBLIC_KEY, cms::OID_SHA384) | (OID_ECDSA_WITH_SHA384, _) => SignatureAlgorithm::EcdsaSha384,
+            (OID_EC_PUBLIC_KEY, cms::OID_SHA512) | (OID_ECDSA_WITH_SHA512, _) => SignatureAlgorithm::EcdsaSha512,
+            _ => return Err(Error::validation("cms", &format!("Unsupported signature algorithm: {}", sig_algorithm))),
+        };
+
+        Ok(algorithm)
+    }
+
+    fn verify_signature(
+        cert: &X509Cert,
+        algorithm: &SignatureAlgorithm,
+        msg: &[u8],
+        sig: &[u8]
+    ) -> Result<(), Error> {
+        // the declared algorithm must be compatible with the certificate's public key
+        match &cert.pub_key.value {
+            PubKeyValue::RSA(key) => {
+                if cert.pub_key.algorithm != OID_RSA_ENCRYPTION && cert.pub_key.algorithm != OID_RSASSA_PSS {
+                    return Err(Error::validation("algorithm", "Certificate public key algorithm is not RSA"));
+                }
+
+                let params: &RsaParameters = match algorithm {
+                    SignatureAlgorithm::RsaPkcs1Sha256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    SignatureAlgorithm::RsaPkcs1Sha384 => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    SignatureAlgorithm::RsaPkcs1Sha512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+                    SignatureAlgorithm::RsaPssSha256 => &signature::RSA_PSS_2048_8192_SHA256,
+                    SignatureAlgorithm::RsaPssSha384 => &signature::RSA_PSS_2048_8192_SHA384,
+                    SignatureAlgorithm::RsaPssSha512 => &signature::RSA_PSS_2048_8192_SHA512,
+                    _ => return Err(Error::validation("algorithm", "Signature algorithm doesn't match the certificate's RSA key")),
+                };
+
+                // keys restricted to PSS can't be used with PKCS#1 v1.5
+                if cert.pub_key.algorithm == OID_RSASSA_PSS {
+                    match algorithm {
+                        SignatureAlgorithm::RsaPkcs1Sha256 |
+                        SignatureAlgorithm::RsaPkcs1Sha384 |
+                        SignatureAlgorithm::RsaPkcs1Sha512 => 
+                            return Err(Error::validation("algorithm", "Certificate key is restricted to RSA-PSS")),
+                        _ => ()
+                    }
+                }
+
+                let pkey = RsaPublicKeyComponents { n: &key.n, e: &key.e };
+                pkey.verify(params, msg, sig)
+                    .map_err(|err| Error::crypto_failure(&err.to_string()))
+            },
+            PubKeyValue::EC(key) => {
+                if cert.pub_key.algorithm != OID_EC_PUBLIC_KEY {
+                    return Err(Error::validation("algorithm", "Certificate public key algorithm is not EC"));
+                }
+
+                let alg: &'static digest::Algorithm = match algorithm {
+                    SignatureAlgorithm::EcdsaSha256 => &digest::SHA256,
+                    SignatureAlgorithm::EcdsaSha384 => &digest::SHA384,
+                    SignatureAlgorithm::EcdsaSha512 => &digest::SHA512,
+                    _ => return Err(Error::validation("algorithm", "Signature algorithm doesn't match the certificate's EC key")),
+                };
+
+                ecdsa::verify(&key.curve, &key.data, alg, msg, sig)
+                    .map_err(|err| Error::crypto_failure(&err))
+            },
+            PubKeyValue::Unknown => {
+                Err(Error::crypto_failure("Unsupported public key type"))
+            }
+        }
     }
 }
 
@@ -0,0 +1,87 @@
+use ic_stable_structures::StableBTreeMap;
+use crate::db::DB;
+use crate::db::memory::{get_memory, Memory, Stored, REBUILD_MEMORY_ID};
+use crate::db::traits::transaction::Transactional;
+use crate::models::consistency::RebuildStatus;
+use crate::models::error::Error;
+use crate::models::maintenance::MaintenanceKind;
+use super::maintenance::MaintenanceService;
+
+// rows indexed per message, so a chunk fits in the instruction limit of an update call
+const REBUILD_ROWS_PER_CHUNK: usize = 1000;
+
+pub struct RelationsService {}
+
+impl RelationsService {
+    // rebuilds the relation tables and indexes, keeping the DB read-only until it's done
+    pub fn rebuild(
+        db: &mut DB,
+        now: u64
+    ) -> Result<RebuildStatus, Error> {
+        if !MaintenanceService::is_active(&MaintenanceKind::RelationsRebuild) {
+            MaintenanceService::begin(MaintenanceKind::RelationsRebuild, now)?;
+        }
+
+        let status = Self::rebuild_chunk(db, now)?;
+        if status.done {
+            MaintenanceService::end(&MaintenanceKind::RelationsRebuild)?;
+        }
+
+        Ok(status)
+    }
+
+    /*
+     * runs the next chunk of the rebuild in progress, starting one if needed. It must be called until
+     * the status returned is done, while writes are rejected by a maintenance
+     */
+    pub fn rebuild_chunk(
+        db: &mut DB,
+        now: u64
+    ) -> Result<RebuildStatus, Error> {
+        let status = match Self::load_rebuild() {
+            Some(status) => status,
+            // the memories are reinitialised, as clearing the tables row by row wouldn't fit in a message
+            None => db.rebuild_begin(now),
+        };
+
+        let status = db.transaction(|db| db.rebuild_chunk(&status, REBUILD_ROWS_PER_CHUNK))
+            .map_err(|err| Error::internal(&err))?;
+
+        if status.done {
+            Self::clear_rebuild();
+        }
+        else {
+            Self::save_rebuild(&status);
+        }
+
+        Ok(status)
+    }
+
+    // discards the rebuild in progress, when the tables it was rebuilding are reset
+    pub fn rebuild_abort(
+    ) {
+        Self::clear_rebuild();
+    }
+
+    // the rebuild in progress is kept in stable memory, so it can be resumed after an upgrade
+    fn rebuild_map(
+    ) -> StableBTreeMap<u32, Stored<RebuildStatus>, Memory> {
+        StableBTreeMap::init(get_memory(REBUILD_MEMORY_ID))
+    }
+
+    fn load_rebuild(
+    ) -> Option<RebuildStatus> {
+        Self::rebuild_map().get(&0).map(|status| status.0)
+    }
+
+    fn save_rebuild(
+        status: &RebuildStatus
+    ) {
+        Self::rebuild_map().insert(0, Stored(status.clone()));
+    }
+
+    fn clear_rebuild(
+    ) {
+        Self::rebuild_map().remove(&0);
+    }
+}
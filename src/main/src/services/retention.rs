@@ -0,0 +1,23 @@
+use crate::db::DB;
+use crate::db::traits::crud::CrudSoftDeletable;
+
+// rows purged per run, to stay within the instructions limit
+const MAX_PURGED_PER_RUN: usize = 1000;
+
+pub struct RetentionService {}
+
+impl RetentionService {
+    // physically removes the rows soft deleted before the given time. Returns the number of rows removed
+    pub fn purge(
+        deleted_before: u64,
+        db: &mut DB
+    ) -> usize {
+        let mut purged = 0;
+        purged += db.prescription_auths.borrow_mut().purge(deleted_before, MAX_PURGED_PER_RUN - purged);
+        purged += db.prescriptions.borrow_mut().purge(deleted_before, MAX_PURGED_PER_RUN - purged);
+        purged += db.prescription_templates.borrow_mut().purge(deleted_before, MAX_PURGED_PER_RUN - purged);
+        purged += db.keys.borrow_mut().purge(deleted_before, MAX_PURGED_PER_RUN - purged);
+        purged += db.users.borrow_mut().purge(deleted_before, MAX_PURGED_PER_RUN - purged);
+        purged
+    }
+}
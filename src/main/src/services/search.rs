@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+use candid::Principal;
+use crate::db::DB;
+use crate::db::traits::crud::{Crud, Page, Pagination};
+use crate::db::traits::table::Table;
+use crate::models::error::Error;
+use crate::models::search::{SearchField, SearchRequest, SEARCH_INDEX, MIN_PREFIX_LEN, MAX_QUERY_TERMS, MAX_MATCHES};
+use crate::models::user::{User, UserId};
+
+pub struct SearchService {}
+
+impl SearchService {
+    // finds the active users matching all the terms of the query, in any of the fields requested
+    pub fn search_users(
+        req: &SearchRequest,
+        pag: Pagination,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Page<User>, Error> {
+        if *caller == Principal::anonymous() {
+            return Err(Error::Unauthenticated);
+        }
+
+        let users = db.users.borrow();
+
+        if users.find_by_id(caller).is_none() {
+            return Err(Error::Forbidden);
+        }
+
+        let terms = req.terms();
+        if terms.is_empty() {
+            return Err(Error::validation("query", "Query can't be empty"));
+        }
+        if terms.len() > MAX_QUERY_TERMS {
+            return Err(Error::validation("query", &format!("Query can't have more than {} terms", MAX_QUERY_TERMS)));
+        }
+
+        let fields = req.fields();
+        if fields.is_empty() {
+            return Err(Error::validation("fields", "At least one field must be searched"));
+        }
+
+        let mut ids: Option<BTreeSet<UserId>> = None;
+        for term in terms {
+            let mut matched = BTreeSet::new();
+            for field in &fields {
+                matched.extend(Self::find_term(field, term, db));
+            }
+
+            ids = Some(match ids {
+                None => matched,
+                Some(ids) => ids.intersection(&matched).cloned().collect(),
+            });
+        }
+
+        let ids = ids.unwrap_or_default();
+        pag.paginate(&ids, |id| users.find_by_id(id)
+            .filter(|u| u.active && !u.banned && pag.accepts(u.created_at))
+        )
+    }
+
+    // a term split into more than one token must match all of them
+    fn find_term(
+        field: &SearchField,
+        term: &str,
+        db: &DB
+    ) -> BTreeSet<UserId> {
+        let mut ids: Option<BTreeSet<UserId>> = None;
+        for token in field.tokens(term) {
+            let matched = Self::find_token(field, &token, db);
+            ids = Some(match ids {
+                None => matched,
+                Some(ids) => ids.intersection(&matched).cloned().collect(),
+            });
+        }
+
+        ids.unwrap_or_default()
+    }
+
+    fn find_token(
+        field: &SearchField,
+        token: &str,
+        db: &DB
+    ) -> BTreeSet<UserId> {
+        match field {
+            SearchField::KeyValue => {
+                let keys = db.keys.borrow();
+                let users = db.users.borrow();
+
+                // the key's owner must be searchable by key value
+                keys.find_by_index(SEARCH_INDEX, &field.key(token)).into_iter()
+                    .take(MAX_MATCHES)
+                    .map(|key| key.created_by)
+                    .filter(|id| users.find_by_id(id)
+                        .map_or(false, |u| SearchField::searchable_by(&u.kind).contains(field))
+                    )
+                    .collect()
+            },
+            _ => {
+                let users = db.users.borrow();
+                let data = users.get_data();
+
+                if field.is_prefix() && token.chars().count() >= MIN_PREFIX_LEN {
+                    data.find_ids_in_range(SEARCH_INDEX, &field.key(token), &field.key_prefix_end(token), MAX_MATCHES)
+                        .into_iter()
+                        .collect()
+                }
+                else {
+                    data.find_ids(SEARCH_INDEX, &field.key(token)).into_iter()
+                        .take(MAX_MATCHES)
+                        .collect()
+                }
+            },
+        }
+    }
+}
\ No newline at end of file
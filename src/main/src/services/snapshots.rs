@@ -0,0 +1,332 @@
+use candid::CandidType;
+use ic_stable_structures::StableBTreeMap;
+use ring::digest;
+use serde::Deserialize;
+use crate::db::{DB, TableName, TABLES};
+use crate::db::memory::{get_memory, get_writes, Memory, Stored, SNAPSHOT_IMPORT_MEMORY_ID};
+use crate::db::traits::table::{Table, TableSnapshotable};
+use crate::models::consistency::RebuildStatus;
+use crate::models::error::Error;
+use crate::models::maintenance::MaintenanceKind;
+use crate::models::snapshot::{SnapshotManifest, SnapshotTableInfo, SnapshotExportRequest, SnapshotChunk, SnapshotImportStatus};
+use super::maintenance::MaintenanceService;
+use super::relations::RelationsService;
+
+// 2: tables are versioned with integers
+// 3: chunks are exported from the DB version recorded in the manifest
+const SNAPSHOT_VERSION: u32 = 3;
+// must leave room for the other fields, as chunks are sent back as ingress messages (2MB max) when importing
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+pub struct SnapshotsService {}
+
+impl SnapshotsService {
+    pub fn get_manifest(
+        db: &DB
+    ) -> SnapshotManifest {
+        SnapshotManifest {
+            version: SNAPSHOT_VERSION,
+            created_at: ic_cdk::api::time(),
+            db_version: get_writes(),
+            tables: TABLES.iter()
+                .map(|table| match table {
+                    TableName::Users => Self::get_info(&*db.users.borrow()),
+                    TableName::Keys => Self::get_info(&*db.keys.borrow()),
+                    TableName::Prescriptions => Self::get_info(&*db.prescriptions.borrow()),
+                    TableName::PrescriptionAuths => Self::get_info(&*db.prescription_auths.borrow()),
+                    TableName::PrescriptionTemplates => Self::get_info(&*db.prescription_templates.borrow()),
+                    TableName::UserPrescriptionsRel => Self::get_info(&*db.user_prescriptions_rel.borrow()),
+                    TableName::PrescriptionAuthsRel => Self::get_info(&*db.prescription_auths_rel.borrow()),
+                    TableName::PrincipalKeysRel => Self::get_info(&*db.principal_keys_rel.borrow()),
+                    TableName::KeyPrincipalRel => Self::get_info(&*db.key_principal_rel.borrow()),
+                    TableName::Groups => Self::get_info(&*db.groups.borrow()),
+                    TableName::PrincipalGroupsRel => Self::get_info(&*db.principal_groups_rel.borrow()),
+                    TableName::DoctorTemplatesRel => Self::get_info(&*db.doctor_templates_rel.borrow()),
+                    TableName::TrustedCerts => Self::get_info(&*db.trusted_certs.borrow()),
+                    TableName::Crls => Self::get_info(&*db.crls.borrow()),
+                    TableName::AccessLogs => Self::get_info(&*db.access_logs.borrow()),
+                })
+                .collect(),
+        }
+    }
+
+    // chunks are exported in many messages: the DB is kept read-only until the export is finished,
+    // so all of them see the rows counted by the manifest
+    pub fn export_begin(
+        db: &DB,
+        now: u64
+    ) -> Result<SnapshotManifest, Error> {
+        MaintenanceService::begin(MaintenanceKind::SnapshotExport, now)?;
+
+        Ok(Self::get_manifest(db))
+    }
+
+    pub fn export(
+        req: &SnapshotExportRequest,
+        db: &DB
+    ) -> Result<SnapshotChunk, Error> {
+        if !MaintenanceService::is_active(&MaintenanceKind::SnapshotExport) {
+            return Err(Error::invalid_state("No snapshot export in progress"));
+        }
+
+        let cursor = &req.cursor;
+        let (version, (data, rows, next)) = match req.table {
+            TableName::Users => Self::export_from(&*db.users.borrow(), cursor),
+            TableName::Keys => Self::export_from(&*db.keys.borrow(), cursor),
+            TableName::Prescriptions => Self::export_from(&*db.prescriptions.borrow(), cursor),
+            TableName::PrescriptionAuths => Self::export_from(&*db.prescription_auths.borrow(), cursor),
+            TableName::PrescriptionTemplates => Self::export_from(&*db.prescription_templates.borrow(), cursor),
+            TableName::UserPrescriptionsRel => Self::export_from(&*db.user_prescriptions_rel.borrow(), cursor),
+            TableName::PrescriptionAuthsRel => Self::export_from(&*db.prescription_auths_rel.borrow(), cursor),
+            TableName::PrincipalKeysRel => Self::export_from(&*db.principal_keys_rel.borrow(), cursor),
+            TableName::KeyPrincipalRel => Self::export_from(&*db.key_principal_rel.borrow(), cursor),
+            TableName::Groups => Self::export_from(&*db.groups.borrow(), cursor),
+            TableName::PrincipalGroupsRel => Self::export_from(&*db.principal_groups_rel.borrow(), cursor),
+            TableName::DoctorTemplatesRel => Self::export_from(&*db.doctor_templates_rel.borrow(), cursor),
+            TableName::TrustedCerts => Self::export_from(&*db.trusted_certs.borrow(), cursor),
+            TableName::Crls => Self::export_from(&*db.crls.borrow(), cursor),
+            TableName::AccessLogs => Self::export_from(&*db.access_logs.borrow(), cursor),
+        }.map_err(|err| Error::validation("cursor", &err))?;
+
+        Ok(SnapshotChunk {
+            table: req.table.clone(),
+            version,
+            index: req.index,
+            rows,
+            checksum: Self::checksum(&data),
+            data,
+            next,
+        })
+    }
+
+    pub fn export_finish(
+    ) -> Result<(), Error> {
+        MaintenanceService::end(&MaintenanceKind::SnapshotExport)
+    }
+
+    pub fn import_begin(
+        manifest: &SnapshotManifest,
+        db: &mut DB
+    ) -> Result<SnapshotImportStatus, Error> {
+        if manifest.version != SNAPSHOT_VERSION {
+            return Err(Error::validation("version", "Unsupported snapshot version"));
+        }
+
+        for table in TABLES.iter() {
+            let count = manifest.tables.iter()
+                .filter(|e| e.table == *table)
+                .count();
+            if count > 1 {
+                return Err(Error::validation("tables", &format!("Duplicated table: {}", table)));
+            }
+            if count == 0 && !table.is_relation() {
+                return Err(Error::validation("tables", &format!("Missing table: {}", table)));
+            }
+        }
+
+        if Self::load_import().is_some() {
+            return Err(Error::invalid_state("A snapshot import is already in progress. It must be finished or aborted first"));
+        }
+
+        // the DB is kept read-only until the import is finished or aborted
+        MaintenanceService::begin(MaintenanceKind::SnapshotImport, ic_cdk::api::time())?;
+
+        // the memories are reinitialised, as clearing the tables row by row wouldn't fit in a message
+        Self::reset_tables(db);
+
+        let status = SnapshotImportStatus::new(manifest);
+        Self::save_import(&status);
+
+        Ok(status)
+    }
+
+    pub fn import_chunk(
+        chunk: &SnapshotChunk,
+        db: &mut DB
+    ) -> Result<SnapshotImportStatus, Error> {
+        let mut status = match Self::load_import() {
+            None => return Err(Error::invalid_state("No snapshot import in progress")),
+            Some(status) => status,
+        };
+
+        let table = match status.tables.iter().find(|e| e.table == chunk.table) {
+            None => return Err(Error::validation("table", "Table is not listed in the manifest")),
+            Some(table) => table,
+        };
+
+        // chunks already imported can be sent again when resuming
+        if chunk.index < table.chunks {
+            return Ok(status);
+        }
+
+        if table.done {
+            return Err(Error::invalid_state("All chunks of this table were already imported"));
+        }
+
+        if chunk.index > table.chunks {
+            return Err(Error::validation("index", &format!("Chunk {} was expected", table.chunks)));
+        }
+
+        if Self::checksum(&chunk.data) != chunk.checksum {
+            return Err(Error::validation("checksum", "Chunk checksum doesn't match its data"));
+        }
+
+        // relation tables are rebuilt when the import finishes
+        let rows = if chunk.table.is_relation() {
+            chunk.rows
+        }
+        else {
+            let version = chunk.version;
+            let data = &chunk.data;
+            match chunk.table {
+                TableName::Users => db.users.borrow_mut().import_chunk(version, data),
+                TableName::Keys => db.keys.borrow_mut().import_chunk(version, data),
+                TableName::Prescriptions => db.prescriptions.borrow_mut().import_chunk(version, data),
+                TableName::PrescriptionAuths => db.prescription_auths.borrow_mut().import_chunk(version, data),
+                TableName::PrescriptionTemplates => db.prescription_templates.borrow_mut().import_chunk(version, data),
+                TableName::Groups => db.groups.borrow_mut().import_chunk(version, data),
+                TableName::TrustedCerts => db.trusted_certs.borrow_mut().import_chunk(version, data),
+                TableName::Crls => db.crls.borrow_mut().import_chunk(version, data),
+                TableName::AccessLogs => db.access_logs.borrow_mut().import_chunk(version, data),
+                _ => Ok(chunk.rows),
+            }.map_err(|err| Error::validation("data", &err))?
+        };
+
+        let table = status.tables.iter_mut()
+            .find(|e| e.table == chunk.table)
+            .unwrap();
+        table.chunks += 1;
+        table.rows += rows as u64;
+        table.done = chunk.next.is_none();
+
+        Self::save_import(&status);
+
+        Ok(status)
+    }
+
+    // the relations and indexes are rebuilt in chunks, so it must be called until the status returned is done
+    pub fn import_finish(
+        db: &mut DB
+    ) -> Result<RebuildStatus, Error> {
+        let status = match Self::load_import() {
+            None => return Err(Error::invalid_state("No snapshot import in progress")),
+            Some(status) => status,
+        };
+
+        for table in &status.tables {
+            if !table.done {
+                return Err(Error::invalid_state(&format!("Table {} wasn't fully imported", table.table)));
+            }
+            if table.rows != table.expected_rows {
+                return Err(Error::invalid_state(&format!(
+                    "Table {} has {} rows but {} were expected", table.table, table.rows, table.expected_rows
+                )));
+            }
+        }
+
+        let status = RelationsService::rebuild_chunk(db, ic_cdk::api::time())?;
+        if status.done {
+            Self::clear_import();
+            MaintenanceService::end(&MaintenanceKind::SnapshotImport)?;
+        }
+
+        Ok(status)
+    }
+
+    // discards the import in progress and the rows imported so far
+    pub fn import_abort(
+        db: &mut DB
+    ) -> Result<(), Error> {
+        if Self::load_import().is_none() {
+            return Err(Error::invalid_state("No snapshot import in progress"));
+        }
+
+        Self::reset_tables(db);
+        RelationsService::rebuild_abort();
+        Self::clear_import();
+        MaintenanceService::end(&MaintenanceKind::SnapshotImport)?;
+
+        Ok(())
+    }
+
+    pub fn import_status(
+    ) -> Result<SnapshotImportStatus, Error> {
+        match Self::load_import() {
+            None => Err(Error::invalid_state("No snapshot import in progress")),
+            Some(status) => Ok(status),
+        }
+    }
+
+    fn reset_tables(
+        db: &mut DB
+    ) {
+        db.users.borrow_mut().get_data_mut().reset();
+        db.keys.borrow_mut().get_data_mut().reset();
+        db.prescriptions.borrow_mut().get_data_mut().reset();
+        db.prescription_auths.borrow_mut().get_data_mut().reset();
+        db.prescription_templates.borrow_mut().get_data_mut().reset();
+        db.user_prescriptions_rel.borrow_mut().get_data_mut().reset();
+        db.prescription_auths_rel.borrow_mut().get_data_mut().reset();
+        db.principal_keys_rel.borrow_mut().get_data_mut().reset();
+        db.key_principal_rel.borrow_mut().get_data_mut().reset();
+        db.groups.borrow_mut().get_data_mut().reset();
+        db.principal_groups_rel.borrow_mut().get_data_mut().reset();
+        db.doctor_templates_rel.borrow_mut().get_data_mut().reset();
+        db.trusted_certs.borrow_mut().get_data_mut().reset();
+        db.crls.borrow_mut().get_data_mut().reset();
+        db.access_logs.borrow_mut().get_data_mut().reset();
+    }
+
+    // the import in progress is kept in stable memory, so it can be resumed after an upgrade
+    fn import_map(
+    ) -> StableBTreeMap<u32, Stored<SnapshotImportStatus>, Memory> {
+        StableBTreeMap::init(get_memory(SNAPSHOT_IMPORT_MEMORY_ID))
+    }
+
+    fn load_import(
+    ) -> Option<SnapshotImportStatus> {
+        Self::import_map().get(&0).map(|status| status.0)
+    }
+
+    fn save_import(
+        status: &SnapshotImportStatus
+    ) {
+        Self::import_map().insert(0, Stored(status.clone()));
+    }
+
+    fn clear_import(
+    ) {
+        Self::import_map().remove(&0);
+    }
+
+    fn get_info<T, K, V>(
+        table: &T
+    ) -> SnapshotTableInfo
+        where
+            T: Table<TableName, K, V>,
+            K: Ord + Clone + CandidType + for<'a> Deserialize<'a>,
+            V: CandidType + for<'a> Deserialize<'a> {
+        SnapshotTableInfo {
+            table: table.get_schema().name.clone(),
+            version: table.get_schema().version,
+            rows: table.get_data().len(),
+        }
+    }
+
+    fn export_from<T, K, V>(
+        table: &T,
+        cursor: &Option<Vec<u8>>
+    ) -> Result<(u32, (Vec<u8>, u32, Option<Vec<u8>>)), String>
+        where
+            T: TableSnapshotable<TableName, K, V>,
+            K: Ord + Clone + CandidType + for<'a> Deserialize<'a>,
+            V: CandidType + for<'a> Deserialize<'a> {
+        Ok((table.get_schema().version, table.export_chunk(cursor, MAX_CHUNK_SIZE)?))
+    }
+
+    fn checksum(
+        data: &[u8]
+    ) -> String {
+        hex::encode(digest::digest(&digest::SHA256, data).as_ref())
+    }
+}
@@ -0,0 +1,66 @@
+use candid::Principal;
+use ring::digest::{digest, SHA256};
+use crate::db::DB;
+use crate::db::traits::crud::Crud;
+use crate::db::traits::table::Table;
+use crate::models::error::Error;
+use crate::models::trusted_cert::{TrustedCert, TrustedCertId, TrustedCertRequest};
+use crate::utils::x509::{X509Cert, pem_to_der};
+
+pub struct TrustedCertsService {}
+
+impl TrustedCertsService {
+    pub fn create(
+        req: &TrustedCertRequest,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<TrustedCert, Error> {
+        let ders = pem_to_der(req.cert.as_bytes())
+            .map_err(|err| Error::validation("cert", &err.to_string()))?;
+
+        if ders.len() != 1 {
+            return Err(Error::validation("cert", "Exactly one certificate must be informed"));
+        }
+
+        let der = &ders[0];
+        let cert = X509Cert::from_der(der, &vec![])
+            .map_err(|err| Error::validation("cert", &err.to_string()))?;
+
+        if !cert.is_ca {
+            return Err(Error::validation("cert", "Certificate is not a CA"));
+        }
+
+        cert.check_validity()
+            .map_err(|err| Error::validation("cert", &err.to_string()))?;
+
+        let id = hex::encode(digest(&SHA256, der));
+        let trusted = TrustedCert::new(&id, &cert.subject, der, caller);
+
+        db.trusted_certs.borrow_mut().insert(id, trusted.clone())?;
+
+        Ok(trusted)
+    }
+
+    pub fn delete(
+        id: &TrustedCertId,
+        db: &mut DB,
+        _caller: &Principal
+    ) -> Result<(), Error> {
+        db.trusted_certs.borrow_mut().delete(id)
+    }
+
+    pub fn find_all(
+        db: &DB
+    ) -> Vec<TrustedCert> {
+        db.trusted_certs.borrow().get_data().values()
+            .collect()
+    }
+
+    pub fn get_roots(
+        db: &DB
+    ) -> Vec<X509Cert> {
+        db.trusted_certs.borrow().get_data().values()
+            .filter_map(|e| X509Cert::from_der(&e.der, &vec![]).ok())
+            .collect()
+    }
+}
@@ -1,10 +1,17 @@
+use std::collections::{BTreeMap, BTreeSet};
 use candid::Principal;
 use crate::db::DB;
-use crate::db::traits::crud::{Crud, Pagination, CrudSubscribable};
-use crate::models::prescription::{Prescription, PrescriptionState};
+use crate::db::tables::prescription_auths::PrescriptionAuthsTable;
+use crate::db::traits::crud::{Crud, Page, Pagination, CrudSubscribable, CrudSoftDeletable};
+use crate::models::error::Error;
+use crate::models::retention::RetentionConfig;
+use crate::models::prescription::{Prescription, PrescriptionFilter, PrescriptionId, PrescriptionState, SharedPrescription};
+use crate::models::prescription_auth::{PrescriptionAuth, PrescriptionAuthSubject, PrescriptionOperation};
 use crate::models::user::{User, UserId, UserKind};
 
 use super::doctors::DoctorsService;
+use super::prescription_templates::PrescriptionTemplatesService;
+use super::prescriptions::PrescriptionsService;
 
 pub struct UsersService {}
 
@@ -13,16 +20,25 @@ impl UsersService {
         user: &User,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         if *caller == Principal::anonymous() {
-            return Err("Anonymous not allowed".to_string());
+            return Err(Error::Unauthenticated);
+        }
+
+        // the row of a deleted account is kept until it's purged, so it must be restored instead
+        if db.users.borrow().find_deleted(caller).is_some() {
+            return Err(Error::invalid_state("User was deleted. It must be restored with user_restore"));
         }
 
         match user.kind.clone() {
             UserKind::Doctor(doctor) => {
-                if let Err(err) = DoctorsService::validate_cert(&doctor.cert.as_bytes().to_vec(), &doctor) {
+                DoctorsService::validate(&doctor)?;
+                if let Err(err) = DoctorsService::validate_cert(&doctor.cert.as_bytes().to_vec(), &doctor, db) {
                     return Err(err);
                 }
+                if let Some(template_id) = &doctor.prescription_template {
+                    PrescriptionTemplatesService::check_ownership(template_id, db, caller)?;
+                }
             },
             _ => ()
         }
@@ -35,16 +51,22 @@ impl UsersService {
         req: &User,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
+        if let UserKind::Doctor(doctor) = &req.kind {
+            if let Some(template_id) = &doctor.prescription_template {
+                PrescriptionTemplatesService::check_ownership(template_id, db, caller)?;
+            }
+        }
+
         let mut users = db.users.borrow_mut();
 
         let user = match users.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("user", id)),
             Some(e) => e
         };
 
         if *id != user.created_by || *caller != user.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
 
         users.update_and_notify(id.to_owned(), req.clone())
@@ -54,75 +76,170 @@ impl UsersService {
         id: &UserId,
         db: &mut DB,
         caller: &Principal
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut users = db.users.borrow_mut();
 
         let user = match users.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("user", id)),
+            Some(e) => e
+        };
+
+        if *id != user.created_by || *caller != user.created_by {
+            return Err(Error::Forbidden);
+        }
+        
+        users.soft_delete_and_notify(id, Some(caller.clone()))
+    }
+
+    pub fn restore(
+        id: &UserId,
+        retention: &RetentionConfig,
+        db: &mut DB,
+        caller: &Principal
+    ) -> Result<(), Error> {
+        let mut users = db.users.borrow_mut();
+
+        let user = match users.find_deleted(id) {
+            None => return Err(Error::not_found("user", id)),
             Some(e) => e
         };
 
         if *id != user.created_by || *caller != user.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
+
+        retention.check_restore(user.deleted_at)?;
         
-        users.delete_and_notify(id)
+        users.restore_and_notify(id)
     }
 
     pub fn find_by_id(
         id: &UserId,
         db: &DB,
         caller: &Principal
-    ) -> Result<User, String> {
+    ) -> Result<User, Error> {
         if *caller == Principal::anonymous() {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
         }
         
         let users = db.users.borrow();
 
         let user = match users.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("user", id)),
             Some(e) => e
         };
 
-        Ok((*user).clone())
+        Ok(user)
     }
 
     pub fn find_prescriptions(
         id: &UserId,
+        filter: &PrescriptionFilter,
         pag: Pagination,
         db: &DB,
         caller: &Principal
-    ) -> Result<Vec<Prescription>, String> {
+    ) -> Result<Page<Prescription>, Error> {
         let users = db.users.borrow();
 
         let user = match users.find_by_id(id) {
-            None => return Err("Not found".to_string()),
+            None => return Err(Error::not_found("user", id)),
             Some(e) => e
         };
 
         if *id != user.created_by || *caller != user.created_by {
-            return Err("Forbidden".to_string());
+            return Err(Error::Forbidden);
+        }
+
+        let rels = db.user_prescriptions_rel.borrow();
+        
+        let mut ids = rels.find_by_id(id)
+            .unwrap_or_default();
+
+        // the counterparty must be related to the prescriptions too
+        if let Some(counterparty) = &filter.counterparty {
+            let others = rels.find_by_id(counterparty)
+                .unwrap_or_default();
+            ids = ids.intersection(&others).cloned().collect();
+        }
+
+        let now = ic_cdk::api::time();
+        let prescriptions = db.prescriptions.borrow();
+        // the relation also lists the prescriptions shared through grants that don't allow viewing
+        // them, or that already expired
+        pag.paginate(&ids, |pid| prescriptions.find_by_id(pid)
+            .filter(|p| pag.accepts(p.created_at) && 
+                filter.accepts(p, id) && 
+                    Self::accepts_shares(p, filter, db, now) &&
+                        PrescriptionsService::check_access(p, &PrescriptionOperation::View, db, id).is_ok())
+        )
+    }
+
+    fn accepts_shares(
+        prescription: &Prescription,
+        filter: &PrescriptionFilter,
+        db: &DB,
+        now: u64
+    ) -> bool {
+        if !filter.filters_shares() {
+            return true;
+        }
+
+        let ids = db.prescription_auths_rel.borrow().find_by_id(&prescription.id)
+            .unwrap_or_default();
+
+        let auths = db.prescription_auths.borrow();
+        ids.iter()
+            .filter_map(|id| auths.find_by_id(id))
+            .any(|auth| filter.accepts_share(&auth, now))
+    }
+
+    /*
+     * prescriptions the caller can view through unexpired grants, made to the caller or to the groups
+     * the caller is a member of
+     */
+    pub fn find_shared_prescriptions(
+        pag: Pagination,
+        db: &DB,
+        caller: &Principal
+    ) -> Result<Page<SharedPrescription>, Error> {
+        if db.users.borrow().find_by_id(caller).is_none() {
+            return Err(Error::not_found("user", caller));
         }
 
-        let ids = match db.user_prescriptions_rel.borrow().find_by_id(id) {
-            None => vec![],
-            Some(set) =>  {
-                let mut arr: Vec<String> = set.iter().cloned().collect();
-                arr.sort_by(|a, b| b.cmp(a));
-                arr.iter()
-                    .skip(pag.offset as usize)
-                    .take(pag.limit as usize)
-                    .cloned()
-                    .collect()
+        let mut subjects = vec![PrescriptionAuthSubject::User(caller.clone())];
+        let group_ids = db.principal_groups_rel.borrow().find_by_id(caller)
+            .unwrap_or_default();
+        let groups = db.groups.borrow();
+        for id in group_ids {
+            // the group's creator isn't a member
+            if groups.find_by_id(&id).map_or(false, |g| g.members.contains(caller)) {
+                subjects.push(PrescriptionAuthSubject::Group(id));
             }
-        };
+        }
 
+        let now = ic_cdk::api::time();
+        let auths = db.prescription_auths.borrow();
+        let mut grants: BTreeMap<PrescriptionId, Vec<PrescriptionAuth>> = BTreeMap::new();
+        for subject in subjects {
+            let key = vec![PrescriptionAuthsTable::subject_value(&subject)];
+            for auth in auths.find_by_index("to", &key) {
+                if auth.to == subject && 
+                    auth.kind.allows(&PrescriptionOperation::View) && 
+                        !auth.is_expired(now) {
+                    grants.entry(auth.prescription_id.clone()).or_default()
+                        .push(auth);
+                }
+            }
+        }
+
+        let ids = grants.keys().cloned().collect::<BTreeSet<_>>();
         let prescriptions = db.prescriptions.borrow();
-        let user_prescriptions = ids.iter().map(|id| prescriptions.get(id).clone())
-            .filter(|p| p.state == PrescriptionState::Signed)
-            .collect();
-        
-        Ok(user_prescriptions)
+        pag.paginate(&ids, |id| prescriptions.find_by_id(id)
+            .filter(|p| p.state == PrescriptionState::Signed && pag.accepts(p.created_at))
+            .map(|p| SharedPrescription {
+                prescription: p,
+                grants: grants.get(id).cloned().unwrap_or_default(),
+            })
+        )
     }
 }
\ No newline at end of file
@@ -0,0 +1,301 @@
+use ring::digest;
+use x509_parser::der_parser::asn1_rs::{Any, Class, FromDer, Tag};
+use x509_parser::time::ASN1Time;
+
+const OID_SIGNED_DATA: &str = "1.2.840.113549.1.7.2";
+const OID_MESSAGE_DIGEST: &str = "1.2.840.113549.1.9.4";
+const OID_SIGNING_TIME: &str = "1.2.840.113549.1.9.5";
+pub const OID_SHA256: &str = "2.16.840.1.101.3.4.2.1";
+pub const OID_SHA384: &str = "2.16.840.1.101.3.4.2.2";
+pub const OID_SHA512: &str = "2.16.840.1.101.3.4.2.3";
+
+pub struct CmsSignerInfo {
+    pub raw_issuer: Vec<u8>,
+    pub serial: String,
+    pub digest_algorithm: String,
+    pub signed_attrs: Option<Vec<u8>>,
+    pub message_digest: Option<Vec<u8>>,
+    pub signing_time: Option<u64>,
+    pub sig_algorithm: String,
+    pub signature: Vec<u8>,
+}
+
+pub struct CmsSignedData {
+    pub content: Option<Vec<u8>>,
+    pub certs: Vec<Vec<u8>>,
+    pub signer: CmsSignerInfo,
+}
+
+impl CmsSignedData {
+    pub fn from_der(
+        der: &[u8]
+    ) -> Result<Self, String> {
+        // ContentInfo ::= SEQUENCE { contentType, [0] EXPLICIT content }
+        let (info, _) = parse_one(der, Tag::Sequence)?;
+        let items = parse_items(info.data)?;
+        if items.len() != 2 {
+            return Err("CMS: invalid ContentInfo".to_string());
+        }
+
+        if to_oid(&items[0].0)? != OID_SIGNED_DATA {
+            return Err("CMS: content is not SignedData".to_string());
+        }
+
+        let (signed_data, _) = parse_one(items[1].0.data, Tag::Sequence)?;
+        let items = parse_items(signed_data.data)?;
+        if items.len() < 4 {
+            return Err("CMS: invalid SignedData".to_string());
+        }
+
+        // encapContentInfo ::= SEQUENCE { eContentType, [0] EXPLICIT eContent OPTIONAL }
+        let encap = parse_items(items[2].0.data)?;
+        let content = match encap.get(1) {
+            None => None,
+            Some((e, _)) => {
+                let (octets, _) = parse_one(e.data, Tag::OctetString)?;
+                Some(octets.data.to_vec())
+            }
+        };
+
+        // optional [0] IMPLICIT certificates and [1] IMPLICIT crls, then signerInfos
+        let mut certs = vec![];
+        for (item, _) in &items[3..items.len()-1] {
+            if item.header.class() == Class::ContextSpecific && item.header.tag() == Tag(0) {
+                for (_, raw) in parse_items(item.data)? {
+                    certs.push(raw.to_vec());
+                }
+            }
+        }
+
+        let (signer_infos, _) = &items[items.len()-1];
+        if signer_infos.header.tag() != Tag::Set {
+            return Err("CMS: invalid SignerInfos".to_string());
+        }
+
+        let signers = parse_items(signer_infos.data)?;
+        if signers.len() != 1 {
+            return Err("CMS: exactly one signer is supported".to_string());
+        }
+
+        Ok(Self {
+            content,
+            certs,
+            signer: CmsSignerInfo::from_any(&signers[0].0)?,
+        })
+    }
+}
+
+impl CmsSignerInfo {
+    fn from_any(
+        info: &Any
+    ) -> Result<Self, String> {
+        // SignerInfo ::= SEQUENCE { version, sid, digestAlgorithm, [0] IMPLICIT signedAttrs OPTIONAL,
+        //  signatureAlgorithm, signature, [1] IMPLICIT unsignedAttrs OPTIONAL }
+        let items = parse_items(info.data)?;
+        if items.len() < 5 {
+            return Err("CMS: invalid SignerInfo".to_string());
+        }
+
+        // only the IssuerAndSerialNumber form of the signer identifier is supported
+        if items[1].0.header.tag() != Tag::Sequence {
+            return Err("CMS: unsupported signer identifier".to_string());
+        }
+        let sid = parse_items(items[1].0.data)?;
+        if sid.len() != 2 || sid[1].0.header.tag() != Tag::Integer {
+            return Err("CMS: invalid signer identifier".to_string());
+        }
+
+        let digest_algorithm = to_algorithm(&items[2].0)?;
+
+        let mut next = 3;
+        let mut signed_attrs = None;
+        let mut message_digest = None;
+        let mut signing_time = None;
+
+        let (attrs, raw) = &items[next];
+        if attrs.header.class() == Class::ContextSpecific && attrs.header.tag() == Tag(0) {
+            for (attr, _) in parse_items(attrs.data)? {
+                let attr = parse_items(attr.data)?;
+                if attr.len() != 2 {
+                    return Err("CMS: invalid signed attribute".to_string());
+                }
+
+                let values = parse_items(attr[1].0.data)?;
+                let value = match values.first() {
+                    None => return Err("CMS: empty signed attribute".to_string()),
+                    Some(value) => value,
+                };
+
+                match to_oid(&attr[0].0)?.as_str() {
+                    OID_MESSAGE_DIGEST => {
+                        message_digest = Some(value.0.data.to_vec());
+                    },
+                    OID_SIGNING_TIME => {
+                        let time = match ASN1Time::from_der(value.1) {
+                            Ok((_, time)) => time,
+                            Err(_) => return Err("CMS: invalid signing time".to_string()),
+                        };
+                        signing_time = Some((time.timestamp().max(0) as u64) * 1_000_000_000);
+                    },
+                    _ => ()
+                }
+            }
+
+            // the signature covers the DER encoding of the attributes as a SET OF, not as [0] IMPLICIT
+            let mut encoded = raw.to_vec();
+            encoded[0] = 0x31;
+            signed_attrs = Some(encoded);
+            next += 1;
+        }
+
+        if items.len() < next + 2 {
+            return Err("CMS: invalid SignerInfo".to_string());
+        }
+
+        Ok(Self {
+            raw_issuer: sid[0].1.to_vec(),
+            serial: to_serial(sid[1].0.data),
+            digest_algorithm,
+            signed_attrs,
+            message_digest,
+            signing_time,
+            sig_algorithm: to_algorithm(&items[next].0)?,
+            signature: items[next+1].0.data.to_vec(),
+        })
+    }
+
+    pub fn get_digest(
+        &self
+    ) -> Result<&'static digest::Algorithm, String> {
+        match self.digest_algorithm.as_str() {
+            OID_SHA256 => Ok(&digest::SHA256),
+            OID_SHA384 => Ok(&digest::SHA384),
+            OID_SHA512 => Ok(&digest::SHA512),
+            _ => Err(format!("Unsupported digest algorithm: {}", self.digest_algorithm)),
+        }
+    }
+}
+
+fn parse_one<'a>(
+    input: &'a [u8],
+    tag: Tag
+) -> Result<(Any<'a>, &'a [u8]), String> {
+    match Any::from_der(input) {
+        Ok((rem, any)) => if any.header.tag() != tag {
+            Err("CMS: unexpected ASN.1 element".to_string())
+        }
+        else {
+            Ok((any, rem))
+        },
+        Err(_) => Err("CMS: decoding DER failed".to_string()),
+    }
+}
+
+// returns each element found in the input together with its raw DER encoding
+fn parse_items<'a>(
+    input: &'a [u8]
+) -> Result<Vec<(Any<'a>, &'a [u8])>, String> {
+    let mut items = vec![];
+    let mut rem = input;
+
+    while rem.len() > 0 {
+        match Any::from_der(rem) {
+            Ok((next, any)) => {
+                items.push((any, &rem[..rem.len() - next.len()]));
+                rem = next;
+            },
+            Err(_) => return Err("CMS: decoding DER failed".to_string()),
+        }
+    }
+
+    Ok(items)
+}
+
+fn to_oid(
+    any: &Any
+) -> Result<String, String> {
+    any.as_oid()
+        .map(|oid| oid.to_id_string())
+        .map_err(|_| "CMS: invalid object identifier".to_string())
+}
+
+fn to_algorithm(
+    any: &Any
+) -> Result<String, String> {
+    let items = parse_items(any.data)?;
+    match items.first() {
+        None => Err("CMS: invalid algorithm identifier".to_string()),
+        Some((oid, _)) => to_oid(oid),
+    }
+}
+
+// same representation used by X509Cert (hex without leading zeros)
+fn to_serial(
+    data: &[u8]
+) -> String {
+    let serial = hex::encode(data);
+    let serial = serial.trim_start_matches('0');
+    if serial.len() == 0 {
+        "0".to_string()
+    }
+    else {
+        serial.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{cert, CMS_ATTACHED_PEM, CMS_CONTENT, CMS_DETACHED_PEM, ROOT_PEM};
+    use crate::utils::x509::{OID_ECDSA_WITH_SHA256, pem_to_der};
+    use super::*;
+
+    fn parse(
+        pem: &[u8]
+    ) -> Result<CmsSignedData, String> {
+        CmsSignedData::from_der(&pem_to_der(pem).unwrap()[0])
+    }
+
+    #[test]
+    fn parses_detached_signatures() {
+        let cms = parse(CMS_DETACHED_PEM).unwrap();
+        let cert = cert(ROOT_PEM);
+        assert_eq!(cms.content, None);
+        assert_eq!(cms.certs.len(), 1);
+        assert_eq!(cms.signer.raw_issuer, cert.raw_issuer);
+        assert_eq!(cms.signer.serial, cert.serial);
+        assert_eq!(cms.signer.digest_algorithm, OID_SHA256);
+        assert_eq!(cms.signer.sig_algorithm, OID_ECDSA_WITH_SHA256);
+        assert!(cms.signer.signing_time.is_some());
+        assert_eq!(
+            cms.signer.message_digest, 
+            Some(digest::digest(cms.signer.get_digest().unwrap(), CMS_CONTENT).as_ref().to_vec())
+        );
+    }
+
+    #[test]
+    fn signature_covers_the_signed_attributes() {
+        let cms = parse(CMS_DETACHED_PEM).unwrap();
+        let attrs = cms.signer.signed_attrs.clone().unwrap();
+        assert_eq!(attrs[0], 0x31);
+        assert_eq!(cert(ROOT_PEM).verify_signed_data(&cms.signer.sig_algorithm, &attrs, &cms.signer.signature), Ok(()));
+    }
+
+    #[test]
+    fn parses_the_embedded_content() {
+        let cms = parse(CMS_ATTACHED_PEM).unwrap();
+        assert_eq!(cms.content, Some(CMS_CONTENT.to_vec()));
+    }
+
+    #[test]
+    fn rejects_other_structures() {
+        assert!(CmsSignedData::from_der(b"not DER").is_err());
+        assert!(CmsSignedData::from_der(&pem_to_der(ROOT_PEM).unwrap()[0]).is_err());
+    }
+
+    #[test]
+    fn serials_drop_the_leading_zeros() {
+        assert_eq!(to_serial(&[0x00, 0x0a, 0xbc]), "abc");
+        assert_eq!(to_serial(&[0x00]), "0");
+    }
+}
\ No newline at end of file
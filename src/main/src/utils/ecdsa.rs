@@ -0,0 +1,55 @@
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use ring::digest::{self, Algorithm};
+
+pub const OID_CURVE_P256: &str = "1.2.840.10045.3.1.7";
+pub const OID_CURVE_P384: &str = "1.3.132.0.34";
+
+// NOTE: ring's ECDSA needs assembly that can't be built for wasm, so the pure-Rust RustCrypto curves are used instead
+pub fn verify(
+    curve: &str,
+    key: &[u8],
+    alg: &'static Algorithm,
+    msg: &[u8],
+    sig: &[u8]
+) -> Result<(), String> {
+    let prehash = digest::digest(alg, msg);
+
+    match curve {
+        OID_CURVE_P256 => verify_p256(key, prehash.as_ref(), sig),
+        OID_CURVE_P384 => verify_p384(key, prehash.as_ref(), sig),
+        _ => Err(format!("Unsupported elliptic curve: {}", curve)),
+    }
+}
+
+fn verify_p256(
+    key: &[u8],
+    prehash: &[u8],
+    sig: &[u8]
+) -> Result<(), String> {
+    let pkey = p256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+        .map_err(|_| "Invalid P-256 public key".to_string())?;
+
+    // signatures can be DER encoded or in the fixed-size (r || s) format
+    let sig = p256::ecdsa::Signature::from_der(sig)
+        .or_else(|_| p256::ecdsa::Signature::from_slice(sig))
+        .map_err(|_| "Invalid P-256 signature encoding".to_string())?;
+
+    pkey.verify_prehash(prehash, &sig)
+        .map_err(|_| "Signature verification failed".to_string())
+}
+
+fn verify_p384(
+    key: &[u8],
+    prehash: &[u8],
+    sig: &[u8]
+) -> Result<(), String> {
+    let pkey = p384::ecdsa::VerifyingKey::from_sec1_bytes(key)
+        .map_err(|_| "Invalid P-384 public key".to_string())?;
+
+    let sig = p384::ecdsa::Signature::from_der(sig)
+        .or_else(|_| p384::ecdsa::Signature::from_slice(sig))
+        .map_err(|_| "Invalid P-384 signature encoding".to_string())?;
+
+    pkey.verify_prehash(prehash, &sig)
+        .map_err(|_| "Signature verification failed".to_string())
+}
@@ -2,3 +2,5 @@ pub mod serdeser;
 pub mod vetkd;
 pub mod random;
 pub mod x509;
+pub mod ecdsa;
+pub mod cms;
@@ -1,44 +1,63 @@
+use std::io::{Read, Write};
 use candid::{ser::IDLBuilder, CandidType, utils::ArgumentEncoder};
-use ic_cdk::api::stable::{StableWriter, StableReader};
 use serde::Deserialize;
 
+// versions used to be stored as f32 (0.1, 0.2...). As u32, those are all above this
+const MAX_INTEGER_VERSION: u32 = 0x0080_0000;
+
 pub fn serialize<T: CandidType> (
     value: &T,
-    version: f32,
-    writer: &mut StableWriter
+    version: u32,
+    writer: &mut impl Write
 ) -> Result<(), String> {
     let mut ser = IDLBuilder::new();
     (value, ).encode(&mut ser).map_err(|e| format!("{:?}", e))?;
     let arr = ser.serialize_to_vec().unwrap();
     // store version
-    writer.write(&f32::to_le_bytes(version)).map_err(|e| format!("{:?}", e))?;
+    writer.write_all(&u32::to_le_bytes(version)).map_err(|e| format!("{:?}", e))?;
     // store size
-    writer.write(&u64::to_le_bytes(arr.len() as u64)).map_err(|e| format!("{:?}", e))?;
+    writer.write_all(&u64::to_le_bytes(arr.len() as u64)).map_err(|e| format!("{:?}", e))?;
     // store value
-    writer.write(&arr).map_err(|e| format!("{:?}", e))?;
+    writer.write_all(&arr).map_err(|e| format!("{:?}", e))?;
     Ok(())
 }
 
+// values stored with another version are converted by migrate() before being decoded
 pub fn deserialize<T: CandidType + for<'a> Deserialize<'a>>(
-    version: f32,
-    reader: &mut StableReader
+    version: u32,
+    migrate: impl Fn(u32, Vec<u8>) -> Result<Vec<u8>, String>,
+    reader: &mut impl Read
 ) -> Result<T, String> {
+    let (stored_version, buf) = read_raw(reader)?;
+    let buf = if stored_version != version {
+        migrate(stored_version, buf)?
+    }
+    else {
+        buf
+    };
+    // decode value
+    let res = candid::decode_args::<'_, (T, )>(&buf)
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(res.0)
+}
+
+// reads a value stored by serialize() without decoding it
+pub fn read_raw(
+    reader: &mut impl Read
+) -> Result<(u32, Vec<u8>), String> {
     // load version
     let mut version_buf = [0u8; 4];
-    reader.read(&mut version_buf).map_err(|e| format!("{:?}", e))?;
-    let stored_version = f32::from_le_bytes(version_buf);
-    if stored_version != version {
-        return Err("Invalid version".to_string());
-    }
+    reader.read_exact(&mut version_buf).map_err(|e| format!("{:?}", e))?;
+    let version = match u32::from_le_bytes(version_buf) {
+        version if version < MAX_INTEGER_VERSION => version,
+        _ => (f32::from_le_bytes(version_buf) * 10.0).round() as u32,
+    };
     // load size
     let mut size_buf = [0u8; 8];
-    reader.read(&mut size_buf).map_err(|e| format!("{:?}", e))?;
+    reader.read_exact(&mut size_buf).map_err(|e| format!("{:?}", e))?;
     let size = u64::from_le_bytes(size_buf);
     // load value
-    let mut table_buf = vec![0u8; size as usize];
-    reader.read(&mut table_buf).map_err(|e| format!("{:?}", e))?;
-    // decode value
-    let res = candid::decode_args::<'_, (T, )>(&table_buf)
-        .map_err(|e| format!("{:?}", e))?;
-    Ok(res.0)
-}
\ No newline at end of file
+    let mut buf = vec![0u8; size as usize];
+    reader.read_exact(&mut buf).map_err(|e| format!("{:?}", e))?;
+    Ok((version, buf))
+}
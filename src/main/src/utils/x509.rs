@@ -1,26 +1,104 @@
 
-use std::{vec, collections::HashMap};
-use x509_parser::{prelude::{Pem, X509Certificate, Validity, KeyUsage, GeneralName}, x509::X509Name, time::ASN1Time, oid_registry::Oid};
+use std::{vec, fmt, collections::{HashMap, BTreeSet}};
+use ring::{digest, signature::{self, RsaPublicKeyComponents, RsaParameters}};
+use super::ecdsa;
+use x509_parser::{prelude::{Pem, X509Certificate, Validity, KeyUsage, GeneralName}, x509::X509Name, time::ASN1Time, 
+    oid_registry::{Oid, OID_X509_SERIALNUMBER, OID_X509_COUNTRY_NAME}};
 
-#[derive(Clone)]
+pub const OID_RSA_ENCRYPTION: &str = "1.2.840.113549.1.1.1";
+pub const OID_RSASSA_PSS: &str = "1.2.840.113549.1.1.10";
+pub const OID_EC_PUBLIC_KEY: &str = "1.2.840.10045.2.1";
+
+const OID_SHA1_WITH_RSA: &str = "1.2.840.113549.1.1.5";
+pub const OID_SHA256_WITH_RSA: &str = "1.2.840.113549.1.1.11";
+pub const OID_SHA384_WITH_RSA: &str = "1.2.840.113549.1.1.12";
+pub const OID_SHA512_WITH_RSA: &str = "1.2.840.113549.1.1.13";
+pub const OID_ECDSA_WITH_SHA256: &str = "1.2.840.10045.4.3.2";
+pub const OID_ECDSA_WITH_SHA384: &str = "1.2.840.10045.4.3.3";
+pub const OID_ECDSA_WITH_SHA512: &str = "1.2.840.10045.4.3.4";
+
+pub const MAX_CHAIN_LEN: usize = 8;
+pub const MAX_CHAIN_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum X509Error {
+    PemDecoding,
+    DerDecoding,
+    CrlDecoding,
+    InvalidPublicKey,
+    MissingCommonName,
+    EmptyChain,
+    ChainTooLong(usize),
+    ChainTooLarge(usize),
+    InvalidTime,
+    Expired(String),
+    CantSign(String),
+    NotCa(String),
+    CantSignCerts(String),
+    CantSignCrls(String),
+    PathLenExceeded(String),
+    NoSigningCert,
+    UntrustedChain,
+    ChainLoop,
+    CrlIssuerMismatch,
+    UnsupportedAlgorithm(String),
+    UnsupportedPublicKey(String),
+    SignatureFailed(String),
+    InvalidSignature(String, Box<X509Error>),
+    InvalidCrlSignature(Box<X509Error>),
+}
+
+impl fmt::Display for X509Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            X509Error::PemDecoding => write!(f, "Reading next PEM block failed"),
+            X509Error::DerDecoding => write!(f, "X.509: decoding DER failed"),
+            X509Error::CrlDecoding => write!(f, "CRL: decoding DER failed"),
+            X509Error::InvalidPublicKey => write!(f, "X.509: decoding public key failed"),
+            X509Error::MissingCommonName => write!(f, "X.509: subject has no common name"),
+            X509Error::EmptyChain => write!(f, "No certificate was found"),
+            X509Error::ChainTooLong(len) => write!(f, "Certificate chain has {} certificates, max is {}", len, MAX_CHAIN_LEN),
+            X509Error::ChainTooLarge(size) => write!(f, "Certificate chain has {} bytes, max is {}", size, MAX_CHAIN_SIZE),
+            X509Error::InvalidTime => write!(f, "Invalid validation time"),
+            X509Error::Expired(subject) => write!(f, "Certificate {} expired or not yet valid", subject),
+            X509Error::CantSign(subject) => write!(f, "Certificate {} can't be used to sign", subject),
+            X509Error::NotCa(subject) => write!(f, "Issuer certificate {} is not a CA", subject),
+            X509Error::CantSignCerts(subject) => write!(f, "Issuer certificate {} can't be used to sign certificates", subject),
+            X509Error::CantSignCrls(subject) => write!(f, "Certificate {} can't be used to sign CRLs", subject),
+            X509Error::PathLenExceeded(subject) => write!(f, "Path length constraint of {} exceeded", subject),
+            X509Error::NoSigningCert => write!(f, "No certificate for digital signature was found"),
+            X509Error::UntrustedChain => write!(f, "Certificate chain doesn't end in a trusted CA"),
+            X509Error::ChainLoop => write!(f, "Certificate chain contains a loop"),
+            X509Error::CrlIssuerMismatch => write!(f, "CRL wasn't issued by the informed CA"),
+            X509Error::UnsupportedAlgorithm(oid) => write!(f, "Unsupported signature algorithm: {}", oid),
+            X509Error::UnsupportedPublicKey(oid) => write!(f, "Unsupported public key type: {}", oid),
+            X509Error::SignatureFailed(reason) => write!(f, "{}", reason),
+            X509Error::InvalidSignature(subject, err) => write!(f, "Signature of certificate {} is invalid: {}", subject, err),
+            X509Error::InvalidCrlSignature(err) => write!(f, "CRL signature is invalid: {}", err),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct RSAPublicKey {
     pub n: Vec<u8>,
     pub e: Vec<u8>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ECPublicKey {
+    pub curve: String,
     pub data: Vec<u8>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum PubKeyValue {
     RSA(RSAPublicKey),
     EC(ECPublicKey),
     Unknown,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct PubKey {
     pub algorithm: String,
     pub value: PubKeyValue,
@@ -31,17 +109,26 @@ pub struct X509Cert{
     pub serial: String,
     pub validity: Validity,
     pub subject: String,
+    pub subject_serial: Option<String>,
+    pub subject_country: Option<String>,
+    pub issuer: String,
+    pub raw_subject: Vec<u8>,
+    pub raw_issuer: Vec<u8>,
     pub is_ca: bool,
+    pub path_len: Option<u32>,
     pub key_usage: Option<KeyUsage>,
     pub pub_key: PubKey,
     pub alt_names: HashMap<String, Vec<u8>>,
+    pub tbs: Vec<u8>,
+    pub sig_algorithm: String,
+    pub signature: Vec<u8>,
 }
 
 impl X509Cert {
     pub fn new (
         x509: X509Certificate,
         alt_name_oids: &Vec<Oid<'static>>
-    ) -> Self {
+    ) -> Result<Self, X509Error> {
 
         let mut alt_names = HashMap::new();
 
@@ -66,13 +153,23 @@ impl X509Cert {
         }
 
         let pub_key = x509.public_key();
-        let pub_key_parsed = pub_key.parsed().unwrap();
+        let pub_key_parsed = pub_key.parsed()
+            .map_err(|_| X509Error::InvalidPublicKey)?;
 
-        Self { 
+        Ok(Self { 
             serial: x509.serial.clone().to_str_radix(16),
             validity: x509.validity.clone(),
-            subject: Self::get_cn(&x509.subject),
+            subject: Self::get_cn(&x509.subject)?,
+            subject_serial: Self::get_attr(&x509.subject, &OID_X509_SERIALNUMBER),
+            subject_country: Self::get_attr(&x509.subject, &OID_X509_COUNTRY_NAME),
+            issuer: x509.issuer.to_string(),
+            raw_subject: x509.subject.as_raw().to_vec(),
+            raw_issuer: x509.issuer.as_raw().to_vec(),
             is_ca: x509.is_ca(),
+            path_len: match x509.basic_constraints() {
+                Ok(Some(bc)) => bc.value.path_len_constraint,
+                _ => None
+            },
             key_usage: match x509.key_usage() {
                 Ok(res) => match res {
                     None => None,
@@ -91,6 +188,12 @@ impl X509Cert {
                     },
                     x509_parser::public_key::PublicKey::EC(key) => {
                         PubKeyValue::EC(ECPublicKey { 
+                            curve: match &pub_key.algorithm.parameters {
+                                Some(params) => params.as_oid()
+                                    .map(|oid| oid.to_id_string())
+                                    .unwrap_or_default(),
+                                None => String::new()
+                            },
                             data: key.data().to_vec() 
                         })
                     }
@@ -99,26 +202,45 @@ impl X509Cert {
                 },
             },
             alt_names,
+            tbs: x509.tbs_certificate.as_ref().to_vec(),
+            sig_algorithm: x509.signature_algorithm.algorithm.to_id_string(),
+            signature: x509.signature_value.data.to_vec(),
+        })
+    }
+
+    pub fn from_der(
+        der: &[u8],
+        alt_name_oids: &Vec<Oid<'static>>
+    ) -> Result<Self, X509Error> {
+        match x509_parser::parse_x509_certificate(der) {
+            Ok((_, x509)) => Self::new(x509, alt_name_oids),
+            Err(_) => Err(X509Error::DerDecoding),
         }
     }
 
     fn remove_leading_zero(
         value: &[u8]
     ) -> Vec<u8> {
-        if value[0] == 0 {
-            value[1..].to_vec()
-        }
-        else {
-            value.to_vec()
+        match value.first() {
+            Some(0) => value[1..].to_vec(),
+            _ => value.to_vec(),
         }
     }
     
     pub fn check_validity(
         &self
-    ) -> Result<(), String> {
-        let now = ASN1Time::from_timestamp((ic_cdk::api::time() / 1000_000_000) as i64).unwrap();
-        if !self.validity.is_valid_at(now) {
-            return Err("Certificate expired".to_string());
+    ) -> Result<(), X509Error> {
+        self.check_validity_at(ic_cdk::api::time())
+    }
+
+    pub fn check_validity_at(
+        &self,
+        time: u64
+    ) -> Result<(), X509Error> {
+        let at = ASN1Time::from_timestamp((time / 1000_000_000) as i64)
+            .map_err(|_| X509Error::InvalidTime)?;
+        if !self.validity.is_valid_at(at) {
+            return Err(X509Error::Expired(self.subject.clone()));
         }
 
         Ok(())
@@ -126,36 +248,202 @@ impl X509Cert {
 
     pub fn check_key_usage(
         &self
-    ) -> Result<(), String> {
+    ) -> Result<(), X509Error> {
         match self.key_usage {
-            None => return Err("Certificate can't be used to sign".to_string()),
+            None => return Err(X509Error::CantSign(self.subject.clone())),
             Some(ku) => if !ku.digital_signature() {
-                return Err("Certificate can't be used to sign".to_string());
+                return Err(X509Error::CantSign(self.subject.clone()));
             }
         }
 
         Ok(())
     }
 
+    pub fn check_ca(
+        &self,
+        intermediates: u32
+    ) -> Result<(), X509Error> {
+        if !self.is_ca {
+            return Err(X509Error::NotCa(self.subject.clone()));
+        }
+
+        if let Some(ku) = self.key_usage {
+            if !ku.key_cert_sign() {
+                return Err(X509Error::CantSignCerts(self.subject.clone()));
+            }
+        }
+
+        if let Some(path_len) = self.path_len {
+            if intermediates > path_len {
+                return Err(X509Error::PathLenExceeded(self.subject.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_issued_by(
+        &self,
+        issuer: &X509Cert
+    ) -> bool {
+        self.raw_issuer == issuer.raw_subject
+    }
+
+    // the certs are cloned around, so they are compared by subject, issuer and key instead of by address
+    pub fn is_same_as(
+        &self,
+        other: &X509Cert
+    ) -> bool {
+        self.raw_subject == other.raw_subject &&
+            self.raw_issuer == other.raw_issuer &&
+            self.pub_key == other.pub_key
+    }
+
+    pub fn verify_signature(
+        &self,
+        issuer: &X509Cert
+    ) -> Result<(), X509Error> {
+        issuer.verify_signed_data(&self.sig_algorithm, &self.tbs, &self.signature)
+            .map_err(|err| X509Error::InvalidSignature(self.subject.clone(), Box::new(err)))
+    }
+
+    pub fn verify_signed_data(
+        &self,
+        sig_algorithm: &str,
+        data: &[u8],
+        sig: &[u8]
+    ) -> Result<(), X509Error> {
+        match &self.pub_key.value {
+            PubKeyValue::RSA(key) => {
+                let params: &RsaParameters = match sig_algorithm {
+                    OID_SHA1_WITH_RSA => &signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+                    OID_SHA256_WITH_RSA => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    OID_SHA384_WITH_RSA => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    OID_SHA512_WITH_RSA => &signature::RSA_PKCS1_2048_8192_SHA512,
+                    _ => return Err(X509Error::UnsupportedAlgorithm(sig_algorithm.to_string())),
+                };
+
+                let pkey = RsaPublicKeyComponents { n: &key.n, e: &key.e };
+                pkey.verify(params, data, sig)
+                    .map_err(|_| X509Error::SignatureFailed("Signature verification failed".to_string()))
+            },
+            PubKeyValue::EC(key) => {
+                let alg: &'static digest::Algorithm = match sig_algorithm {
+                    OID_ECDSA_WITH_SHA256 => &digest::SHA256,
+                    OID_ECDSA_WITH_SHA384 => &digest::SHA384,
+                    OID_ECDSA_WITH_SHA512 => &digest::SHA512,
+                    _ => return Err(X509Error::UnsupportedAlgorithm(sig_algorithm.to_string())),
+                };
+
+                ecdsa::verify(&key.curve, &key.data, alg, data, sig)
+                    .map_err(|err| X509Error::SignatureFailed(err))
+            },
+            PubKeyValue::Unknown => {
+                Err(X509Error::UnsupportedPublicKey(self.pub_key.algorithm.clone()))
+            }
+        }
+    }
+
+    fn get_attr<'a>(
+        name: &X509Name<'a>,
+        oid: &Oid<'a>
+    ) -> Option<String> {
+        name.iter_by_oid(oid)
+            .next()
+            .and_then(|attr| attr.as_str().ok())
+            .map(|attr| attr.to_string())
+    }
+
     fn get_cn(
         name: &X509Name
-    ) -> String {
-        let cn = name.iter_common_name()
+    ) -> Result<String, X509Error> {
+        let cn = match name.iter_common_name()
             .next()
-            .and_then(|cn| cn.as_str().ok()).unwrap().to_string();
+            .and_then(|cn| cn.as_str().ok()) {
+            None => return Err(X509Error::MissingCommonName),
+            Some(cn) => cn.to_string(),
+        };
 
-        if let Some(p) = cn.find(":") {
-            if p == 0 {
-                "".to_string()
-            } 
-            else {
-                cn.get(0..p).unwrap().to_string()
+        // ICP-Brasil CNs are in the "name:national ID" format
+        match cn.find(":") {
+            Some(p) => Ok(cn[0..p].to_string()),
+            None => Ok(cn),
+        }
+    }
+}
+
+pub struct X509Crl {
+    pub issuer: String,
+    pub raw_issuer: Vec<u8>,
+    pub this_update: u64,
+    pub next_update: Option<u64>,
+    pub revoked: BTreeSet<String>,
+    pub tbs: Vec<u8>,
+    pub sig_algorithm: String,
+    pub signature: Vec<u8>,
+}
+
+impl X509Crl {
+    pub fn from_der(
+        der: &[u8]
+    ) -> Result<Self, X509Error> {
+        let crl = match x509_parser::parse_x509_crl(der) {
+            Ok((_, crl)) => crl,
+            Err(_) => return Err(X509Error::CrlDecoding),
+        };
+
+        Ok(Self {
+            issuer: crl.issuer().to_string(),
+            raw_issuer: crl.issuer().as_raw().to_vec(),
+            this_update: Self::to_nanos(&crl.last_update()),
+            next_update: crl.next_update().map(|t| Self::to_nanos(&t)),
+            revoked: crl.iter_revoked_certificates()
+                .map(|r| r.user_certificate.to_str_radix(16))
+                .collect(),
+            tbs: crl.tbs_cert_list.as_ref().to_vec(),
+            sig_algorithm: crl.signature_algorithm.algorithm.to_id_string(),
+            signature: crl.signature_value.data.to_vec(),
+        })
+    }
+
+    pub fn verify(
+        &self,
+        issuer: &X509Cert
+    ) -> Result<(), X509Error> {
+        if self.raw_issuer != issuer.raw_subject {
+            return Err(X509Error::CrlIssuerMismatch);
+        }
+
+        if let Some(ku) = issuer.key_usage {
+            if !ku.crl_sign() {
+                return Err(X509Error::CantSignCrls(issuer.subject.clone()));
             }
         }
-        else {
-            cn
+
+        issuer.verify_signed_data(&self.sig_algorithm, &self.tbs, &self.signature)
+            .map_err(|err| X509Error::InvalidCrlSignature(Box::new(err)))
+    }
+
+    fn to_nanos(
+        time: &ASN1Time
+    ) -> u64 {
+        (time.timestamp().max(0) as u64) * 1_000_000_000
+    }
+}
+
+pub fn pem_to_der(
+    buffer: &[u8]
+) -> Result<Vec<Vec<u8>>, X509Error> {
+    let mut ders = vec![];
+
+    for pem in Pem::iter_from_buffer(buffer) {
+        match pem {
+            Ok(pem) => ders.push(pem.contents),
+            Err(_) => return Err(X509Error::PemDecoding),
         }
     }
+
+    Ok(ders)
 }
 
 pub struct X509CertChain {
@@ -166,27 +454,71 @@ impl X509CertChain {
     pub fn new(
         buffer: &Vec<u8>,
         alt_name_oids: &Vec<Oid<'static>>
-    ) -> Self {
-        let mut chain = vec![];
+    ) -> Result<Self, X509Error> {
+        if buffer.len() > MAX_CHAIN_SIZE {
+            return Err(X509Error::ChainTooLarge(buffer.len()));
+        }
 
-        for pem in Pem::iter_from_buffer(&buffer) {
-            let pem = pem.expect("Reading next PEM block failed");
+        Self::from_ders(&pem_to_der(buffer)?, alt_name_oids)
+    }
 
-            let x509 = pem.parse_x509().expect("X.509: decoding DER failed");
+    // DER input: certificates concatenated one after the other
+    pub fn from_der(
+        buffer: &[u8],
+        alt_name_oids: &Vec<Oid<'static>>
+    ) -> Result<Self, X509Error> {
+        if buffer.len() > MAX_CHAIN_SIZE {
+            return Err(X509Error::ChainTooLarge(buffer.len()));
+        }
 
-            let cert = X509Cert::new(x509, alt_name_oids);
+        let mut ders = vec![];
+        let mut rem = buffer;
 
-            chain.push(cert);
+        while rem.len() > 0 {
+            match x509_parser::parse_x509_certificate(rem) {
+                Ok((next, _)) => {
+                    ders.push(rem[..rem.len() - next.len()].to_vec());
+                    rem = next;
+                },
+                Err(_) => return Err(X509Error::DerDecoding),
+            }
         }
-        
-        Self { 
-            chain
+
+        Self::from_ders(&ders, alt_name_oids)
+    }
+
+    pub fn from_ders(
+        ders: &Vec<Vec<u8>>,
+        alt_name_oids: &Vec<Oid<'static>>
+    ) -> Result<Self, X509Error> {
+        if ders.len() == 0 {
+            return Err(X509Error::EmptyChain);
+        }
+
+        if ders.len() > MAX_CHAIN_LEN {
+            return Err(X509Error::ChainTooLong(ders.len()));
         }
+
+        let size = ders.iter().map(|der| der.len()).sum();
+        if size > MAX_CHAIN_SIZE {
+            return Err(X509Error::ChainTooLarge(size));
+        }
+
+        let mut chain = vec![];
+
+        for der in ders {
+            chain.push(X509Cert::from_der(der, alt_name_oids)?);
+        }
+
+        Ok(Self { 
+            chain
+        })
     }
 
     pub fn validate(
-        &self
-    ) -> Result<&X509Cert, String> {
+        &self,
+        roots: &Vec<X509Cert>
+    ) -> Result<&X509Cert, X509Error> {
         let mut top = None;
         
         for cert in &self.chain {
@@ -202,30 +534,210 @@ impl X509CertChain {
             else {
                 top = Some(cert);
             }
+        }
 
-            //TODO: check if the cert signature is valid (needs the issuer cert)
+        let top = match top {
+            None => return Err(X509Error::NoSigningCert),
+            Some(top) => top,
+        };
 
-            //TODO: validate the chain (check if CA is trusted and if it was not revoked)
+        self.verify_path(top, roots)?;
 
-            //TODO: check this cert CRL ("offline")
-        }
+        Ok(top)
+    }
+
+    pub fn verify_path(
+        &self,
+        leaf: &X509Cert,
+        roots: &Vec<X509Cert>
+    ) -> Result<(), X509Error> {
+        let mut cert = leaf;
+        let mut intermediates = 0u32;
 
-        if top.is_none() {
-            return Err("No certificate for digital signature was found".to_string());
+        // each step goes up one level, so a valid path can't be longer than the chain itself
+        for _ in 0..=self.chain.len() {
+            // reached a trusted CA?
+            let anchors: Vec<&X509Cert> = roots.iter()
+                .filter(|root| cert.is_issued_by(root))
+                .collect();
+            if anchors.len() > 0 {
+                let mut last_err = X509Error::UntrustedChain;
+                for anchor in anchors {
+                    match anchor.check_validity()
+                        .and_then(|_| anchor.check_ca(intermediates))
+                        .and_then(|_| cert.verify_signature(anchor)) {
+                        Ok(()) => return Ok(()),
+                        Err(err) => last_err = err,
+                    }
+                }
+                return Err(last_err);
+            }
+
+            // otherwise, the issuer must be part of the chain
+            let issuer = match self.chain.iter()
+                .find(|c| !c.is_same_as(cert) && cert.is_issued_by(c)) {
+                None => return Err(X509Error::UntrustedChain),
+                Some(issuer) => issuer,
+            };
+
+            issuer.check_ca(intermediates)?;
+            cert.verify_signature(issuer)?;
+
+            intermediates += 1;
+            cert = issuer;
         }
 
-        Ok(top.unwrap())
+        Err(X509Error::ChainLoop)
     }
 
     pub fn get_top(
         &self
-    ) -> Result<X509Cert, String> {
+    ) -> Result<X509Cert, X509Error> {
         for cert in &self.chain {
             if cert.check_key_usage().is_ok() {
                 return Ok(cert.clone());
             }
         }
 
-        Err("No certificate for digital signature was found".to_string())
+        Err(X509Error::NoSigningCert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{cert, CRL_PEM, P384_PEM, ROOT_PEM, RSA_PEM};
+    use super::*;
+
+    fn crl(
+    ) -> X509Crl {
+        X509Crl::from_der(&pem_to_der(CRL_PEM).unwrap()[0]).unwrap()
+    }
+
+    #[test]
+    fn parses_crls() {
+        let crl = crl();
+        assert_eq!(crl.raw_issuer, cert(ROOT_PEM).raw_subject);
+        assert!(crl.revoked.contains("a1b"));
+        assert!(crl.next_update.unwrap() > crl.this_update);
+        assert!(X509Crl::from_der(b"garbage").is_err());
+    }
+
+    #[test]
+    fn crls_are_verified_with_their_issuer() {
+        assert_eq!(crl().verify(&cert(ROOT_PEM)), Ok(()));
+        assert!(crl().verify(&cert(P384_PEM)).is_err());
+    }
+
+    #[test]
+    fn crls_with_other_contents_are_rejected() {
+        let mut crl = crl();
+        let last = crl.tbs.len() - 1;
+        crl.tbs[last] ^= 1;
+        assert!(crl.verify(&cert(ROOT_PEM)).is_err());
+    }
+
+    #[test]
+    fn verifies_p256_signatures() {
+        let cert = cert(ROOT_PEM);
+        assert_eq!(cert.sig_algorithm, OID_ECDSA_WITH_SHA256);
+        assert_eq!(cert.verify_signature(&cert), Ok(()));
+    }
+
+    #[test]
+    fn verifies_p384_signatures() {
+        let cert = cert(P384_PEM);
+        assert_eq!(cert.sig_algorithm, OID_ECDSA_WITH_SHA384);
+        assert_eq!(cert.verify_signature(&cert), Ok(()));
+    }
+
+    #[test]
+    fn verifies_rsa_signatures_with_the_cert_hash() {
+        let cert = cert(RSA_PEM);
+        assert_eq!(cert.sig_algorithm, OID_SHA512_WITH_RSA);
+        assert_eq!(cert.verify_signature(&cert), Ok(()));
+        assert!(cert.verify_signed_data(OID_SHA256_WITH_RSA, &cert.tbs, &cert.signature).is_err());
+    }
+
+    #[test]
+    fn rejects_signatures_over_other_data() {
+        for pem in [ROOT_PEM, P384_PEM, RSA_PEM] {
+            let cert = cert(pem);
+            let mut tbs = cert.tbs.clone();
+            let last = tbs.len() - 1;
+            tbs[last] ^= 1;
+            assert!(cert.verify_signed_data(&cert.sig_algorithm, &tbs, &cert.signature).is_err());
+        }
+    }
+
+    #[test]
+    fn hash_must_match_the_signature_algorithm() {
+        let cert = cert(ROOT_PEM);
+        assert!(cert.verify_signed_data(OID_ECDSA_WITH_SHA384, &cert.tbs, &cert.signature).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_algorithms() {
+        let cert = cert(ROOT_PEM);
+        assert_eq!(
+            cert.verify_signed_data(OID_SHA256_WITH_RSA, &cert.tbs, &cert.signature), 
+            Err(X509Error::UnsupportedAlgorithm(OID_SHA256_WITH_RSA.to_string()))
+        );
+    }
+
+    #[test]
+    fn keys_of_other_curves_are_rejected() {
+        let cert = cert(P384_PEM);
+        let key = match &cert.pub_key.value {
+            PubKeyValue::EC(key) => key.data.clone(),
+            _ => panic!("EC key expected"),
+        };
+        assert!(ecdsa::verify(ecdsa::OID_CURVE_P256, &key, &digest::SHA384, &cert.tbs, &cert.signature).is_err());
+        assert!(ecdsa::verify("1.3.132.0.35", &key, &digest::SHA384, &cert.tbs, &cert.signature).is_err());
+        assert_eq!(ecdsa::verify(ecdsa::OID_CURVE_P384, &key, &digest::SHA384, &cert.tbs, &cert.signature), Ok(()));
+    }
+
+    #[test]
+    fn malformed_pem_is_an_error() {
+        let pem = b"-----BEGIN CERTIFICATE-----\n!!!!\n-----END CERTIFICATE-----\n".to_vec();
+        assert!(matches!(X509CertChain::new(&pem, &vec![]), Err(X509Error::PemDecoding)));
+        assert!(matches!(X509CertChain::new(&b"no certs".to_vec(), &vec![]), Err(X509Error::EmptyChain)));
+    }
+
+    #[test]
+    fn malformed_der_is_an_error() {
+        let der = pem_to_der(ROOT_PEM).unwrap().remove(0);
+        assert!(matches!(X509CertChain::from_der(&der[..der.len() - 1], &vec![]), Err(X509Error::DerDecoding)));
+        assert!(matches!(X509CertChain::from_ders(&vec![b"garbage".to_vec()], &vec![]), Err(X509Error::DerDecoding)));
+        assert_eq!(X509CertChain::from_der(&der, &vec![]).unwrap().chain.len(), 1);
+    }
+
+    #[test]
+    fn chains_are_limited() {
+        let der = pem_to_der(ROOT_PEM).unwrap().remove(0);
+        assert!(matches!(
+            X509CertChain::from_ders(&vec![der; MAX_CHAIN_LEN + 1], &vec![]), 
+            Err(X509Error::ChainTooLong(_))
+        ));
+        assert!(matches!(
+            X509CertChain::new(&vec![b' '; MAX_CHAIN_SIZE + 1], &vec![]), 
+            Err(X509Error::ChainTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn is_same_as_compares_the_contents() {
+        let cert = cert(ROOT_PEM);
+        assert!(cert.is_same_as(&cert.clone()));
+
+        let mut other = cert.clone();
+        other.raw_subject = b"other".to_vec();
+        assert!(!cert.is_same_as(&other));
+    }
+
+    #[test]
+    fn verify_path_doesnt_take_a_cloned_cert_as_its_own_issuer() {
+        let chain = X509CertChain::new(&ROOT_PEM.to_vec(), &vec![]).unwrap();
+        let leaf = chain.chain[0].clone();
+        assert_eq!(chain.verify_path(&leaf, &vec![]), Err(X509Error::UntrustedChain));
     }
 }
\ No newline at end of file